@@ -0,0 +1,25 @@
+use std::process::Command;
+
+// /versionで返すコミットを埋め込む
+// .gitがないビルド環境(Dockerなど)ではGIT_SHAを環境変数で渡す
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    let sha = std::env::var("GIT_SHA").ok().or_else(|| {
+        Command::new("git")
+            .arg("rev-parse")
+            .arg("HEAD")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_string())
+    });
+
+    println!(
+        "cargo:rustc-env=GIT_SHA={}",
+        sha.unwrap_or_else(|| "unknown".to_string())
+    );
+}
@@ -1,7 +1,7 @@
 use crate::base64::Base64;
 use crate::domain::model::{
-    GachaEvent, GachaType, Gift, GiftId, GiftStatus, JankenEvent, JankenStatus,
-    PointDiffRankingRecord, PointEvent, User, UserId,
+    GachaEvent, GachaEventId, GachaState, GachaType, Gift, GiftId, GiftStatus, JankenEvent,
+    JankenStatus, PointDiffRankingRecord, PointEvent, PointTransaction, User, UserId,
 };
 use crate::unixtime::UnixTime;
 use crate::url::Url;
@@ -11,17 +11,13 @@ use async_trait::async_trait;
 #[async_trait]
 pub trait IUserRepository {
     async fn list_id(&self) -> Result<Vec<UserId>, ServiceError>;
-    async fn find_oldest_user(&self) -> Result<UserId, ServiceError>;
     async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError>;
     async fn find_by_screen_name(&self, screen_name: &String) -> Result<User, ServiceError>;
     async fn find_by_subject(&self, subject: &str) -> Result<User, ServiceError>;
+    // トランザクション中に使い, コミットするまで同じユーザーを読む他のトランザクションを待たせる
+    async fn find_by_subject_for_update(&self, subject: &str) -> Result<User, ServiceError>;
     async fn create(&self, user: User) -> Result<(), ServiceError>;
     async fn save(&self, user: User) -> Result<(), ServiceError>;
-    async fn conditional_save_point(
-        &self,
-        user: User,
-        daily_gacha_timestamp: UnixTime,
-    ) -> Result<(), ServiceError>;
 }
 
 #[async_trait]
@@ -31,7 +27,33 @@ pub trait IGachaEventRepository {
         user_id: &UserId,
         gacha_type: &GachaType,
     ) -> Result<GachaEvent, ServiceError>;
+    // 同じidのイベントは上書きするので, 失敗したときはそのままやり直してよい
+    async fn save(&self, event: GachaEvent) -> Result<(), ServiceError>;
+}
+
+#[async_trait]
+pub trait IGachaStateRepository {
+    async fn find_by_user_type(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<GachaState, ServiceError>;
+    // 保存済みの状態がprevから変わっていない(draw_countが同じ)ときに限り保存する
+    // prevがNoneのときはまだ保存されていないときに限る
+    // 条件を満たさなければConditionNotMetのconflictを返す
+    async fn conditional_save(
+        &self,
+        state: GachaState,
+        prev: Option<GachaState>,
+    ) -> Result<(), ServiceError>;
+}
+
+// DynamoDBに書き込むまでのガチャの記録をMySQLに置いておく
+#[async_trait]
+pub trait IPendingGachaEventRepository {
+    async fn find_by_user_id(&self, user_id: &UserId) -> Result<Vec<GachaEvent>, ServiceError>;
     async fn create(&self, event: GachaEvent) -> Result<(), ServiceError>;
+    async fn delete(&self, event_id: &GachaEventId) -> Result<(), ServiceError>;
 }
 
 #[async_trait]
@@ -101,3 +123,71 @@ pub trait IRankingRepository {
         limit: u64,
     ) -> Result<Vec<PointDiffRankingRecord>, ServiceError>;
 }
+
+#[async_trait]
+pub trait IPointTransactionRepository {
+    async fn find_by_user_id(
+        &self,
+        user_id: &UserId,
+        limit: i32,
+    ) -> Result<Vec<PointTransaction>, ServiceError>;
+    // 残高の更新と取引の記録を1つのトランザクションで行い, balance_afterを埋めて返す
+    async fn apply(&self, transaction: PointTransaction) -> Result<PointTransaction, ServiceError>;
+    // applyに加えて, デイリーガチャのタイムスタンプがdaily_gacha_timestampのときに限り
+    // user.last_tried_daily_gachaへ更新する
+    async fn conditional_apply(
+        &self,
+        user: User,
+        transaction: PointTransaction,
+        daily_gacha_timestamp: UnixTime,
+    ) -> Result<PointTransaction, ServiceError>;
+}
+
+// 1つのトランザクションの中で複数のリポジトリを操作するためのもの
+// commitもrollbackもされずに捨てられたトランザクションはロールバックされる
+#[async_trait]
+pub trait IUnitOfWork {
+    async fn begin(&self) -> Result<Box<dyn ITransaction + Sync + Send>, ServiceError>;
+}
+
+#[async_trait]
+pub trait ITransaction {
+    fn user_repository(&self) -> &(dyn IUserRepository + Sync + Send);
+    fn gift_repository(&self) -> &(dyn IGiftRepository + Sync + Send);
+    fn janken_repository(&self) -> &(dyn IJankenEventRepository + Sync + Send);
+    fn gacha_state_repository(&self) -> &(dyn IGachaStateRepository + Sync + Send);
+    fn pending_gacha_event_repository(&self) -> &(dyn IPendingGachaEventRepository + Sync + Send);
+    fn point_transaction_repository(&self) -> &(dyn IPointTransactionRepository + Sync + Send);
+    async fn commit(self: Box<Self>) -> Result<(), ServiceError>;
+    async fn rollback(self: Box<Self>) -> Result<(), ServiceError>;
+}
+
+impl dyn ITransaction + Sync + Send {
+    // 処理の結果に応じてコミットかロールバックを行う
+    pub async fn finish<T>(
+        self: Box<Self>,
+        result: Result<T, ServiceError>,
+    ) -> Result<T, ServiceError> {
+        match result {
+            Ok(value) => {
+                self.commit().await?;
+
+                Ok(value)
+            }
+            Err(err) => {
+                if let Err(rollback_err) = self.rollback().await {
+                    error!("Failed to rollback: {:?}", rollback_err);
+                }
+
+                Err(err)
+            }
+        }
+    }
+}
+
+// /readyzで確認する外部依存
+#[async_trait]
+pub trait IHealthCheck {
+    fn name(&self) -> &str;
+    async fn check(&self) -> Result<(), ServiceError>;
+}
@@ -10,6 +10,12 @@ pub use user::*;
 mod gacha_event;
 pub use gacha_event::*;
 
+mod gacha_catalog;
+pub use gacha_catalog::*;
+
+mod gacha_state;
+pub use gacha_state::*;
+
 mod gift;
 pub use gift::*;
 
@@ -21,3 +27,6 @@ pub use point_event::*;
 
 mod ranking;
 pub use ranking::*;
+
+mod point_transaction;
+pub use point_transaction::*;
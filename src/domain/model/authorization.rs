@@ -1,4 +1,8 @@
-use crate::wrapper::error::ServiceError;
+use crate::server::string_enum_schema;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::*;
 
 #[derive(Clone, PartialOrd, PartialEq)]
@@ -35,6 +39,21 @@ impl Serialize for Role {
     }
 }
 
+impl JsonSchema for Role {
+    fn schema_name() -> String {
+        "Role".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_enum_schema(
+            [Role::Unknown, Role::Admin]
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+        )
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct AuthUser {
     pub subject: String,
@@ -44,9 +63,10 @@ pub struct AuthUser {
 impl AuthUser {
     pub fn require_admin(&self) -> Result<(), ServiceError> {
         if !self.roles.contains(&Role::Admin) {
-            return Err(ServiceError::unauthorized(failure::err_msg(
-                "access_denied",
-            )));
+            return Err(ServiceError::forbidden(
+                ErrorCode::AdminRequired,
+                failure::err_msg("access_denied"),
+            ));
         }
 
         Ok(())
@@ -65,4 +85,9 @@ impl Authorization {
     pub fn require_auth(self) -> Result<AuthUser, ServiceError> {
         self.result
     }
+
+    // 認証に成功していればそのsubject
+    pub fn subject(&self) -> Option<&str> {
+        self.result.as_ref().ok().map(|user| user.subject.as_str())
+    }
 }
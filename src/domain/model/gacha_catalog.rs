@@ -0,0 +1,572 @@
+use crate::domain::model::GachaType;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use schemars::JsonSchema;
+use serde::*;
+
+// 排出物のレア度, 後ろのものほどレア
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Rarity {
+    Common,
+    Rare,
+    SuperRare,
+    UltraRare,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GachaReward {
+    // min以上max以下のみょんポイント
+    Point { min: u64, max: u64 },
+    // 名前で区別するアイテム
+    Item { name: String },
+}
+
+// ドロップテーブルの1行, weightの比で排出される
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GachaDrop {
+    pub weight: u32,
+    pub rarity: Rarity,
+    pub reward: GachaReward,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GachaCooldown {
+    // 日付が変わるまで引けない
+    Daily,
+    // 前回から指定の秒数が経つまで引けない
+    Seconds(i64),
+    // いつでも引ける
+    None,
+}
+
+// まとめて引くときの設定
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GachaMultiDraw {
+    pub count: u32,
+    // count回の中で最低1回はこのレア度以上が出る
+    pub guaranteed_rarity: Rarity,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GachaDefinition {
+    pub gacha_type: GachaType,
+    // 1回引くのに必要なみょんポイント
+    pub cost: u64,
+    pub cooldown: GachaCooldown,
+    pub drops: Vec<GachaDrop>,
+    // ないときはまとめて引けない
+    #[serde(default)]
+    pub multi_draw: Option<GachaMultiDraw>,
+    // この回数続けて最高レア度を外すと, 次の1回は最高レア度が確定する
+    #[serde(default)]
+    pub pity_threshold: Option<u32>,
+}
+
+// 1回分の結果
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GachaResult {
+    pub rarity: Rarity,
+    pub point: u64,
+    pub item: Option<String>,
+}
+
+impl GachaDefinition {
+    // 排出されうる中で最もレアなもの
+    pub fn top_rarity(&self) -> Rarity {
+        self.drops
+            .iter()
+            .filter(|d| d.weight > 0)
+            .map(|d| d.rarity.clone())
+            .fold(
+                Rarity::Common,
+                |top, rarity| {
+                    if rarity > top {
+                        rarity
+                    } else {
+                        top
+                    }
+                },
+            )
+    }
+
+    // pityは直前まで続けて最高レア度を外した回数
+    pub fn next_pity(&self, pity: u32, result: &GachaResult) -> u32 {
+        if result.rarity >= self.top_rarity() {
+            0
+        } else {
+            pity + 1
+        }
+    }
+
+    // randomは[x, y)の一様乱数, テストでは決まった値を返すものを渡す
+    pub fn draw(&self, pity: u32, random: impl FnMut(u64, u64) -> u64) -> GachaResult {
+        match self.pity_threshold {
+            Some(threshold) if pity >= threshold => {
+                let top = self.top_rarity();
+                GachaDefinition::draw_from(
+                    self.drops
+                        .iter()
+                        .filter(|d| d.weight > 0 && d.rarity >= top)
+                        .collect(),
+                    random,
+                )
+            }
+            _ => GachaDefinition::draw_from(self.drops.iter().collect(), random),
+        }
+    }
+
+    // multi_drawの回数だけ引き, 保証されたレア度が1回も出なければ最後の1回をその中から引き直す
+    pub fn draw_multi(
+        &self,
+        mut pity: u32,
+        mut random: impl FnMut(u64, u64) -> u64,
+    ) -> Vec<GachaResult> {
+        let multi = match &self.multi_draw {
+            Some(multi) => multi,
+            None => return Vec::new(),
+        };
+
+        let mut results = (0..multi.count)
+            .map(|_| {
+                let result = self.draw(pity, &mut random);
+                pity = self.next_pity(pity, &result);
+
+                result
+            })
+            .collect::<Vec<_>>();
+        if results
+            .iter()
+            .all(|result| result.rarity < multi.guaranteed_rarity)
+        {
+            results.pop();
+            results.push(GachaDefinition::draw_from(
+                self.drops
+                    .iter()
+                    .filter(|d| d.rarity >= multi.guaranteed_rarity)
+                    .collect(),
+                &mut random,
+            ));
+        }
+
+        results
+    }
+
+    fn draw_from(drops: Vec<&GachaDrop>, mut random: impl FnMut(u64, u64) -> u64) -> GachaResult {
+        let total = drops.iter().map(|d| d.weight as u64).sum::<u64>();
+        let mut n = random(0, total);
+        let drop = drops
+            .iter()
+            .find(|d| {
+                if n < d.weight as u64 {
+                    true
+                } else {
+                    n -= d.weight as u64;
+                    false
+                }
+            })
+            .unwrap_or_else(|| &drops[drops.len() - 1]);
+
+        match &drop.reward {
+            GachaReward::Point { min, max } => GachaResult {
+                rarity: drop.rarity.clone(),
+                point: random(*min, *max + 1),
+                item: None,
+            },
+            GachaReward::Item { name } => GachaResult {
+                rarity: drop.rarity.clone(),
+                point: 0,
+                item: Some(name.clone()),
+            },
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.gacha_type == GachaType::Unknown {
+            return Err("gacha_type is empty".to_string());
+        }
+        if self.drops.iter().map(|d| d.weight as u64).sum::<u64>() == 0 {
+            return Err(format!(
+                "{}: drops must have a positive weight",
+                self.gacha_type.to_string()
+            ));
+        }
+        if self.drops.iter().any(|d| match d.reward {
+            GachaReward::Point { min, max } => min > max,
+            _ => false,
+        }) {
+            return Err(format!(
+                "{}: point reward must satisfy min <= max",
+                self.gacha_type.to_string()
+            ));
+        }
+        // dailyはユーザーに持たせたタイムスタンプで引けるかを判定する
+        if self.gacha_type == GachaType::Daily
+            && (self.cooldown != GachaCooldown::Daily || self.cost != 0)
+        {
+            return Err("daily: cooldown must be daily and cost must be 0".to_string());
+        }
+        if self.pity_threshold == Some(0) {
+            return Err(format!(
+                "{}: pity_threshold must be positive",
+                self.gacha_type.to_string()
+            ));
+        }
+        if let Some(multi) = &self.multi_draw {
+            if self.gacha_type == GachaType::Daily {
+                return Err("daily: multi_draw is not supported".to_string());
+            }
+            if multi.count == 0 {
+                return Err(format!(
+                    "{}: multi_draw.count must be positive",
+                    self.gacha_type.to_string()
+                ));
+            }
+            if !self
+                .drops
+                .iter()
+                .any(|d| d.weight > 0 && d.rarity >= multi.guaranteed_rarity)
+            {
+                return Err(format!(
+                    "{}: no drop satisfies the guaranteed rarity",
+                    self.gacha_type.to_string()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_multiplier() -> u64 {
+    1
+}
+
+// デイリーガチャをdays日続けて引いた日のボーナス
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StreakBonus {
+    pub days: u32,
+    // その日に引いたみょんポイントを何倍にするか
+    #[serde(default = "default_multiplier")]
+    pub multiplier: u64,
+    // ギフトとして贈るみょんポイント
+    #[serde(default)]
+    pub gift_point: Option<u64>,
+}
+
+// 引けるガチャの一覧
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GachaCatalog {
+    pub gachas: Vec<GachaDefinition>,
+    #[serde(default)]
+    pub streak_bonuses: Vec<StreakBonus>,
+}
+
+impl Default for GachaCatalog {
+    // 設定ファイルがないときはデイリーガチャとプレミアムガチャ, 連続ボーナスは7, 30, 100日
+    fn default() -> Self {
+        let drop = |weight, rarity, min, max| GachaDrop {
+            weight,
+            rarity,
+            reward: GachaReward::Point { min, max },
+        };
+
+        GachaCatalog {
+            gachas: vec![
+                GachaDefinition {
+                    gacha_type: GachaType::Daily,
+                    cost: 0,
+                    cooldown: GachaCooldown::Daily,
+                    drops: vec![drop(1, Rarity::Common, 5, 15)],
+                    multi_draw: None,
+                    pity_threshold: None,
+                },
+                // みょんポイントの使い道, 期待値はコストより少し低くしてある
+                GachaDefinition {
+                    gacha_type: GachaType::new("premium"),
+                    cost: 20,
+                    cooldown: GachaCooldown::None,
+                    drops: vec![
+                        drop(70, Rarity::Common, 5, 10),
+                        drop(25, Rarity::Rare, 15, 30),
+                        drop(4, Rarity::SuperRare, 50, 100),
+                        drop(1, Rarity::UltraRare, 300, 300),
+                    ],
+                    multi_draw: Some(GachaMultiDraw {
+                        count: 10,
+                        guaranteed_rarity: Rarity::Rare,
+                    }),
+                    pity_threshold: Some(100),
+                },
+            ],
+            streak_bonuses: vec![
+                StreakBonus {
+                    days: 7,
+                    multiplier: 2,
+                    gift_point: None,
+                },
+                StreakBonus {
+                    days: 30,
+                    multiplier: 2,
+                    gift_point: Some(100),
+                },
+                StreakBonus {
+                    days: 100,
+                    multiplier: 3,
+                    gift_point: Some(500),
+                },
+            ],
+        }
+    }
+}
+
+impl GachaCatalog {
+    pub fn from_json(json: &str) -> Result<Self, ServiceError> {
+        let catalog: GachaCatalog = serde_json::from_str(json).map_err(|err| {
+            ServiceError::internal_server_error(
+                ErrorCode::InvalidConfiguration,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        })?;
+        catalog.validate().map_err(|err| {
+            ServiceError::internal_server_error(
+                ErrorCode::InvalidConfiguration,
+                failure::err_msg(err),
+            )
+        })?;
+
+        Ok(catalog)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (i, gacha) in self.gachas.iter().enumerate() {
+            gacha.validate()?;
+            if self.gachas[..i]
+                .iter()
+                .any(|g| g.gacha_type == gacha.gacha_type)
+            {
+                return Err(format!(
+                    "{}: gacha_type is duplicated",
+                    gacha.gacha_type.to_string()
+                ));
+            }
+        }
+        if self.find(&GachaType::Daily).is_err() {
+            return Err("daily is not defined".to_string());
+        }
+        for (i, bonus) in self.streak_bonuses.iter().enumerate() {
+            if bonus.days == 0 || bonus.multiplier == 0 {
+                return Err("streak_bonuses: days and multiplier must be positive".to_string());
+            }
+            if self.streak_bonuses[..i]
+                .iter()
+                .any(|b| b.days == bonus.days)
+            {
+                return Err(format!("streak_bonuses: {} days is duplicated", bonus.days));
+            }
+        }
+
+        Ok(())
+    }
+
+    // ちょうどdays日目のときだけボーナスが付く
+    pub fn streak_bonus(&self, days: u32) -> Option<&StreakBonus> {
+        self.streak_bonuses.iter().find(|b| b.days == days)
+    }
+
+    pub fn find(&self, gacha_type: &GachaType) -> Result<&GachaDefinition, ServiceError> {
+        self.gachas
+            .iter()
+            .find(|g| &g.gacha_type == gacha_type)
+            .ok_or_else(|| {
+                ServiceError::not_found(
+                    ErrorCode::UnknownGachaType,
+                    failure::err_msg(format!("Unknown gacha type: {}", gacha_type.to_string())),
+                )
+            })
+    }
+}
+
+#[test]
+fn draw_should_follow_weights() {
+    let gacha = GachaDefinition {
+        gacha_type: GachaType::new("test"),
+        cost: 0,
+        cooldown: GachaCooldown::None,
+        drops: vec![
+            GachaDrop {
+                weight: 3,
+                rarity: Rarity::Common,
+                reward: GachaReward::Point { min: 1, max: 3 },
+            },
+            GachaDrop {
+                weight: 1,
+                rarity: Rarity::UltraRare,
+                reward: GachaReward::Item {
+                    name: "crown".to_string(),
+                },
+            },
+        ],
+        multi_draw: None,
+        pity_threshold: Some(2),
+    };
+
+    // [0, 3)は1行目, 3は2行目
+    let result = gacha.draw(0, |x, y| if x == 0 { 2 } else { y - 1 });
+    assert_eq!(
+        result,
+        GachaResult {
+            rarity: Rarity::Common,
+            point: 3,
+            item: None,
+        }
+    );
+
+    let result = gacha.draw(0, |x, _| if x == 0 { 3 } else { x });
+    assert_eq!(
+        result,
+        GachaResult {
+            rarity: Rarity::UltraRare,
+            point: 0,
+            item: Some("crown".to_string()),
+        }
+    );
+}
+
+#[test]
+fn pity_should_force_top_rarity() {
+    let mut gacha = GachaCatalog::default()
+        .find(&GachaType::new("premium"))
+        .unwrap()
+        .clone();
+    gacha.pity_threshold = Some(3);
+    assert_eq!(gacha.top_rarity(), Rarity::UltraRare);
+
+    // 常に先頭の行(common)が出る乱数でも, 3回外した次は最高レア度になる
+    let mut pity = 0;
+    let results = (0..5)
+        .map(|_| {
+            let result = gacha.draw(pity, |x, _| x);
+            pity = gacha.next_pity(pity, &result);
+
+            (result.rarity, pity)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            (Rarity::Common, 1),
+            (Rarity::Common, 2),
+            (Rarity::Common, 3),
+            (Rarity::UltraRare, 0),
+            (Rarity::Common, 1),
+        ]
+    );
+
+    // まとめて引くときも途中で天井に達する
+    let results = gacha.draw_multi(1, |x, _| x);
+    assert_eq!(results[1].rarity, Rarity::Common);
+    assert_eq!(results[2].rarity, Rarity::UltraRare);
+    assert_eq!(results[2].point, 300);
+    assert_eq!(results[9].rarity, Rarity::Common);
+}
+
+#[test]
+fn draw_multi_should_guarantee_rarity() {
+    let gacha = GachaCatalog::default()
+        .find(&GachaType::new("premium"))
+        .unwrap()
+        .clone();
+
+    // 常に先頭の行(common)が出るときは最後の1回がrare以上になる
+    let results = gacha.draw_multi(0, |x, _| x);
+    assert_eq!(results.len(), 10);
+    assert!(results[..9].iter().all(|r| r.rarity == Rarity::Common));
+    assert_eq!(results[9].rarity, Rarity::Rare);
+    assert_eq!(results[9].point, 15);
+
+    // 既にrare以上が出ていれば引き直さない
+    let mut n = 0;
+    let results = gacha.draw_multi(0, |x, y| {
+        n += 1;
+        if n == 1 {
+            y - 1
+        } else {
+            x
+        }
+    });
+    assert_eq!(results[0].rarity, Rarity::UltraRare);
+    assert!(results[1..].iter().all(|r| r.rarity == Rarity::Common));
+}
+
+#[test]
+fn catalog_should_be_validated() {
+    let catalog = GachaCatalog::from_json(
+        r#"{
+            "gachas": [
+                {
+                    "gacha_type": "daily",
+                    "cost": 0,
+                    "cooldown": "daily",
+                    "drops": [
+                        { "weight": 1, "rarity": "common", "reward": { "point": { "min": 5, "max": 15 } } }
+                    ]
+                },
+                {
+                    "gacha_type": "hourly",
+                    "cost": 1,
+                    "cooldown": { "seconds": 3600 },
+                    "drops": [
+                        { "weight": 9, "rarity": "common", "reward": { "point": { "min": 1, "max": 2 } } },
+                        { "weight": 1, "rarity": "rare", "reward": { "item": { "name": "badge" } } }
+                    ]
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(catalog.gachas.len(), 2);
+    assert_eq!(
+        catalog.find(&GachaType::new("hourly")).unwrap().cooldown,
+        GachaCooldown::Seconds(3600)
+    );
+    assert_eq!(
+        catalog
+            .find(&GachaType::new("unknown_type"))
+            .unwrap_err()
+            .code,
+        ErrorCode::UnknownGachaType
+    );
+
+    // 省略したときは連続ボーナスなし
+    assert!(catalog.streak_bonuses.is_empty());
+    assert_eq!(
+        GachaCatalog::default().streak_bonus(30).unwrap().gift_point,
+        Some(100)
+    );
+    assert_eq!(GachaCatalog::default().streak_bonus(31), None);
+
+    // dailyがない
+    assert!(GachaCatalog::from_json(r#"{ "gachas": [] }"#).is_err());
+
+    // 同じ日数のボーナスが重複している
+    let mut catalog = GachaCatalog::default();
+    catalog.streak_bonuses[1].days = 7;
+    assert!(GachaCatalog::from_json(&serde_json::to_string(&catalog).unwrap()).is_err());
+
+    // 保証するレア度が出ない
+    let mut catalog = GachaCatalog::default();
+    catalog.gachas[1]
+        .multi_draw
+        .as_mut()
+        .unwrap()
+        .guaranteed_rarity = Rarity::UltraRare;
+    catalog.gachas[1].drops.pop();
+    assert!(GachaCatalog::from_json(&serde_json::to_string(&catalog).unwrap()).is_err());
+
+    // 重みが全て0
+    let mut catalog = GachaCatalog::default();
+    catalog.gachas[0].drops[0].weight = 0;
+    assert!(GachaCatalog::from_json(&serde_json::to_string(&catalog).unwrap()).is_err());
+}
@@ -1,11 +1,16 @@
-use crate::domain::model::{GachaEventId, UserId};
+use crate::domain::model::{GachaEventId, GachaResult, UserId};
 use crate::wrapper::unixtime::UnixTime;
-use serde::{Serialize, Serializer};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+// ガチャの種類, daily以外はガチャのカタログで定義された名前
 #[derive(Debug, Clone, PartialEq)]
 pub enum GachaType {
     Unknown,
     Daily,
+    Named(String),
 }
 
 impl GachaType {
@@ -15,6 +20,7 @@ impl GachaType {
         match self {
             Unknown => "unknown",
             Daily => "daily",
+            Named(name) => name.as_str(),
         }
         .to_string()
     }
@@ -22,7 +28,8 @@ impl GachaType {
     pub fn new(rep: &str) -> Self {
         match rep {
             "daily" => GachaType::Daily,
-            _ => GachaType::Unknown,
+            "" | "unknown" => GachaType::Unknown,
+            _ => GachaType::Named(rep.to_string()),
         }
     }
 }
@@ -36,16 +43,39 @@ impl Serialize for GachaType {
     }
 }
 
+impl<'de> Deserialize<'de> for GachaType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(|s| GachaType::new(&s))
+    }
+}
+
+impl JsonSchema for GachaType {
+    fn schema_name() -> String {
+        "GachaType".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        gen.subschema_for::<String>()
+    }
+}
+
 impl Default for GachaType {
     fn default() -> Self {
         GachaType::Unknown
     }
 }
 
-#[derive(Debug, Clone, Serialize, Default, PartialEq)]
+#[derive(Debug, Clone, Serialize, Default, PartialEq, JsonSchema)]
 pub struct GachaEvent {
     pub id: GachaEventId,
     pub user_id: UserId,
     pub gacha_type: GachaType,
     pub created_at: UnixTime,
+    // 排出結果, 結果を記録する前のイベントにはない
+    pub result: Option<GachaResult>,
+    // このガチャで続けて最高レア度を外している回数
+    pub pity_count: u32,
 }
@@ -0,0 +1,37 @@
+use crate::domain::model::{GachaType, UserId};
+use crate::wrapper::unixtime::UnixTime;
+
+// ユーザーとガチャの種類ごとに1つだけ持つ状態
+#[derive(Clone, Debug, PartialEq)]
+pub struct GachaState {
+    pub user_id: UserId,
+    pub gacha_type: GachaType,
+    // 最後に引いた時刻, クールダウンの判定に使う
+    pub last_drawn_at: UnixTime,
+    // これまでに引いた回数, 引くたびに必ず増えるので同時に引いたときの競合の検出に使う
+    pub draw_count: u64,
+    // 続けて最高レア度を外している回数
+    pub pity_count: u32,
+}
+
+impl GachaState {
+    pub fn new(user_id: UserId, gacha_type: GachaType) -> Self {
+        GachaState {
+            user_id,
+            gacha_type,
+            last_drawn_at: UnixTime(0),
+            draw_count: 0,
+            pity_count: 0,
+        }
+    }
+
+    // count回引いた後の状態
+    pub fn drawn(&self, count: u64, pity_count: u32, now: UnixTime) -> Self {
+        GachaState {
+            last_drawn_at: now,
+            draw_count: self.draw_count + count,
+            pity_count,
+            ..self.clone()
+        }
+    }
+}
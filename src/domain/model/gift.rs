@@ -1,9 +1,13 @@
 use crate::domain::model::{GiftId, JankenEventId};
-use crate::wrapper::error::ServiceError;
+use crate::server::string_enum_schema;
+use crate::wrapper::error::{ErrorCode, ServiceError};
 use crate::wrapper::unixtime::UnixTime;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::*;
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialOrd, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialOrd, PartialEq, JsonSchema)]
 pub enum GiftType {
     #[serde(rename = "point")]
     Point(u64),
@@ -46,7 +50,22 @@ impl Serialize for GiftStatus {
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+impl JsonSchema for GiftStatus {
+    fn schema_name() -> String {
+        "GiftStatus".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_enum_schema(
+            [GiftStatus::Unknown, GiftStatus::Ready, GiftStatus::Opened]
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub struct Gift {
     pub id: GiftId,
     pub gift_type: GiftType,
@@ -72,9 +91,10 @@ impl Gift {
 
     pub fn open(&mut self) -> Result<(), ServiceError> {
         if self.status != GiftStatus::Ready {
-            return Err(ServiceError::bad_request(failure::err_msg(
-                "The gift cannot be opened",
-            )));
+            return Err(ServiceError::conflict(
+                ErrorCode::GiftCannotBeOpened,
+                failure::err_msg("The gift cannot be opened"),
+            ));
         }
 
         self.status = GiftStatus::Opened;
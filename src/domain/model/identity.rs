@@ -1,6 +1,7 @@
+use schemars::JsonSchema;
 use serde::*;
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq, JsonSchema)]
 pub struct UserId(pub String);
 
 impl UserId {
@@ -9,7 +10,7 @@ impl UserId {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq, JsonSchema)]
 pub struct GachaEventId(pub String);
 
 impl GachaEventId {
@@ -18,7 +19,7 @@ impl GachaEventId {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq, JsonSchema)]
 pub struct GiftId(pub String);
 
 impl GiftId {
@@ -27,7 +28,7 @@ impl GiftId {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq, JsonSchema)]
 pub struct JankenEventId(pub String);
 
 impl JankenEventId {
@@ -35,3 +36,12 @@ impl JankenEventId {
         JankenEventId(uuid::Uuid::new_v4().to_string())
     }
 }
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialOrd, PartialEq, JsonSchema)]
+pub struct PointTransactionId(pub String);
+
+impl PointTransactionId {
+    pub fn new() -> Self {
+        PointTransactionId(uuid::Uuid::new_v4().to_string())
+    }
+}
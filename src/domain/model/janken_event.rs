@@ -1,6 +1,10 @@
 use crate::domain::model::{JankenEventId, UserId};
+use crate::server::string_enum_schema;
 use crate::unixtime::UnixTime;
-use crate::wrapper::error::ServiceError;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub enum JankenResult {
@@ -33,10 +37,11 @@ impl JankenHand {
             "rock" => Ok(JankenHand::Rock),
             "paper" => Ok(JankenHand::Paper),
             "scissors" => Ok(JankenHand::Scissors),
-            _ => Err(ServiceError::bad_request(failure::err_msg(format!(
-                "Unsupported hand: {}",
-                rep
-            )))),
+            _ => Err(ServiceError::bad_request(
+                ErrorCode::UnsupportedJankenHand,
+                failure::err_msg(format!("Unsupported hand: {}", rep)),
+            )
+            .with_details(serde_json::json!({ "hand": rep }))),
         }
     }
 
@@ -64,6 +69,21 @@ impl Serialize for JankenHand {
     }
 }
 
+impl JsonSchema for JankenHand {
+    fn schema_name() -> String {
+        "JankenHand".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_enum_schema(
+            [JankenHand::Rock, JankenHand::Paper, JankenHand::Scissors]
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+        )
+    }
+}
+
 impl<'de> Deserialize<'de> for JankenHand {
     fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
     where
@@ -102,10 +122,11 @@ impl JankenStatus {
             "won" => Ok(JankenStatus::Won),
             "lost" => Ok(JankenStatus::Lost),
             "timeout" => Ok(JankenStatus::Timeout),
-            _ => Err(ServiceError::bad_request(failure::err_msg(format!(
-                "Unsupported status: {}",
-                rep
-            )))),
+            _ => Err(ServiceError::bad_request(
+                ErrorCode::UnsupportedJankenStatus,
+                failure::err_msg(format!("Unsupported status: {}", rep)),
+            )
+            .with_details(serde_json::json!({ "status": rep }))),
         }
     }
 }
@@ -119,6 +140,26 @@ impl Serialize for JankenStatus {
     }
 }
 
+impl JsonSchema for JankenStatus {
+    fn schema_name() -> String {
+        "JankenStatus".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_enum_schema(
+            [
+                JankenStatus::Ready,
+                JankenStatus::Won,
+                JankenStatus::Lost,
+                JankenStatus::Timeout,
+            ]
+            .iter()
+            .map(|v| v.to_string())
+            .collect(),
+        )
+    }
+}
+
 impl<'de> Deserialize<'de> for JankenStatus {
     fn deserialize<D>(deserializer: D) -> Result<Self, <D as Deserializer<'de>>::Error>
     where
@@ -130,7 +171,7 @@ impl<'de> Deserialize<'de> for JankenStatus {
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub struct JankenEvent {
     pub id: JankenEventId,
     pub user_id: UserId,
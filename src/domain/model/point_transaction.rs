@@ -0,0 +1,121 @@
+use crate::domain::model::{PointTransactionId, UserId};
+use crate::server::string_enum_schema;
+use crate::wrapper::unixtime::UnixTime;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Serialize, Serializer};
+
+// みょんポイントが変動した理由
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum PointTransactionReason {
+    #[default]
+    Unknown,
+    // ledger導入前から持っていた残高
+    OpeningBalance,
+    DailyGacha,
+    // デイリー以外のガチャの代金と排出されたポイント
+    GachaCost,
+    GachaReward,
+    GiftOpened,
+    JankenBet,
+}
+
+// DBやメトリクスのラベルに使う文字列
+impl std::fmt::Display for PointTransactionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use PointTransactionReason::*;
+
+        let rep = match self {
+            Unknown => "unknown",
+            OpeningBalance => "opening_balance",
+            DailyGacha => "daily_gacha",
+            GachaCost => "gacha_cost",
+            GachaReward => "gacha_reward",
+            GiftOpened => "gift_opened",
+            JankenBet => "janken_bet",
+        };
+        write!(f, "{}", rep)
+    }
+}
+
+impl PointTransactionReason {
+    pub fn from_str(rep: &str) -> Self {
+        use PointTransactionReason::*;
+
+        match rep {
+            "opening_balance" => OpeningBalance,
+            "daily_gacha" => DailyGacha,
+            "gacha_cost" => GachaCost,
+            "gacha_reward" => GachaReward,
+            "gift_opened" => GiftOpened,
+            "janken_bet" => JankenBet,
+            _ => Unknown,
+        }
+    }
+}
+
+impl Serialize for PointTransactionReason {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl JsonSchema for PointTransactionReason {
+    fn schema_name() -> String {
+        "PointTransactionReason".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_enum_schema(
+            [
+                PointTransactionReason::Unknown,
+                PointTransactionReason::OpeningBalance,
+                PointTransactionReason::DailyGacha,
+                PointTransactionReason::GachaCost,
+                PointTransactionReason::GachaReward,
+                PointTransactionReason::GiftOpened,
+                PointTransactionReason::JankenBet,
+            ]
+            .iter()
+            .map(|v| v.to_string())
+            .collect(),
+        )
+    }
+}
+
+// みょんポイントの増減の記録, 追記のみで更新はしない
+#[derive(Clone, Debug, Serialize, Default, PartialEq, JsonSchema)]
+pub struct PointTransaction {
+    pub id: PointTransactionId,
+    pub user_id: UserId,
+    pub reason: PointTransactionReason,
+    // 変動の原因になったイベント(ガチャ, ギフト, じゃんけん)のID
+    pub source_event_id: Option<String>,
+    pub delta: i64,
+    // 適用後の残高, リポジトリで適用されるまでは0
+    pub balance_after: u64,
+    pub created_at: UnixTime,
+}
+
+impl PointTransaction {
+    pub fn new(
+        user_id: UserId,
+        reason: PointTransactionReason,
+        source_event_id: Option<String>,
+        delta: i64,
+    ) -> Self {
+        PointTransaction {
+            id: PointTransactionId::new(),
+            user_id,
+            reason,
+            source_event_id,
+            delta,
+            balance_after: 0,
+            created_at: UnixTime::now(),
+        }
+    }
+}
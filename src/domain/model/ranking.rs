@@ -1,8 +1,9 @@
 use crate::domain::model::{User, UserId};
 use crate::url::Url;
+use schemars::JsonSchema;
 use serde::*;
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct UserInfo {
     user_id: UserId,
     screen_name: Option<String>,
@@ -23,7 +24,7 @@ impl UserInfo {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct PointDiffRankingRecord {
     #[serde(flatten)]
     pub user: UserInfo,
@@ -1,9 +1,60 @@
+use crate::daily_reset::DailyReset;
 use crate::domain::model::UserId;
 use crate::unixtime::UnixTime;
 use crate::url::Url;
+use chrono::NaiveDate;
+use schemars::JsonSchema;
 use serde::*;
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+const STREAK_DATE_FORMAT: &str = "%Y-%m-%d";
+
+// デイリーガチャを続けて引いた日数
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct DailyStreak {
+    pub current: u32,
+    pub longest: u32,
+    // 最後に引いた日付 (YYYY-MM-DD), 日付の区切りはDailyResetに従う
+    pub last_date: Option<String>,
+}
+
+impl DailyStreak {
+    fn last_naive_date(&self) -> Option<NaiveDate> {
+        self.last_date
+            .as_ref()
+            .and_then(|date| NaiveDate::parse_from_str(date, STREAK_DATE_FORMAT).ok())
+    }
+
+    // dateに引いたことを記録する, 前日に引いていれば続きになる
+    pub fn record(&mut self, date: NaiveDate) {
+        let last = self.last_naive_date();
+        if last == Some(date) {
+            return;
+        }
+
+        self.current = if last.is_some() && last == date.pred_opt() {
+            self.current + 1
+        } else {
+            1
+        };
+        self.longest = self.longest.max(self.current);
+        self.last_date = Some(date.format(STREAK_DATE_FORMAT).to_string());
+    }
+
+    // dateの時点での記録, 前日までに引いていなければcurrentは0になる
+    pub fn at(&self, date: NaiveDate) -> DailyStreak {
+        let last = self.last_naive_date();
+        if last.is_some() && (last == Some(date) || last == date.pred_opt()) {
+            self.clone()
+        } else {
+            DailyStreak {
+                current: 0,
+                ..self.clone()
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
 pub struct User {
     pub id: UserId,
     pub screen_name: Option<String>,
@@ -15,6 +66,9 @@ pub struct User {
     pub picture_url: Option<Url>,
     // 最後にデイリーガチャを引いた時刻
     pub last_tried_daily_gacha: UnixTime,
+    // 本人にだけ/gacha/daily/recordで見せる
+    #[serde(skip)]
+    pub daily_streak: DailyStreak,
 }
 
 impl User {
@@ -33,33 +87,33 @@ impl User {
             subject,
             picture_url,
             last_tried_daily_gacha: UnixTime(0),
+            daily_streak: DailyStreak::default(),
         }
     }
 
-    pub fn add_point(&mut self, p: u64) {
-        self.point += p;
-    }
-
-    pub fn subtract_point(&mut self, p: u64) {
-        self.point -= p;
-    }
-
     pub fn update(&mut self, screen_name: String, display_name: String, picture_url: Url) {
         self.screen_name = Some(screen_name);
         self.display_name = display_name;
         self.picture_url = Some(picture_url);
     }
 
-    pub fn update_daily_gacha_timestamp(&mut self) -> UnixTime {
+    pub fn update_daily_gacha_timestamp(&mut self, reset: &DailyReset) -> UnixTime {
         let prev = self.last_tried_daily_gacha.clone();
         self.last_tried_daily_gacha = UnixTime::now();
+        self.daily_streak
+            .record(reset.date_of(&self.last_tried_daily_gacha));
 
         prev
     }
 
-    pub fn is_daily_gacha_available_at(&self, target_time: UnixTime) -> bool {
+    pub fn is_daily_gacha_available_at(&self, target_time: UnixTime, reset: &DailyReset) -> bool {
         // デイリーガチャなので、日付が違ったら引いても良い
-        self.last_tried_daily_gacha.datetime_jst().date() != target_time.datetime_jst().date()
+        !reset.is_same_day(&self.last_tried_daily_gacha, &target_time)
+    }
+
+    // 次にデイリーガチャを引けるようになる時刻
+    pub fn next_daily_gacha_time(&self, reset: &DailyReset) -> UnixTime {
+        reset.next_reset(&self.last_tried_daily_gacha)
     }
 }
 
@@ -71,6 +125,50 @@ fn gacha_event_is_available_at() {
         ..Default::default()
     };
 
-    assert!(!ev.is_daily_gacha_available_at(UnixTime(1)));
-    assert!(ev.is_daily_gacha_available_at(UnixTime(89400)));
+    let reset = DailyReset::default();
+    assert!(!ev.is_daily_gacha_available_at(UnixTime(1), &reset));
+    assert!(ev.is_daily_gacha_available_at(UnixTime(89400), &reset));
+    assert_eq!(ev.next_daily_gacha_time(&reset), UnixTime(54000));
+
+    // 5時(JST)に日付が変わる場合は, 1970-01-01 05:00 JSTから引ける
+    let reset = DailyReset::new("Asia/Tokyo", 5).unwrap();
+    assert!(!ev.is_daily_gacha_available_at(UnixTime(71999), &reset));
+    assert!(ev.is_daily_gacha_available_at(UnixTime(72000), &reset));
+    assert_eq!(ev.next_daily_gacha_time(&reset), UnixTime(72000));
+}
+
+#[test]
+fn daily_streak_should_count_consecutive_days() {
+    let date = |d| NaiveDate::from_ymd_opt(2020, 12, d).unwrap();
+    let mut streak = DailyStreak::default();
+
+    streak.record(date(30));
+    streak.record(date(31));
+    // 同じ日に2回記録しても変わらない
+    streak.record(date(31));
+    assert_eq!(
+        streak,
+        DailyStreak {
+            current: 2,
+            longest: 2,
+            last_date: Some("2020-12-31".to_string()),
+        }
+    );
+    assert_eq!(streak.at(date(31)).current, 2);
+
+    // 年をまたいでも続く
+    let mut next_year = streak.clone();
+    next_year.record(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap());
+    assert_eq!(next_year.current, 3);
+
+    // 1日空くと途切れる
+    assert_eq!(
+        streak
+            .at(NaiveDate::from_ymd_opt(2021, 1, 2).unwrap())
+            .current,
+        0
+    );
+    streak.record(NaiveDate::from_ymd_opt(2021, 1, 2).unwrap());
+    assert_eq!(streak.current, 1);
+    assert_eq!(streak.longest, 2);
 }
@@ -27,3 +27,9 @@ pub use point_process_service::*;
 
 mod point_ranking_service;
 pub use point_ranking_service::*;
+
+mod point_transaction_service;
+pub use point_transaction_service::*;
+
+mod health_service;
+pub use health_service::*;
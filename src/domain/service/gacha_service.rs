@@ -1,151 +1,635 @@
-use crate::domain::interface::{IGachaEventRepository, IUserRepository};
-use crate::domain::model::{Authorization, GachaEvent, GachaEventId, GachaType};
-use crate::error::ServiceError;
+use crate::domain::interface::{
+    IGachaEventRepository, IGachaStateRepository, IPendingGachaEventRepository, IUnitOfWork,
+    IUserRepository,
+};
+use crate::domain::model::{
+    Authorization, DailyStreak, GachaCatalog, GachaCooldown, GachaDefinition, GachaEvent,
+    GachaEventId, GachaResult, GachaState, GachaType, Gift, GiftType, PointTransaction,
+    PointTransactionReason, StreakBonus, User, UserId,
+};
+use crate::error::{ErrorCode, ServiceError};
+use crate::metrics;
+use crate::wrapper::daily_reset::DailyReset;
 use crate::wrapper::rand_gen::RandomGen;
 use crate::wrapper::unixtime::UnixTime;
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
 // ガチャ
 pub struct GachaService {
     gacha_repo: Arc<dyn IGachaEventRepository + Sync + Send>,
+    gacha_state_repo: Arc<dyn IGachaStateRepository + Sync + Send>,
+    pending_gacha_repo: Arc<dyn IPendingGachaEventRepository + Sync + Send>,
     user_repo: Arc<dyn IUserRepository + Sync + Send>,
+    unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+    catalog: Arc<GachaCatalog>,
+    daily_reset: DailyReset,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DailyGachaResponse {
+    // 獲得したみょんポイント
+    obtained: u64,
+    streak: DailyStreak,
+    // 連続日数が節目に達したときのボーナス
+    streak_bonus: Option<StreakBonus>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GachaDrawResponse {
+    gacha_type: GachaType,
+    // 獲得したみょんポイント
+    obtained: u64,
+    result: GachaResult,
+    // 続けて最高レア度を外している回数
+    pity_count: u32,
+    // デイリーガチャのときだけ
+    streak: Option<DailyStreak>,
+    streak_bonus: Option<StreakBonus>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GachaMultiDrawResponse {
+    gacha_type: GachaType,
+    // 支払ったみょんポイント
+    spent: u64,
+    // 獲得したみょんポイントの合計
+    obtained: u64,
+    results: Vec<GachaResult>,
+    pity_count: u32,
+}
+
+#[derive(Serialize, JsonSchema)]
 pub struct DailyGachaRecord {
     latest: Option<GachaEvent>,
     is_available: bool,
     next_gacha_time: UnixTime,
+    pity_count: u32,
+    streak: DailyStreak,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct GachaRecord {
+    gacha_type: GachaType,
+    latest: Option<GachaEvent>,
+    is_available: bool,
+    next_gacha_time: UnixTime,
+    // 続けて最高レア度を外している回数, pity_thresholdに達すると次は最高レア度が確定する
+    pity_count: u32,
+    pity_threshold: Option<u32>,
+}
+
+// recordの結果
+struct Recorded {
+    pity_count: u32,
+    obtained: u64,
+    streak_bonus: Option<StreakBonus>,
+    user: User,
 }
 
 impl GachaService {
     pub fn new(
         gacha_repo: Arc<dyn IGachaEventRepository + Sync + Send>,
+        gacha_state_repo: Arc<dyn IGachaStateRepository + Sync + Send>,
+        pending_gacha_repo: Arc<dyn IPendingGachaEventRepository + Sync + Send>,
         user_repo: Arc<dyn IUserRepository + Sync + Send>,
+        unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+        catalog: Arc<GachaCatalog>,
+        daily_reset: DailyReset,
     ) -> GachaService {
         GachaService {
             gacha_repo,
+            gacha_state_repo,
+            pending_gacha_repo,
             user_repo,
+            unit_of_work,
+            catalog,
+            daily_reset,
         }
     }
 
-    pub async fn get_latest_daily_event(
+    pub fn get_catalog(&self) -> &GachaCatalog {
+        self.catalog.as_ref()
+    }
+
+    async fn find_latest(
         &self,
-        auth: Authorization,
-    ) -> Result<serde_json::Value, ServiceError> {
-        let auth_user = auth.require_auth()?;
-        let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<Option<GachaEvent>, ServiceError> {
+        match self.gacha_repo.find_by_user_type(user_id, gacha_type).await {
+            Ok(r) => Ok(Some(r)),
+            Err(err) if err.status_code == http::StatusCode::NOT_FOUND => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
 
+    async fn find_state(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<Option<GachaState>, ServiceError> {
         match self
-            .gacha_repo
-            .find_by_user_type(&user.id, &GachaType::Daily)
+            .gacha_state_repo
+            .find_by_user_type(user_id, gacha_type)
             .await
         {
-            Ok(r) => serde_json::to_value(&r).map_err(|err| {
-                ServiceError::internal_server_error(failure::Error::from_boxed_compat(Box::new(
-                    err,
-                )))
-            }),
-            Err(err) if err.status_code == http::StatusCode::NOT_FOUND => {
-                Ok(serde_json::json!(null))
-            }
+            Ok(r) => Ok(Some(r)),
+            Err(err) if err.status_code == http::StatusCode::NOT_FOUND => Ok(None),
             Err(err) => Err(err),
         }
     }
 
+    // 最後に引いた時刻, 状態を持つ前に引いたユーザーは最後のガチャの記録の時刻を使う
+    fn last_drawn_at(state: Option<&GachaState>, latest: Option<&GachaEvent>) -> Option<UnixTime> {
+        state
+            .map(|s| s.last_drawn_at.clone())
+            .or_else(|| latest.map(|e| e.created_at.clone()))
+    }
+
+    // 天井までのカウント, last_drawn_atと同じく状態がなければ最後のガチャの記録を使う
+    // 10連の記録は全て同じ時刻なので, 状態があるときに記録から求めてはいけない
+    fn pity_count(state: Option<&GachaState>, latest: Option<&GachaEvent>) -> u32 {
+        state
+            .map(|s| s.pity_count)
+            .or_else(|| latest.map(|e| e.pity_count))
+            .unwrap_or(0)
+    }
+
+    pub async fn get_latest_daily_event(
+        &self,
+        auth: Authorization,
+    ) -> Result<Option<GachaEvent>, ServiceError> {
+        let auth_user = auth.require_auth()?;
+        let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+        self.replay_pending_events(&user.id).await?;
+
+        self.find_latest(&user.id, &GachaType::Daily).await
+    }
+
     pub async fn get_daily_gacha_record(
         &self,
         auth: Authorization,
     ) -> Result<DailyGachaRecord, ServiceError> {
         let auth_user = auth.require_auth()?;
         let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+        self.replay_pending_events(&user.id).await?;
 
-        let latest = match self
-            .gacha_repo
-            .find_by_user_type(&user.id, &GachaType::Daily)
-            .await
-        {
-            Err(err) if err.status_code == http::StatusCode::NOT_FOUND => Ok(None),
-            r => r.map(|e| Some(e)),
-        }?;
+        let state = self.find_state(&user.id, &GachaType::Daily).await?;
+        let latest = self.find_latest(&user.id, &GachaType::Daily).await?;
+        let now = UnixTime::now();
+        let is_available = user.is_daily_gacha_available_at(now.clone(), &self.daily_reset);
 
         Ok(DailyGachaRecord {
+            pity_count: GachaService::pity_count(state.as_ref(), latest.as_ref()),
             latest,
-            is_available: user.is_daily_gacha_available_at(UnixTime::now()),
-            next_gacha_time: UnixTime::now(),
+            is_available,
+            // 今引けるなら今, 引けないなら次に日付が変わる時刻
+            next_gacha_time: if is_available {
+                now.clone()
+            } else {
+                user.next_daily_gacha_time(&self.daily_reset)
+            },
+            streak: user.daily_streak.at(self.daily_reset.date_of(&now)),
         })
     }
 
-    pub async fn try_daily(&self, auth: Authorization) -> Result<serde_json::Value, ServiceError> {
+    pub async fn get_gacha_record(
+        &self,
+        auth: Authorization,
+        gacha_type: GachaType,
+    ) -> Result<GachaRecord, ServiceError> {
         let auth_user = auth.require_auth()?;
-        let mut user = self.user_repo.find_by_subject(&auth_user.subject).await?;
-        let user_cloned = user.clone();
+        let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+        let gacha = self.catalog.find(&gacha_type)?;
+        self.replay_pending_events(&user.id).await?;
 
-        if !user.is_daily_gacha_available_at(UnixTime::now()) {
-            return Err(ServiceError::bad_request(failure::err_msg(
-                "Daily Gacha Rate Limit Exceeded",
-            )));
+        let state = self.find_state(&user.id, &gacha.gacha_type).await?;
+        let latest = self.find_latest(&user.id, &gacha.gacha_type).await?;
+        let last_drawn_at = GachaService::last_drawn_at(state.as_ref(), latest.as_ref());
+        let now = UnixTime::now();
+        let is_available = self
+            .check_cooldown(&user, gacha, last_drawn_at.as_ref(), now.clone())
+            .is_ok();
+
+        Ok(GachaRecord {
+            gacha_type: gacha.gacha_type.clone(),
+            is_available,
+            next_gacha_time: if is_available {
+                now
+            } else {
+                self.next_gacha_time(&user, gacha, last_drawn_at.as_ref())
+            },
+            pity_count: GachaService::pity_count(state.as_ref(), latest.as_ref()),
+            pity_threshold: gacha.pity_threshold,
+            latest,
+        })
+    }
+
+    pub async fn try_daily(&self, auth: Authorization) -> Result<DailyGachaResponse, ServiceError> {
+        let response = self.draw(auth, GachaType::Daily).await?;
+
+        Ok(DailyGachaResponse {
+            obtained: response.obtained,
+            streak: response.streak.unwrap_or_default(),
+            streak_bonus: response.streak_bonus,
+        })
+    }
+
+    // クールダウンが明けて次に引けるようになる時刻
+    fn next_gacha_time(
+        &self,
+        user: &User,
+        gacha: &GachaDefinition,
+        last_drawn_at: Option<&UnixTime>,
+    ) -> UnixTime {
+        if gacha.gacha_type == GachaType::Daily {
+            return user.next_daily_gacha_time(&self.daily_reset);
         }
 
-        // 終端の16は含まない
-        let n = RandomGen::range(5, 16);
-        user.add_point(n);
-        let prev_timestamp = user.update_daily_gacha_timestamp();
+        match (last_drawn_at, &gacha.cooldown) {
+            (Some(last_drawn_at), GachaCooldown::Daily) => {
+                self.daily_reset.next_reset(last_drawn_at)
+            }
+            (Some(last_drawn_at), GachaCooldown::Seconds(seconds)) => {
+                UnixTime(last_drawn_at.0 + seconds)
+            }
+            _ => UnixTime::now(),
+        }
+    }
 
-        // ここでデイリーガチャのタイムスタンプでconditional writeを行うことで競合を防ぐ
-        self.user_repo
-            .conditional_save_point(user.clone(), prev_timestamp)
-            .await?;
+    fn check_cooldown(
+        &self,
+        user: &User,
+        gacha: &GachaDefinition,
+        last_drawn_at: Option<&UnixTime>,
+        now: UnixTime,
+    ) -> Result<(), ServiceError> {
+        // デイリーガチャはユーザーに持たせたタイムスタンプで判定する
+        if gacha.gacha_type == GachaType::Daily {
+            if !user.is_daily_gacha_available_at(now.clone(), &self.daily_reset) {
+                return Err(ServiceError::too_many_requests(
+                    ErrorCode::DailyGachaRateLimitExceeded,
+                    failure::err_msg("Daily Gacha Rate Limit Exceeded"),
+                ));
+            }
 
-        let event = GachaEvent {
-            id: GachaEventId::new(),
-            user_id: user.id,
-            gacha_type: GachaType::Daily,
-            created_at: UnixTime::now(),
+            return Ok(());
+        }
+
+        // 読んだ時点の状態で判定する, 同時に引かれたときはrecordのconditional writeで片方が失敗する
+        let last_drawn_at = match last_drawn_at {
+            Some(last_drawn_at) => last_drawn_at,
+            None => return Ok(()),
         };
+        let available = match gacha.cooldown {
+            GachaCooldown::Daily => !self.daily_reset.is_same_day(last_drawn_at, &now),
+            GachaCooldown::Seconds(seconds) => now.0 - last_drawn_at.0 >= seconds,
+            GachaCooldown::None => true,
+        };
+        if !available {
+            return Err(ServiceError::too_many_requests(
+                ErrorCode::GachaCooldownNotElapsed,
+                failure::err_msg("Gacha cooldown has not elapsed"),
+            )
+            .with_details(serde_json::json!({
+                "gacha_type": gacha.gacha_type,
+                "latest": last_drawn_at,
+            })));
+        }
+
+        Ok(())
+    }
+
+    // 足りないことが分かっているなら抽選しない, 最終的なチェックはapplyで行う
+    fn check_balance(user: &User, cost: u64) -> Result<(), ServiceError> {
+        if user.point < cost {
+            return Err(ServiceError::bad_request(
+                ErrorCode::NotEnoughPoint,
+                failure::err_msg("You do not have enough myon point"),
+            )
+            .with_details(serde_json::json!({
+                "required": cost,
+                "current": user.point,
+            })));
+        }
+
+        Ok(())
+    }
+
+    pub async fn draw(
+        &self,
+        auth: Authorization,
+        gacha_type: GachaType,
+    ) -> Result<GachaDrawResponse, ServiceError> {
+        let auth_user = auth.require_auth()?;
+        let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+        let gacha = self.catalog.find(&gacha_type)?;
+        self.replay_pending_events(&user.id).await?;
+        let state = self.find_state(&user.id, &gacha.gacha_type).await?;
+        let latest = self.find_latest(&user.id, &gacha.gacha_type).await?;
+
+        let now = UnixTime::now();
+        self.check_cooldown(
+            &user,
+            gacha,
+            GachaService::last_drawn_at(state.as_ref(), latest.as_ref()).as_ref(),
+            now.clone(),
+        )?;
+        GachaService::check_balance(&user, gacha.cost)?;
+
+        let pity = GachaService::pity_count(state.as_ref(), latest.as_ref());
+        let result = gacha.draw(pity, RandomGen::range);
+        let recorded = self
+            .record(user, gacha, vec![result.clone()], pity, state, now)
+            .await?;
 
-        if let Err(err) = self.gacha_repo.create(event.clone()).await {
-            warn!("Failed to create a new gacha event: {:?} {:?}", event, err);
-            error!("{:?}", err);
+        Ok(GachaDrawResponse {
+            gacha_type: gacha.gacha_type.clone(),
+            obtained: recorded.obtained,
+            result,
+            pity_count: recorded.pity_count,
+            streak: if gacha.gacha_type == GachaType::Daily {
+                Some(recorded.user.daily_streak)
+            } else {
+                None
+            },
+            streak_bonus: recorded.streak_bonus,
+        })
+    }
 
-            // 失敗したときはロールバックを試みる
-            if let Err(err) = self.user_repo.save(user_cloned.clone()).await {
-                // ロールバックに失敗した場合は不整合が起こるのでログだけ吐いておく
-                error!("Failed to save the original user data: {:?}", user_cloned);
-                error!("{:?}", err);
+    pub async fn draw_multi(
+        &self,
+        auth: Authorization,
+        gacha_type: GachaType,
+    ) -> Result<GachaMultiDrawResponse, ServiceError> {
+        let auth_user = auth.require_auth()?;
+        let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+        let gacha = self.catalog.find(&gacha_type)?;
+        let multi = gacha.multi_draw.as_ref().ok_or_else(|| {
+            ServiceError::bad_request(
+                ErrorCode::MultiDrawNotSupported,
+                failure::err_msg(format!(
+                    "Gacha {} cannot be drawn at once",
+                    gacha_type.to_string()
+                )),
+            )
+        })?;
+        self.replay_pending_events(&user.id).await?;
+
+        let state = self.find_state(&user.id, &gacha.gacha_type).await?;
+        let latest = self.find_latest(&user.id, &gacha.gacha_type).await?;
+
+        let now = UnixTime::now();
+        self.check_cooldown(
+            &user,
+            gacha,
+            GachaService::last_drawn_at(state.as_ref(), latest.as_ref()).as_ref(),
+            now.clone(),
+        )?;
+        let cost = gacha.cost * multi.count as u64;
+        GachaService::check_balance(&user, cost)?;
+
+        let pity = GachaService::pity_count(state.as_ref(), latest.as_ref());
+        let results = gacha.draw_multi(pity, RandomGen::range);
+        let recorded = self
+            .record(user, gacha, results.clone(), pity, state, now)
+            .await?;
 
-                return Err(ServiceError::internal_server_error(failure::err_msg(
-                    "operation failed",
-                )));
+        Ok(GachaMultiDrawResponse {
+            gacha_type: gacha.gacha_type.clone(),
+            spent: cost,
+            obtained: recorded.obtained,
+            results,
+            pity_count: recorded.pity_count,
+        })
+    }
+
+    // ガチャの記録はポイントの記録と同じトランザクションでpending_gacha_eventに書いておき, コミットした後にDynamoDBへ写す
+    // 写せた記録だけを消すので, 失敗した記録はMySQLに残り, 次にそのユーザーのガチャを読むときに写し直す
+    // 同じidなら上書きになるので, 同時に写し直されても構わない
+    async fn flush_events(&self, events: Vec<GachaEvent>) {
+        for event in events {
+            if let Err(err) = self.gacha_repo.save(event.clone()).await {
+                warn!(
+                    "Failed to save a gacha event, it stays pending: {:?} {:?}",
+                    event, err
+                );
+                continue;
+            }
+
+            if let Err(err) = self.pending_gacha_repo.delete(&event.id).await {
+                warn!(
+                    "Failed to delete a pending gacha event: {:?} {:?}",
+                    event.id, err
+                );
             }
+        }
+    }
+
+    // 前に写せなかったガチャの記録をDynamoDBへ写す
+    async fn replay_pending_events(&self, user_id: &UserId) -> Result<(), ServiceError> {
+        let events = self.pending_gacha_repo.find_by_user_id(user_id).await?;
+        if !events.is_empty() {
+            info!("Replaying {} pending gacha events", events.len());
+            self.flush_events(events).await;
+        }
+
+        Ok(())
+    }
 
-            warn!("Rollback completed");
+    // 1回ごとにGachaEventを作り, コストと獲得したポイントを記録する
+    // デイリーガチャのときは連続記録も更新する
+    // stateにはクールダウンの判定に使った状態を渡す
+    async fn record(
+        &self,
+        mut user: User,
+        gacha: &GachaDefinition,
+        results: Vec<GachaResult>,
+        mut pity: u32,
+        state: Option<GachaState>,
+        now: UnixTime,
+    ) -> Result<Recorded, ServiceError> {
+        let events = results
+            .iter()
+            .map(|result| {
+                pity = gacha.next_pity(pity, result);
 
-            return Err(err);
+                GachaEvent {
+                    id: GachaEventId::new(),
+                    user_id: user.id.clone(),
+                    gacha_type: gacha.gacha_type.clone(),
+                    created_at: now.clone(),
+                    result: Some(result.clone()),
+                    pity_count: pity,
+                }
+            })
+            .collect::<Vec<_>>();
+        let cost = gacha.cost * events.len() as u64;
+        // 天井までのカウントも同じ条件で更新するので, 同時に引かれても数え間違えない
+        let next_state = state
+            .clone()
+            .unwrap_or_else(|| GachaState::new(user.id.clone(), gacha.gacha_type.clone()))
+            .drawn(events.len() as u64, pity, now.clone());
+        let is_daily = gacha.gacha_type == GachaType::Daily;
+        let prev_timestamp = if is_daily {
+            Some(user.update_daily_gacha_timestamp(&self.daily_reset))
+        } else {
+            None
         };
+        let streak_bonus = if is_daily {
+            self.catalog
+                .streak_bonus(user.daily_streak.current)
+                .cloned()
+        } else {
+            None
+        };
+        let obtained = results.iter().map(|r| r.point).sum::<u64>()
+            * streak_bonus.as_ref().map(|b| b.multiplier).unwrap_or(1);
+
+        // ポイントの増減, gacha_stateの更新とガチャの記録を1つのトランザクションで行う
+        let tx = self.unit_of_work.begin().await?;
+        let tx_result = async {
+            // 判定に使った状態から変わっていないときだけ引ける
+            // 同時に引いた場合は後の方が先のトランザクションの終わりを待ってから失敗する
+            tx.gacha_state_repository()
+                .conditional_save(next_state, state)
+                .await?;
+            for event in events.iter() {
+                tx.pending_gacha_event_repository()
+                    .create(event.clone())
+                    .await?;
+            }
+
+            match prev_timestamp {
+                // ここでデイリーガチャのタイムスタンプでconditional writeを行うことで競合を防ぐ
+                Some(prev_timestamp) => {
+                    tx.point_transaction_repository()
+                        .conditional_apply(
+                            user.clone(),
+                            PointTransaction::new(
+                                user.id.clone(),
+                                PointTransactionReason::DailyGacha,
+                                Some(events[0].id.0.clone()),
+                                obtained as i64,
+                            ),
+                            prev_timestamp,
+                        )
+                        .await?;
+
+                    if let Some(point) = streak_bonus.as_ref().and_then(|b| b.gift_point) {
+                        let gift = Gift::new(
+                            GiftType::Point(point),
+                            format!(
+                                "デイリーガチャを{}日続けて引いたボーナスです",
+                                user.daily_streak.current
+                            ),
+                        );
+                        let status = gift.status.clone();
+                        tx.gift_repository()
+                            .create_for(gift, vec![user.id.clone()], status)
+                            .await?;
+                    }
+                }
+                None => {
+                    // 残高が足りなければここで失敗してロールバックされる
+                    if cost > 0 {
+                        tx.point_transaction_repository()
+                            .apply(PointTransaction::new(
+                                user.id.clone(),
+                                PointTransactionReason::GachaCost,
+                                Some(events[0].id.0.clone()),
+                                -(cost as i64),
+                            ))
+                            .await?;
+                    }
+                    for (event, result) in events.iter().zip(results.iter()) {
+                        if result.point > 0 {
+                            tx.point_transaction_repository()
+                                .apply(PointTransaction::new(
+                                    user.id.clone(),
+                                    PointTransactionReason::GachaReward,
+                                    Some(event.id.0.clone()),
+                                    result.point as i64,
+                                ))
+                                .await?;
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        .await;
+        tx.finish(tx_result).await?;
 
-        Ok(serde_json::json!({ "obtained": n }))
+        self.flush_events(events).await;
+
+        if is_daily {
+            metrics::inc_daily_gacha_drawn();
+            metrics::observe_point_delta(
+                &PointTransactionReason::DailyGacha.to_string(),
+                obtained as i64,
+            );
+        } else {
+            metrics::observe_point_delta(
+                &PointTransactionReason::GachaCost.to_string(),
+                -(cost as i64),
+            );
+            metrics::observe_point_delta(
+                &PointTransactionReason::GachaReward.to_string(),
+                obtained as i64,
+            );
+        }
+
+        Ok(Recorded {
+            pity_count: pity,
+            obtained,
+            streak_bonus,
+            user,
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::domain::model::{User, UserId};
+    use crate::domain::model::{GachaDrop, GachaReward, Rarity, UserId};
     use crate::infra::gacha_event_repository_mock::*;
+    use crate::infra::gacha_state_repository_mock::*;
+    use crate::infra::gift_repository_mock::*;
+    use crate::infra::janken_event_repository_mock::*;
+    use crate::infra::pending_gacha_event_repository_mock::*;
+    use crate::infra::point_transaction_repository_mock::*;
+    use crate::infra::unit_of_work_mock::*;
     use crate::infra::user_repository_mock::*;
 
+    fn unit_of_work(
+        point_transaction_repo: Arc<PointTransactionRepositoryMock>,
+    ) -> Arc<UnitOfWorkMock> {
+        Arc::new(UnitOfWorkMock::new(
+            Arc::new(GiftRepositoryMock::new()),
+            Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+            point_transaction_repo,
+        ))
+    }
+
     #[tokio::test]
     async fn gacha_available_with_no_records() -> Result<(), ServiceError> {
         let user_id = UserId::new();
         let service = GachaService::new(
             Arc::new(GachaEventRepositoryStub::new_empty()),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
             Arc::new(UserRepositoryStub::new(User {
                 id: user_id.clone(),
                 last_tried_daily_gacha: UnixTime(0),
                 ..Default::default()
             })),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(0))),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
         );
 
         let record = service
@@ -167,11 +651,16 @@ mod test {
         };
         let service = GachaService::new(
             Arc::new(GachaEventRepositoryStub::new(event.clone())),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
             Arc::new(UserRepositoryStub::new(User {
                 id: user_id.clone(),
                 last_tried_daily_gacha: UnixTime(0),
                 ..Default::default()
             })),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(0))),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
         );
 
         let record = service
@@ -192,11 +681,16 @@ mod test {
                 created_at: UnixTime::now(),
                 ..Default::default()
             })),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
             Arc::new(UserRepositoryStub::new(User {
                 id: user_id.clone(),
                 last_tried_daily_gacha: UnixTime::now(),
                 ..Default::default()
             })),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(0))),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
         );
 
         let record = service
@@ -211,18 +705,709 @@ mod test {
     async fn cannot_try_gacha_if_already_tried_in_same_day() -> Result<(), ServiceError> {
         let service = GachaService::new(
             Arc::new(GachaEventRepositoryStub::new(GachaEvent::default())),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
             Arc::new(UserRepositoryStub::new(User {
                 last_tried_daily_gacha: UnixTime::now(),
                 ..Default::default()
             })),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(0))),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
         );
 
         let err = service
             .try_daily(Authorization::new(Ok(Default::default())))
             .await
             .expect_err("expect error");
-        assert_eq!(err.status_code, http::StatusCode::BAD_REQUEST);
+        assert_eq!(err.status_code, http::StatusCode::TOO_MANY_REQUESTS);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn try_daily_records_point_transaction() -> Result<(), ServiceError> {
+        let user_id = UserId::new();
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(10));
+        let service = GachaService::new(
+            Arc::new(GachaEventRepositoryStub::new_empty()),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                id: user_id.clone(),
+                point: 10,
+                last_tried_daily_gacha: UnixTime(0),
+                ..Default::default()
+            })),
+            unit_of_work(point_transaction_repo.clone()),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
+        );
+
+        service
+            .try_daily(Authorization::new(Ok(Default::default())))
+            .await?;
+
+        let applied = point_transaction_repo.applied.lock().unwrap().clone();
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].user_id, user_id);
+        assert_eq!(applied[0].reason, PointTransactionReason::DailyGacha);
+        assert!(5 <= applied[0].delta && applied[0].delta < 16);
+        assert_eq!(applied[0].balance_after, 10 + applied[0].delta as u64);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn draw_named_gacha_spends_cost() -> Result<(), ServiceError> {
+        let mut catalog = GachaCatalog::default();
+        catalog.gachas.push(GachaDefinition {
+            gacha_type: GachaType::new("fixed"),
+            cost: 30,
+            cooldown: GachaCooldown::None,
+            drops: vec![GachaDrop {
+                weight: 1,
+                rarity: Rarity::Rare,
+                reward: GachaReward::Point { min: 10, max: 10 },
+            }],
+            multi_draw: None,
+            pity_threshold: None,
+        });
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(40));
+        let service = GachaService::new(
+            Arc::new(GachaEventRepositoryStub::new_empty()),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                point: 40,
+                ..Default::default()
+            })),
+            unit_of_work(point_transaction_repo.clone()),
+            Arc::new(catalog),
+            DailyReset::default(),
+        );
+
+        let response = service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("fixed"),
+            )
+            .await?;
+        assert_eq!(response.obtained, 10);
+        assert_eq!(response.result.rarity, Rarity::Rare);
+
+        let applied = point_transaction_repo.applied.lock().unwrap().clone();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].reason, PointTransactionReason::GachaCost);
+        assert_eq!(applied[0].delta, -30);
+        assert_eq!(applied[1].reason, PointTransactionReason::GachaReward);
+        assert_eq!(applied[1].balance_after, 20);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn draw_multi_records_each_pull() -> Result<(), ServiceError> {
+        let gacha_repo = Arc::new(GachaEventRepositoryStub::new_empty());
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(200));
+        let service = GachaService::new(
+            gacha_repo.clone(),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                point: 200,
+                ..Default::default()
+            })),
+            unit_of_work(point_transaction_repo.clone()),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
+        );
+
+        let response = service
+            .draw_multi(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("premium"),
+            )
+            .await?;
+        assert_eq!(response.spent, 200);
+        assert_eq!(response.results.len(), 10);
+        assert!(response.results.iter().any(|r| r.rarity >= Rarity::Rare));
+
+        let saved = gacha_repo.saved.lock().unwrap().clone();
+        assert_eq!(saved.len(), 10);
+        assert_eq!(
+            saved.iter().map(|e| e.result.clone()).collect::<Vec<_>>(),
+            response.results.into_iter().map(Some).collect::<Vec<_>>()
+        );
+
+        let applied = point_transaction_repo.applied.lock().unwrap().clone();
+        assert_eq!(applied[0].reason, PointTransactionReason::GachaCost);
+        assert_eq!(applied[0].delta, -200);
+        assert_eq!(applied.len(), 11);
+        assert_eq!(applied[10].balance_after, response.obtained);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_event_saves_should_stay_pending() -> Result<(), ServiceError> {
+        // DynamoDBへの書き込みが失敗しても, コミット済みのポイントはそのままで記録はMySQLに残る
+        let gacha_repo = Arc::new(GachaEventRepositoryStub::new_empty().failing(2));
+        let pending_gacha_repo = Arc::new(PendingGachaEventRepositoryMock::new());
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(200));
+        let unit_of_work = Arc::new(
+            UnitOfWorkMock::new(
+                Arc::new(GiftRepositoryMock::new()),
+                Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                point_transaction_repo.clone(),
+            )
+            .with_pending_gacha_event_repository(pending_gacha_repo.clone()),
+        );
+        let service = GachaService::new(
+            gacha_repo.clone(),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            pending_gacha_repo.clone(),
+            Arc::new(UserRepositoryStub::new(User {
+                point: 200,
+                ..Default::default()
+            })),
+            unit_of_work.clone(),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
+        );
+
+        let response = service
+            .draw_multi(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("premium"),
+            )
+            .await?;
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 1);
+        assert_eq!(*unit_of_work.rolled_back.lock().unwrap(), 0);
+        assert_eq!(response.results.len(), 10);
+        assert_eq!(gacha_repo.saved.lock().unwrap().len(), 8);
+        assert_eq!(pending_gacha_repo.events.lock().unwrap().len(), 2);
+
+        // 次に記録を読むときに残りを写し直す
+        service
+            .get_gacha_record(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("premium"),
+            )
+            .await?;
+        assert!(pending_gacha_repo.events.lock().unwrap().is_empty());
+
+        // 10回分が1つずつ書き込まれ, 各イベントは報酬の取引から辿れる
+        let mut saved = gacha_repo
+            .saved
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.id.0.clone())
+            .collect::<Vec<_>>();
+        saved.sort();
+        let mut referenced = point_transaction_repo
+            .applied
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| t.reason == PointTransactionReason::GachaReward)
+            .filter_map(|t| t.source_event_id.clone())
+            .collect::<Vec<_>>();
+        referenced.sort();
+        assert_eq!(saved, referenced);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn draw_multi_requires_enough_point() -> Result<(), ServiceError> {
+        let gacha_repo = Arc::new(GachaEventRepositoryStub::new_empty());
+        let service = GachaService::new(
+            gacha_repo.clone(),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                point: 199,
+                ..Default::default()
+            })),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(199))),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
+        );
+
+        let err = service
+            .draw_multi(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("premium"),
+            )
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.code, ErrorCode::NotEnoughPoint);
+        assert!(gacha_repo.saved.lock().unwrap().is_empty());
+
+        let err = service
+            .draw_multi(Authorization::new(Ok(Default::default())), GachaType::Daily)
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.code, ErrorCode::MultiDrawNotSupported);
+
+        Ok(())
+    }
+
+    fn hourly_catalog() -> GachaCatalog {
+        let mut catalog = GachaCatalog::default();
+        catalog.gachas.push(GachaDefinition {
+            gacha_type: GachaType::new("hourly"),
+            cost: 0,
+            cooldown: GachaCooldown::Seconds(60 * 60),
+            drops: vec![GachaDrop {
+                weight: 1,
+                rarity: Rarity::Common,
+                reward: GachaReward::Point { min: 10, max: 10 },
+            }],
+            multi_draw: None,
+            pity_threshold: None,
+        });
+
+        catalog
+    }
+
+    #[tokio::test]
+    async fn cooldown_should_use_saved_state() -> Result<(), ServiceError> {
+        let gacha_state_repo = Arc::new(GachaStateRepositoryMock::new(Vec::new()));
+        let service = GachaService::new(
+            Arc::new(GachaEventRepositoryStub::new_empty()),
+            gacha_state_repo.clone(),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User::default())),
+            Arc::new(
+                UnitOfWorkMock::new(
+                    Arc::new(GiftRepositoryMock::new()),
+                    Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                    Arc::new(PointTransactionRepositoryMock::new(0)),
+                )
+                .with_gacha_state_repository(gacha_state_repo.clone()),
+            ),
+            Arc::new(hourly_catalog()),
+            DailyReset::default(),
+        );
+
+        service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("hourly"),
+            )
+            .await?;
+        let states = gacha_state_repo.states.lock().unwrap().clone();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].draw_count, 1);
+
+        let err = service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("hourly"),
+            )
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.code, ErrorCode::GachaCooldownNotElapsed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_draw_should_not_pay_out_twice() -> Result<(), ServiceError> {
+        let user_id = UserId::new();
+        let state = GachaState::new(user_id.clone(), GachaType::new("hourly"));
+        // 判定の後に他のリクエストが先に引いて状態を更新した
+        let drawn = state.drawn(1, 0, UnixTime::now());
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(0));
+        let unit_of_work = Arc::new(
+            UnitOfWorkMock::new(
+                Arc::new(GiftRepositoryMock::new()),
+                Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                point_transaction_repo.clone(),
+            )
+            .with_gacha_state_repository(Arc::new(GachaStateRepositoryMock::new(vec![drawn]))),
+        );
+        let service = GachaService::new(
+            Arc::new(GachaEventRepositoryStub::new_empty()),
+            Arc::new(GachaStateRepositoryMock::new(vec![state])),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                id: user_id,
+                ..Default::default()
+            })),
+            unit_of_work.clone(),
+            Arc::new(hourly_catalog()),
+            DailyReset::default(),
+        );
+
+        let err = service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("hourly"),
+            )
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.code, ErrorCode::ConditionNotMet);
+        assert!(point_transaction_repo.applied.lock().unwrap().is_empty());
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 0);
+        assert_eq!(*unit_of_work.rolled_back.lock().unwrap(), 1);
+
+        Ok(())
+    }
+
+    fn yesterday() -> String {
+        DailyReset::default()
+            .date_of(&UnixTime(UnixTime::now().0 - 24 * 60 * 60))
+            .format("%Y-%m-%d")
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn try_daily_should_apply_streak_bonus() -> Result<(), ServiceError> {
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(0));
+        let gift_repo = Arc::new(GiftRepositoryMock::new());
+        let service = GachaService::new(
+            Arc::new(GachaEventRepositoryStub::new_empty()),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                last_tried_daily_gacha: UnixTime(0),
+                daily_streak: DailyStreak {
+                    current: 29,
+                    longest: 29,
+                    last_date: Some(yesterday()),
+                },
+                ..Default::default()
+            })),
+            Arc::new(UnitOfWorkMock::new(
+                gift_repo.clone(),
+                Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                point_transaction_repo.clone(),
+            )),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
+        );
+
+        let response = service
+            .try_daily(Authorization::new(Ok(Default::default())))
+            .await?;
+        assert_eq!(response.streak.current, 30);
+        assert_eq!(response.streak.longest, 30);
+        assert_eq!(response.streak_bonus.as_ref().unwrap().days, 30);
+
+        // 30日目はポイントが2倍になり, ギフトも届く
+        let applied = point_transaction_repo.applied.lock().unwrap().clone();
+        assert_eq!(applied[0].delta as u64, response.obtained);
+        assert_eq!(response.obtained % 2, 0);
+        assert!(10 <= response.obtained && response.obtained <= 30);
+
+        let gifts = gift_repo.created.lock().unwrap().clone();
+        assert_eq!(gifts.len(), 1);
+        assert_eq!(gifts[0].gift_type, GiftType::Point(100));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn try_daily_should_reset_broken_streak() -> Result<(), ServiceError> {
+        let gift_repo = Arc::new(GiftRepositoryMock::new());
+        let service = GachaService::new(
+            Arc::new(GachaEventRepositoryStub::new_empty()),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                last_tried_daily_gacha: UnixTime(0),
+                daily_streak: DailyStreak {
+                    current: 6,
+                    longest: 20,
+                    last_date: Some("2020-01-01".to_string()),
+                },
+                ..Default::default()
+            })),
+            Arc::new(UnitOfWorkMock::new(
+                gift_repo.clone(),
+                Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                Arc::new(PointTransactionRepositoryMock::new(0)),
+            )),
+            Arc::new(GachaCatalog::default()),
+            DailyReset::default(),
+        );
+
+        let response = service
+            .try_daily(Authorization::new(Ok(Default::default())))
+            .await?;
+        assert_eq!(response.streak.current, 1);
+        assert_eq!(response.streak.longest, 20);
+        assert!(response.streak_bonus.is_none());
+        assert!(gift_repo.created.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    fn pity_catalog() -> GachaCatalog {
+        let mut catalog = GachaCatalog::default();
+        catalog.gachas.push(GachaDefinition {
+            gacha_type: GachaType::new("pity"),
+            cost: 0,
+            cooldown: GachaCooldown::None,
+            drops: vec![
+                GachaDrop {
+                    weight: 1_000_000,
+                    rarity: Rarity::Common,
+                    reward: GachaReward::Point { min: 1, max: 1 },
+                },
+                GachaDrop {
+                    weight: 0,
+                    rarity: Rarity::SuperRare,
+                    reward: GachaReward::Point { min: 50, max: 50 },
+                },
+                GachaDrop {
+                    weight: 1,
+                    rarity: Rarity::UltraRare,
+                    reward: GachaReward::Point { min: 100, max: 100 },
+                },
+            ],
+            multi_draw: None,
+            pity_threshold: Some(3),
+        });
+
+        catalog
+    }
+
+    #[tokio::test]
+    async fn pity_should_force_top_rarity_and_reset() -> Result<(), ServiceError> {
+        let gacha_repo = Arc::new(GachaEventRepositoryStub::new(GachaEvent {
+            gacha_type: GachaType::new("pity"),
+            created_at: UnixTime(0),
+            pity_count: 3,
+            ..Default::default()
+        }));
+        let service = GachaService::new(
+            gacha_repo.clone(),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User::default())),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(0))),
+            Arc::new(pity_catalog()),
+            DailyReset::default(),
+        );
+
+        let record = service
+            .get_gacha_record(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("pity"),
+            )
+            .await?;
+        assert_eq!(record.pity_count, 3);
+        assert_eq!(record.pity_threshold, Some(3));
+
+        // 3回外した後なので重みに関わらず最高レア度が出て, カウントが戻る
+        let response = service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("pity"),
+            )
+            .await?;
+        assert_eq!(response.result.rarity, Rarity::UltraRare);
+        assert_eq!(response.obtained, 100);
+        assert_eq!(response.pity_count, 0);
+
+        let saved = gacha_repo.saved.lock().unwrap().clone();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].pity_count, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pity_should_count_misses() -> Result<(), ServiceError> {
+        let gacha_repo = Arc::new(GachaEventRepositoryStub::new(GachaEvent {
+            gacha_type: GachaType::new("pity"),
+            created_at: UnixTime(0),
+            pity_count: 1,
+            ..Default::default()
+        }));
+        let mut catalog = pity_catalog();
+        // 最高レア度が出ないようにする
+        catalog.gachas[2].drops[2].weight = 0;
+        catalog.gachas[2].drops[1].weight = 1;
+        let service = GachaService::new(
+            gacha_repo.clone(),
+            Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User::default())),
+            unit_of_work(Arc::new(PointTransactionRepositoryMock::new(0))),
+            Arc::new(catalog),
+            DailyReset::default(),
+        );
+
+        let response = service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("pity"),
+            )
+            .await?;
+        assert_eq!(response.result.rarity, Rarity::Common);
+        assert_eq!(response.pity_count, 2);
+        assert_eq!(gacha_repo.saved.lock().unwrap()[0].pity_count, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pity_should_come_from_state() -> Result<(), ServiceError> {
+        let user_id = UserId::new();
+        // 10連の記録は同じ時刻なので, どれが最新として返るか分からない
+        let gacha_repo = Arc::new(GachaEventRepositoryStub::new(GachaEvent {
+            gacha_type: GachaType::new("pity"),
+            created_at: UnixTime(0),
+            pity_count: 0,
+            ..Default::default()
+        }));
+        let mut state = GachaState::new(user_id.clone(), GachaType::new("pity"));
+        state.pity_count = 3;
+        let gacha_state_repo = Arc::new(GachaStateRepositoryMock::new(vec![state]));
+        let service = GachaService::new(
+            gacha_repo.clone(),
+            gacha_state_repo.clone(),
+            Arc::new(PendingGachaEventRepositoryMock::new()),
+            Arc::new(UserRepositoryStub::new(User {
+                id: user_id,
+                ..Default::default()
+            })),
+            Arc::new(
+                UnitOfWorkMock::new(
+                    Arc::new(GiftRepositoryMock::new()),
+                    Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                    Arc::new(PointTransactionRepositoryMock::new(0)),
+                )
+                .with_gacha_state_repository(gacha_state_repo.clone()),
+            ),
+            Arc::new(pity_catalog()),
+            DailyReset::default(),
+        );
+
+        let record = service
+            .get_gacha_record(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("pity"),
+            )
+            .await?;
+        assert_eq!(record.pity_count, 3);
+
+        let response = service
+            .draw(
+                Authorization::new(Ok(Default::default())),
+                GachaType::new("pity"),
+            )
+            .await?;
+        assert_eq!(response.result.rarity, Rarity::UltraRare);
+        assert_eq!(response.pity_count, 0);
+
+        let states = gacha_state_repo.states.lock().unwrap().clone();
+        assert_eq!(states[0].pity_count, 0);
+        assert_eq!(states[0].draw_count, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = DailyGachaResponse {
+            obtained: 20,
+            streak: DailyStreak {
+                current: 7,
+                longest: 7,
+                last_date: Some("2020-09-13".to_string()),
+            },
+            streak_bonus: Some(StreakBonus {
+                days: 7,
+                multiplier: 2,
+                gift_point: None,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "obtained": 20,
+                "streak": { "current": 7, "longest": 7, "last_date": "2020-09-13" },
+                "streak_bonus": { "days": 7, "multiplier": 2, "gift_point": null },
+            })
+        );
+
+        let response = GachaMultiDrawResponse {
+            gacha_type: GachaType::new("premium"),
+            spent: 40,
+            obtained: 35,
+            results: vec![
+                GachaResult {
+                    rarity: Rarity::Common,
+                    point: 5,
+                    item: None,
+                },
+                GachaResult {
+                    rarity: Rarity::Rare,
+                    point: 30,
+                    item: None,
+                },
+            ],
+            pity_count: 0,
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "gacha_type": "premium",
+                "spent": 40,
+                "obtained": 35,
+                "results": [
+                    { "rarity": "common", "point": 5, "item": null },
+                    { "rarity": "rare", "point": 30, "item": null },
+                ],
+                "pity_count": 0,
+            })
+        );
+
+        let record = DailyGachaRecord {
+            latest: Some(GachaEvent {
+                id: GachaEventId("gacha-1".to_string()),
+                user_id: UserId("user-1".to_string()),
+                gacha_type: GachaType::Daily,
+                created_at: UnixTime(1600000000),
+                result: Some(GachaResult {
+                    rarity: Rarity::Common,
+                    point: 10,
+                    item: None,
+                }),
+                pity_count: 0,
+            }),
+            is_available: false,
+            next_gacha_time: UnixTime(1600012800),
+            pity_count: 0,
+            streak: DailyStreak {
+                current: 0,
+                longest: 12,
+                last_date: Some("2020-09-01".to_string()),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(record).unwrap(),
+            serde_json::json!({
+                "latest": {
+                    "id": "gacha-1",
+                    "user_id": "user-1",
+                    "gacha_type": "daily",
+                    "created_at": 1600000000,
+                    "result": { "rarity": "common", "point": 10, "item": null },
+                    "pity_count": 0,
+                },
+                "is_available": false,
+                "next_gacha_time": 1600012800,
+                "pity_count": 0,
+                "streak": { "current": 0, "longest": 12, "last_date": "2020-09-01" },
+            })
+        );
+    }
 }
@@ -1,6 +1,7 @@
 use crate::domain::interface::{IGiftRepository, IUserRepository};
 use crate::domain::model::{Authorization, Gift, GiftType};
 use crate::wrapper::error::ServiceError;
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
@@ -9,7 +10,7 @@ pub struct GiftDistributionService {
     gift_repo: Arc<dyn IGiftRepository + Sync + Send>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct DistributeInput {
     point: u64,
     description: String,
@@ -93,7 +94,7 @@ mod tests {
             .await
             .expect_err("error");
 
-        assert_eq!(err.status_code, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(err.status_code, http::StatusCode::FORBIDDEN);
 
         Ok(())
     }
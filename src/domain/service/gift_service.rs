@@ -1,15 +1,20 @@
-use crate::domain::interface::{IGiftRepository, IUserRepository};
-use crate::domain::model::{Authorization, Gift, GiftId, GiftStatus, GiftType};
+use crate::domain::interface::{IGiftRepository, IUnitOfWork, IUserRepository};
+use crate::domain::model::{
+    Authorization, Gift, GiftId, GiftStatus, GiftType, PointTransaction, PointTransactionReason,
+};
+use crate::metrics;
 use crate::wrapper::error::ServiceError;
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
 pub struct GiftService {
     gift_repository: Arc<dyn IGiftRepository + Sync + Send>,
     user_repository: Arc<dyn IUserRepository + Sync + Send>,
+    unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct ListGiftResponse {
     data: Vec<Gift>,
 }
@@ -18,10 +23,12 @@ impl GiftService {
     pub fn new(
         gift_repository: Arc<dyn IGiftRepository + Sync + Send>,
         user_repository: Arc<dyn IUserRepository + Sync + Send>,
+        unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
     ) -> Self {
         GiftService {
             gift_repository,
             user_repository,
+            unit_of_work,
         }
     }
 
@@ -46,39 +53,43 @@ impl GiftService {
 
     pub async fn open(&self, auth: Authorization, gift_id: &GiftId) -> Result<(), ServiceError> {
         let auth_user = auth.require_auth()?;
-        let mut user = self
+        let user = self
             .user_repository
             .find_by_subject(&auth_user.subject)
             .await?;
-        let user_cloned = user.clone();
-
-        let mut gift = self.gift_repository.find_by_id(gift_id, &user.id).await?;
 
-        gift.open()?;
-        match gift.gift_type {
-            GiftType::Point(p) => {
-                user.add_point(p);
-                self.user_repository.save(user).await?;
-            }
+        // ポイントの付与とギフトの開封済みへの更新は1つのトランザクションで行う
+        let tx = self.unit_of_work.begin().await?;
+        let result = async {
+            let mut gift = tx.gift_repository().find_by_id(gift_id, &user.id).await?;
+
+            gift.open()?;
+            let point = match gift.gift_type {
+                GiftType::Point(p) => {
+                    tx.point_transaction_repository()
+                        .apply(PointTransaction::new(
+                            user.id.clone(),
+                            PointTransactionReason::GiftOpened,
+                            Some(gift.id.0.clone()),
+                            p as i64,
+                        ))
+                        .await?;
+
+                    p as i64
+                }
+            };
+
+            tx.gift_repository()
+                .save_status(gift.id, user.id.clone(), gift.status)
+                .await?;
+
+            Ok(point)
         }
+        .await;
+        let point = tx.finish(result).await?;
 
-        match self
-            .gift_repository
-            .save_status(gift.id, user_cloned.id.clone(), gift.status)
-            .await
-        {
-            Ok(_) => (),
-            Err(err) => {
-                error!("Error: {:?}", err);
-                error!("Start to rollback...");
-
-                // エラーが起きたらロールバックする
-                // ロールバックも失敗したら諦める
-                self.user_repository.save(user_cloned).await?;
-
-                warn!("Rollback completed");
-            }
-        }
+        metrics::inc_gifts_opened();
+        metrics::observe_point_delta(&PointTransactionReason::GiftOpened.to_string(), point);
 
         Ok(())
     }
@@ -87,9 +98,13 @@ impl GiftService {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::model::{GiftStatus, User};
+    use crate::domain::model::{GiftId, GiftStatus, JankenEventId, User};
     use crate::infra::gift_repository_mock::GiftRepositoryItemStub;
+    use crate::infra::janken_event_repository_mock::JankenEventRepositoryMock;
+    use crate::infra::point_transaction_repository_mock::PointTransactionRepositoryMock;
+    use crate::infra::unit_of_work_mock::UnitOfWorkMock;
     use crate::infra::user_repository_mock::UserRepositoryStub;
+    use crate::wrapper::unixtime::UnixTime;
 
     #[tokio::test]
     async fn open_gift_and_got_point() -> Result<(), ServiceError> {
@@ -101,7 +116,13 @@ mod tests {
 
         let gift_repo = Arc::new(GiftRepositoryItemStub::new(gift.clone()));
         let user_repo = Arc::new(UserRepositoryStub::new(user));
-        let service = GiftService::new(gift_repo.clone(), user_repo.clone());
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(10));
+        let unit_of_work = Arc::new(UnitOfWorkMock::new(
+            gift_repo.clone(),
+            Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+            point_transaction_repo.clone(),
+        ));
+        let service = GiftService::new(gift_repo.clone(), user_repo.clone(), unit_of_work.clone());
 
         service
             .open(Authorization::new(Ok(Default::default())), &gift.id)
@@ -111,10 +132,71 @@ mod tests {
         assert_eq!(gifts.len(), 1);
         assert_eq!(gifts[0].2, GiftStatus::Opened);
 
-        let users = user_repo.saved.lock().unwrap().clone();
-        assert_eq!(users.len(), 1);
-        assert_eq!(users[0].point, 15);
+        let transactions = point_transaction_repo.applied.lock().unwrap().clone();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].reason, PointTransactionReason::GiftOpened);
+        assert_eq!(transactions[0].source_event_id, Some(gift.id.0.clone()));
+        assert_eq!(transactions[0].delta, 5);
+        assert_eq!(transactions[0].balance_after, 15);
+
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 1);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn open_gift_twice_should_rollback() -> Result<(), ServiceError> {
+        let mut gift = Gift::new(GiftType::Point(5), "".to_string());
+        gift.open()?;
+
+        let gift_repo = Arc::new(GiftRepositoryItemStub::new(gift.clone()));
+        let user_repo = Arc::new(UserRepositoryStub::new(User::default()));
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(10));
+        let unit_of_work = Arc::new(UnitOfWorkMock::new(
+            gift_repo.clone(),
+            Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+            point_transaction_repo.clone(),
+        ));
+        let service = GiftService::new(gift_repo.clone(), user_repo.clone(), unit_of_work.clone());
+
+        service
+            .open(Authorization::new(Ok(Default::default())), &gift.id)
+            .await
+            .expect_err("expect error");
+
+        assert_eq!(point_transaction_repo.applied.lock().unwrap().len(), 0);
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 0);
+        assert_eq!(*unit_of_work.rolled_back.lock().unwrap(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = ListGiftResponse {
+            data: vec![Gift {
+                id: GiftId("gift-1".to_string()),
+                gift_type: GiftType::Point(5),
+                description: "じゃんけんの勝ち分".to_string(),
+                created_at: UnixTime(1600000000),
+                status: GiftStatus::Ready,
+                janken_win_event: Some(JankenEventId("janken-1".to_string())),
+                janken_lose_event: None,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "data": [{
+                    "id": "gift-1",
+                    "gift_type": { "point": 5 },
+                    "description": "じゃんけんの勝ち分",
+                    "created_at": 1600000000,
+                    "status": "ready",
+                    "janken_win_event": "janken-1",
+                    "janken_lose_event": null,
+                }]
+            })
+        );
+    }
 }
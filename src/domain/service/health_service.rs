@@ -0,0 +1,245 @@
+use crate::domain::interface::IHealthCheck;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use schemars::JsonSchema;
+use serde::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub struct HealthService {
+    checks: Vec<Arc<dyn IHealthCheck + Sync + Send>>,
+    timeout: Duration,
+    schema_version: u32,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HealthResponse {
+    status: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DependencyStatus {
+    name: String,
+    ok: bool,
+    elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ReadinessResponse {
+    status: String,
+    checks: Vec<DependencyStatus>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct VersionResponse {
+    version: String,
+    git_sha: String,
+    schema_version: u32,
+}
+
+impl HealthService {
+    pub fn new(
+        checks: Vec<Arc<dyn IHealthCheck + Sync + Send>>,
+        timeout: Duration,
+        schema_version: u32,
+    ) -> Self {
+        HealthService {
+            checks,
+            timeout,
+            schema_version,
+        }
+    }
+
+    // プロセスが応答できるかどうかだけを見る
+    pub fn liveness(&self) -> HealthResponse {
+        HealthResponse {
+            status: "ok".to_string(),
+        }
+    }
+
+    // 全ての依存を並行に確認し, 1つでも失敗したら503で各依存の結果を返す
+    pub async fn readiness(&self) -> Result<ReadinessResponse, ServiceError> {
+        let checks = futures::future::join_all(
+            self.checks
+                .iter()
+                .map(|check| HealthService::run_check(check.as_ref(), self.timeout)),
+        )
+        .await;
+
+        if checks.iter().all(|check| check.ok) {
+            return Ok(ReadinessResponse {
+                status: "ok".to_string(),
+                checks,
+            });
+        }
+
+        let failed = checks
+            .iter()
+            .filter(|check| !check.ok)
+            .map(|check| check.name.clone())
+            .collect::<Vec<_>>();
+
+        Err(ServiceError::service_unavailable(
+            ErrorCode::DependencyUnavailable,
+            failure::err_msg(format!("Dependencies are not ready: {}", failed.join(", "))),
+        )
+        .with_details(serde_json::json!({ "checks": checks })))
+    }
+
+    async fn run_check(
+        check: &(dyn IHealthCheck + Sync + Send),
+        timeout: Duration,
+    ) -> DependencyStatus {
+        let started_at = Instant::now();
+        let result = tokio::time::timeout(timeout, check.check()).await;
+        let elapsed_ms = started_at.elapsed().as_millis() as u64;
+
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err.error.to_string()),
+            Err(_) => Some(format!("timed out after {}ms", timeout.as_millis())),
+        };
+
+        DependencyStatus {
+            name: check.name().to_string(),
+            ok: error.is_none(),
+            elapsed_ms,
+            error,
+        }
+    }
+
+    pub fn version(&self) -> VersionResponse {
+        VersionResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            // build.rsで埋め込む
+            git_sha: env!("GIT_SHA").to_string(),
+            schema_version: self.schema_version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    struct CheckStub {
+        name: &'static str,
+        delay: Duration,
+        result: fn() -> Result<(), ServiceError>,
+    }
+
+    #[async_trait]
+    impl IHealthCheck for CheckStub {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn check(&self) -> Result<(), ServiceError> {
+            tokio::time::delay_for(self.delay).await;
+
+            (self.result)()
+        }
+    }
+
+    fn service(checks: Vec<CheckStub>) -> HealthService {
+        HealthService::new(
+            checks
+                .into_iter()
+                .map(|check| Arc::new(check) as Arc<dyn IHealthCheck + Sync + Send>)
+                .collect(),
+            Duration::from_millis(100),
+            1,
+        )
+    }
+
+    #[tokio::test]
+    async fn readiness_should_succeed_when_all_checks_pass() -> Result<(), ServiceError> {
+        let resp = service(vec![CheckStub {
+            name: "mysql",
+            delay: Duration::from_millis(0),
+            result: || Ok(()),
+        }])
+        .readiness()
+        .await?;
+        assert_eq!(resp.status, "ok");
+        assert!(resp.checks[0].ok);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readiness_should_report_failed_and_timed_out_checks() {
+        let err = service(vec![
+            CheckStub {
+                name: "mysql",
+                delay: Duration::from_millis(0),
+                result: || {
+                    Err(ServiceError::service_unavailable(
+                        ErrorCode::DatabaseUnavailable,
+                        failure::err_msg("connection refused"),
+                    ))
+                },
+            },
+            CheckStub {
+                name: "dynamodb",
+                delay: Duration::from_secs(10),
+                result: || Ok(()),
+            },
+            CheckStub {
+                name: "s3",
+                delay: Duration::from_millis(0),
+                result: || Ok(()),
+            },
+        ])
+        .readiness()
+        .await
+        .err()
+        .expect("expect error");
+        assert_eq!(err.status_code, http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code, ErrorCode::DependencyUnavailable);
+
+        let checks = &err.details.unwrap()["checks"];
+        assert_eq!(checks[0]["name"], "mysql");
+        assert_eq!(checks[0]["ok"], false);
+        assert_eq!(checks[0]["error"], "connection refused");
+        assert_eq!(checks[1]["ok"], false);
+        assert_eq!(checks[1]["error"], "timed out after 100ms");
+        assert_eq!(checks[2]["ok"], true);
+    }
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = ReadinessResponse {
+            status: "ok".to_string(),
+            checks: vec![DependencyStatus {
+                name: "mysql".to_string(),
+                ok: true,
+                elapsed_ms: 3,
+                error: None,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "status": "ok",
+                "checks": [{ "name": "mysql", "ok": true, "elapsed_ms": 3 }],
+            })
+        );
+
+        let response = VersionResponse {
+            version: "0.1.0".to_string(),
+            git_sha: "0123abc".to_string(),
+            schema_version: 2,
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "version": "0.1.0",
+                "git_sha": "0123abc",
+                "schema_version": 2,
+            })
+        );
+    }
+}
@@ -1,26 +1,48 @@
-use crate::domain::interface::{IGiftRepository, IJankenEventRepository, IUserRepository};
+use crate::domain::interface::{IJankenEventRepository, IUnitOfWork, IUserRepository};
 use crate::domain::model::{Gift, GiftType, JankenEvent, JankenResult, JankenStatus};
 use crate::error::ServiceError;
+use crate::metrics;
+use crate::shutdown::Shutdown;
+use crate::wrapper::daily_reset::DailyReset;
 use crate::wrapper::unixtime::UnixTime;
+use futures::prelude::*;
 use rand::seq::SliceRandom;
 use std::sync::Arc;
 
 pub struct JankenProcessService {
     janken_repo: Arc<dyn IJankenEventRepository + Sync + Send>,
-    gift_repo: Arc<dyn IGiftRepository + Sync + Send>,
     user_repo: Arc<dyn IUserRepository + Sync + Send>,
+    unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+    daily_reset: DailyReset,
 }
 
+// 相手が見つからないまま待つ最大の時間
+const JANKEN_TIMEOUT_HOURS: i64 = 8;
+
 impl JankenProcessService {
     pub fn new(
         janken_repo: Arc<dyn IJankenEventRepository + Sync + Send>,
-        gift_repo: Arc<dyn IGiftRepository + Sync + Send>,
         user_repo: Arc<dyn IUserRepository + Sync + Send>,
+        unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+        daily_reset: DailyReset,
     ) -> Self {
         JankenProcessService {
             janken_repo,
-            gift_repo,
             user_repo,
+            unit_of_work,
+            daily_reset,
+        }
+    }
+
+    // 8時間経つか日付が変わったらタイムアウト
+    fn timeout_at(&self, created_at: &UnixTime) -> UnixTime {
+        let timeout = UnixTime(created_at.0 + JANKEN_TIMEOUT_HOURS * 60 * 60);
+        let next_reset = self.daily_reset.next_reset(created_at);
+
+        if next_reset.0 < timeout.0 {
+            next_reset
+        } else {
+            timeout
         }
     }
 
@@ -28,20 +50,25 @@ impl JankenProcessService {
         let mut events_filtered = Vec::new();
         for mut event in events {
             // タイムアウトを設定
-            if (UnixTime::now().datetime_jst() - event.created_at.datetime_jst())
-                >= chrono::Duration::hours(8)
-            {
+            if UnixTime::now().0 >= self.timeout_at(&event.created_at).0 {
                 event.set_timeout();
-                self.janken_repo.save(event.clone()).await?;
 
                 let gift = Gift::new(
                     GiftType::Point(event.point * 2),
                     "じゃんけんで不戦勝となったのでその報酬です".to_string(),
                 );
                 let status = gift.status.clone();
-                self.gift_repo
-                    .create_for(gift, vec![event.user_id], status)
-                    .await?;
+
+                let tx = self.unit_of_work.begin().await?;
+                let result = async {
+                    tx.janken_repository().save(event.clone()).await?;
+                    tx.gift_repository()
+                        .create_for(gift, vec![event.user_id], status)
+                        .await
+                }
+                .await;
+                tx.finish(result).await?;
+                metrics::inc_janken_matches("timed_out");
 
                 continue;
             } else {
@@ -67,10 +94,6 @@ impl JankenProcessService {
                     winner.set_opponent(loser_user.id, loser_user.screen_name);
                     loser.set_opponent(winner_user.id, winner_user.screen_name);
 
-                    self.janken_repo
-                        .save_all(vec![winner.clone(), loser.clone()])
-                        .await?;
-
                     // 勝った方にはギフトとして合計ポイントを送る
                     // 負けた方は、すでにポイントを払っているため何もしない
                     let mut gift = Gift::new(
@@ -79,12 +102,23 @@ impl JankenProcessService {
                     );
 
                     // じゃんけんのイベントIDを追跡用に紐付けておくことで、途中で落ちたときに追跡できるようにしておく
-                    gift.set_janken_events(winner.id, loser.id);
+                    gift.set_janken_events(winner.id.clone(), loser.id.clone());
 
                     let status = gift.status.clone();
-                    self.gift_repo
-                        .create_for(gift, vec![winner.user_id], status)
-                        .await?;
+
+                    // 結果の保存と報酬の作成は1つのトランザクションで行う
+                    let tx = self.unit_of_work.begin().await?;
+                    let result = async {
+                        tx.janken_repository()
+                            .save_all(vec![winner.clone(), loser.clone()])
+                            .await?;
+                        tx.gift_repository()
+                            .create_for(gift, vec![winner.user_id.clone()], status)
+                            .await
+                    }
+                    .await;
+                    tx.finish(result).await?;
+                    metrics::inc_janken_matches("resolved");
                 }
                 _ => break,
             }
@@ -93,8 +127,9 @@ impl JankenProcessService {
         Ok(())
     }
 
-    pub async fn run(&self) -> Result<(), ServiceError> {
-        loop {
+    // 止める合図があっても処理中のバッチは最後まで終わらせてから抜ける
+    pub async fn run(&self, mut shutdown: Shutdown) -> Result<(), ServiceError> {
+        while !shutdown.is_triggered() {
             let mut events = self
                 .janken_repo
                 .scan_by_status(JankenStatus::Ready, 100)
@@ -104,10 +139,18 @@ impl JankenProcessService {
             events.shuffle(&mut rand::thread_rng());
 
             self.process(events).await?;
+            // 更新が止まっていたらワーカーが詰まっている
+            metrics::set_janken_process_last_run(UnixTime::now().0);
 
             // 30秒くらい待つ
-            tokio::time::delay_for(tokio::time::Duration::from_secs(30)).await;
+            future::select(
+                tokio::time::delay_for(tokio::time::Duration::from_secs(30)),
+                shutdown.wait().boxed(),
+            )
+            .await;
         }
+
+        Ok(())
     }
 }
 
@@ -117,6 +160,8 @@ mod tests {
     use crate::domain::model::{GiftStatus, JankenEventId, JankenHand, UserId};
     use crate::infra::gift_repository_mock::GiftRepositoryMock;
     use crate::infra::janken_event_repository_mock::JankenEventRepositoryMock;
+    use crate::infra::point_transaction_repository_mock::PointTransactionRepositoryMock;
+    use crate::infra::unit_of_work_mock::UnitOfWorkMock;
     use crate::infra::user_repository_mock::UserRepositoryStub;
     use crate::unixtime::UnixTime;
 
@@ -125,8 +170,17 @@ mod tests {
         let janken_repo = Arc::new(JankenEventRepositoryMock::new(Vec::new()));
         let gift_repo = Arc::new(GiftRepositoryMock::new());
         let user_repo = Arc::new(UserRepositoryStub::new(Default::default()));
-        let service =
-            JankenProcessService::new(janken_repo.clone(), gift_repo.clone(), user_repo.clone());
+        let unit_of_work = Arc::new(UnitOfWorkMock::new(
+            gift_repo.clone(),
+            janken_repo.clone(),
+            Arc::new(PointTransactionRepositoryMock::new(0)),
+        ));
+        let service = JankenProcessService::new(
+            janken_repo.clone(),
+            user_repo.clone(),
+            unit_of_work.clone(),
+            DailyReset::default(),
+        );
 
         let event_rock = JankenEventId::new();
         let event_paper = JankenEventId::new();
@@ -224,6 +278,115 @@ mod tests {
         assert_eq!(statuses[1].1, user_winner.clone());
         assert_eq!(statuses[1].2, GiftStatus::Ready);
 
+        // タイムアウトと決着でそれぞれ1回ずつコミットされる
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_should_not_cross_daily_reset() {
+        let service = JankenProcessService::new(
+            Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+            Arc::new(UserRepositoryStub::new(Default::default())),
+            Arc::new(UnitOfWorkMock::new(
+                Arc::new(GiftRepositoryMock::new()),
+                Arc::new(JankenEventRepositoryMock::new(Vec::new())),
+                Arc::new(PointTransactionRepositoryMock::new(0)),
+            )),
+            DailyReset::new("Asia/Tokyo", 5).unwrap(),
+        );
+
+        // 1970-01-01 09:00 JSTから8時間後
+        assert_eq!(service.timeout_at(&UnixTime(0)), UnixTime(8 * 60 * 60));
+        // 1970-01-02 01:00 JSTからは8時間経つ前に5時で日付が変わる
+        assert_eq!(
+            service.timeout_at(&UnixTime(16 * 60 * 60)),
+            UnixTime(20 * 60 * 60)
+        );
+    }
+
+    #[tokio::test]
+    async fn bet_just_before_reset_should_time_out_as_walkover() -> Result<(), ServiceError> {
+        let gift_repo = Arc::new(GiftRepositoryMock::new());
+        let janken_repo = Arc::new(JankenEventRepositoryMock::new(Vec::new()));
+        let daily_reset = DailyReset::default();
+        let service = JankenProcessService::new(
+            janken_repo.clone(),
+            Arc::new(UserRepositoryStub::new(Default::default())),
+            Arc::new(UnitOfWorkMock::new(
+                gift_repo.clone(),
+                janken_repo.clone(),
+                Arc::new(PointTransactionRepositoryMock::new(0)),
+            )),
+            daily_reset,
+        );
+
+        // 直前に日付が変わった時刻の1分前に賭けた
+        let now = UnixTime::now();
+        let last_reset = daily_reset.start_of(daily_reset.date_of(&now));
+        service
+            .process(vec![JankenEvent {
+                id: JankenEventId::new(),
+                user_id: UserId::new(),
+                hand: JankenHand::Rock,
+                created_at: UnixTime(last_reset.0 - 60),
+                status: JankenStatus::Ready,
+                point: 5,
+                opponent_user_id: None,
+                opponent_user_screen_name: None,
+            }])
+            .await?;
+
+        let events = janken_repo.saved.lock().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].status, JankenStatus::Timeout);
+
+        // 日付が変わった時点でタイムアウトし, 8時間経ったときと同じく不戦勝になる
+        let gifts = gift_repo.created.lock().unwrap().clone();
+        assert_eq!(gifts.len(), 1);
+        assert_eq!(gifts[0].gift_type, GiftType::Point(10));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_should_stop_after_current_batch_on_shutdown() -> Result<(), ServiceError> {
+        let event = |hand| JankenEvent {
+            id: JankenEventId::new(),
+            user_id: UserId::new(),
+            hand,
+            created_at: UnixTime::now(),
+            status: JankenStatus::Ready,
+            point: 5,
+            opponent_user_id: None,
+            opponent_user_screen_name: None,
+        };
+        // 1回目のバッチのイベントを読んだところで止める合図が来る
+        let (trigger, shutdown) = crate::shutdown::channel();
+        let janken_repo = Arc::new(
+            JankenEventRepositoryMock::new(vec![event(JankenHand::Rock), event(JankenHand::Paper)])
+                .with_shutdown_on_scan(trigger),
+        );
+        let unit_of_work = Arc::new(UnitOfWorkMock::new(
+            Arc::new(GiftRepositoryMock::new()),
+            janken_repo.clone(),
+            Arc::new(PointTransactionRepositoryMock::new(0)),
+        ));
+        let service = JankenProcessService::new(
+            janken_repo.clone(),
+            Arc::new(UserRepositoryStub::new(Default::default())),
+            unit_of_work,
+            DailyReset::default(),
+        );
+
+        // 30秒待たずに抜ける
+        tokio::time::timeout(std::time::Duration::from_secs(1), service.run(shutdown))
+            .await
+            .expect("run should stop without waiting for the next batch")?;
+
+        assert_eq!(janken_repo.saved.lock().unwrap().len(), 2);
+
         Ok(())
     }
 }
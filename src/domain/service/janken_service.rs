@@ -1,27 +1,39 @@
-use crate::domain::interface::{IJankenEventRepository, IUserRepository};
-use crate::domain::model::{Authorization, JankenEvent, JankenHand, JankenStatus};
-use crate::wrapper::error::ServiceError;
+use crate::domain::interface::{IJankenEventRepository, IUnitOfWork, IUserRepository};
+use crate::domain::model::{
+    Authorization, JankenEvent, JankenHand, JankenStatus, PointTransaction, PointTransactionReason,
+};
+use crate::metrics;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
 pub struct JankenService {
     user_repo: Arc<dyn IUserRepository + Sync + Send>,
     janken_repo: Arc<dyn IJankenEventRepository + Sync + Send>,
+    unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct JankenCreateInput {
     hand: JankenHand,
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ListJankenEventResponse {
+    events: Vec<JankenEvent>,
+}
+
 impl JankenService {
     pub fn new(
         user_repo: Arc<dyn IUserRepository + Sync + Send>,
         janken_repo: Arc<dyn IJankenEventRepository + Sync + Send>,
+        unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
     ) -> Self {
         JankenService {
             user_repo,
             janken_repo,
+            unit_of_work,
         }
     }
 
@@ -31,35 +43,59 @@ impl JankenService {
         input: JankenCreateInput,
     ) -> Result<(), ServiceError> {
         let auth_user = auth.require_auth()?;
-        let mut user = self.user_repo.find_by_subject(&auth_user.subject).await?;
-
         let bet_point = 5;
 
-        // みょんポイントが5ポイント未満だと出来ない
-        if user.point < bet_point {
-            return Err(ServiceError::bad_request(failure::err_msg(
-                "You do not have enough myon point",
-            )));
-        }
+        // 残高と準備中のじゃんけんの確認から5ポイント払って参加するまでを1つのトランザクションで行う
+        // ユーザーの行をロックして, 同時に来たリクエストが両方とも確認を通り抜けないようにする
+        let tx = self.unit_of_work.begin().await?;
+        let result = async {
+            let user = tx
+                .user_repository()
+                .find_by_subject_for_update(&auth_user.subject)
+                .await?;
 
-        // 準備中のじゃんけんが残っていたら引けなくする
-        let events = self
-            .janken_repo
-            .find_by_user_id_status(&user.id, JankenStatus::Ready)
-            .await?;
-        if !events.is_empty() {
-            return Err(ServiceError::bad_request(failure::err_msg(
-                "Janken Rate Limit Exceeded",
-            )));
-        }
+            // みょんポイントが5ポイント未満だと出来ない
+            if user.point < bet_point {
+                return Err(ServiceError::bad_request(
+                    ErrorCode::NotEnoughPoint,
+                    failure::err_msg("You do not have enough myon point"),
+                )
+                .with_details(serde_json::json!({
+                    "required": bet_point,
+                    "current": user.point,
+                })));
+            }
+
+            // 準備中のじゃんけんが残っていたら引けなくする
+            let events = tx
+                .janken_repository()
+                .find_by_user_id_status(&user.id, JankenStatus::Ready)
+                .await?;
+            if !events.is_empty() {
+                return Err(ServiceError::too_many_requests(
+                    ErrorCode::JankenRateLimitExceeded,
+                    failure::err_msg("Janken Rate Limit Exceeded"),
+                ));
+            }
 
-        // ここトランザクション
-        // 5ポイント払って参加する
-        user.subtract_point(bet_point);
-        self.user_repo.save(user.clone()).await?;
+            let janken = JankenEvent::new(user.id.clone(), input.hand, bet_point);
+            tx.point_transaction_repository()
+                .apply(PointTransaction::new(
+                    user.id,
+                    PointTransactionReason::JankenBet,
+                    Some(janken.id.0.clone()),
+                    -(bet_point as i64),
+                ))
+                .await?;
+            tx.janken_repository().create(janken).await
+        }
+        .await;
+        tx.finish(result).await?;
 
-        let janken = JankenEvent::new(user.id, input.hand, bet_point);
-        self.janken_repo.create(janken).await?;
+        metrics::observe_point_delta(
+            &PointTransactionReason::JankenBet.to_string(),
+            -(bet_point as i64),
+        );
 
         Ok(())
     }
@@ -68,24 +104,32 @@ impl JankenService {
         &self,
         auth: Authorization,
         limit: i32,
-    ) -> Result<serde_json::Value, ServiceError> {
+    ) -> Result<ListJankenEventResponse, ServiceError> {
         let auth_user = auth.require_auth()?;
         let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
 
         let events = self.janken_repo.find_by_user_id(&user.id, limit).await?;
-        Ok(serde_json::json!({ "events": events }))
+        Ok(ListJankenEventResponse { events })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::model::{JankenEventId, User, UserId};
+    use crate::infra::gift_repository_mock::GiftRepositoryMock;
     use crate::infra::janken_event_repository_mock::JankenEventRepositoryMock;
+    use crate::infra::point_transaction_repository_mock::PointTransactionRepositoryMock;
+    use crate::infra::unit_of_work_mock::UnitOfWorkMock;
     use crate::infra::user_repository_mock::UserRepositoryStub;
+    use crate::wrapper::unixtime::UnixTime;
 
     #[tokio::test]
     async fn create_should_fail_if_previous_is_still_ready() {
-        let user_repo = Arc::new(UserRepositoryStub::new(Default::default()));
+        let user_repo = Arc::new(UserRepositoryStub::new(User {
+            point: 10,
+            ..Default::default()
+        }));
         let janken_repo = Arc::new(JankenEventRepositoryMock::new(vec![JankenEvent {
             id: Default::default(),
             user_id: Default::default(),
@@ -96,9 +140,18 @@ mod tests {
             opponent_user_id: None,
             opponent_user_screen_name: None,
         }]));
+        let unit_of_work = Arc::new(
+            UnitOfWorkMock::new(
+                Arc::new(GiftRepositoryMock::new()),
+                janken_repo.clone(),
+                Arc::new(PointTransactionRepositoryMock::new(0)),
+            )
+            .with_user_repository(user_repo.clone()),
+        );
         let service = JankenService {
             user_repo: user_repo.clone(),
             janken_repo: janken_repo.clone(),
+            unit_of_work: unit_of_work.clone(),
         };
 
         let err = service
@@ -110,6 +163,81 @@ mod tests {
             )
             .await
             .expect_err("expect error");
-        assert_eq!(err.status_code, http::StatusCode::BAD_REQUEST);
+        assert_eq!(err.status_code, http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 0);
+        assert_eq!(*unit_of_work.rolled_back.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_should_pay_and_register_in_one_transaction() -> Result<(), ServiceError> {
+        let user_repo = Arc::new(UserRepositoryStub::new(User {
+            point: 10,
+            ..Default::default()
+        }));
+        let janken_repo = Arc::new(JankenEventRepositoryMock::new(Vec::new()));
+        let point_transaction_repo = Arc::new(PointTransactionRepositoryMock::new(10));
+        let unit_of_work = Arc::new(
+            UnitOfWorkMock::new(
+                Arc::new(GiftRepositoryMock::new()),
+                janken_repo.clone(),
+                point_transaction_repo.clone(),
+            )
+            .with_user_repository(user_repo.clone()),
+        );
+        let service =
+            JankenService::new(user_repo.clone(), janken_repo.clone(), unit_of_work.clone());
+
+        service
+            .create(
+                Authorization::new(Ok(Default::default())),
+                JankenCreateInput {
+                    hand: JankenHand::Paper,
+                },
+            )
+            .await?;
+
+        let created = janken_repo.created.lock().unwrap().clone();
+        assert_eq!(created.len(), 1);
+
+        let applied = point_transaction_repo.applied.lock().unwrap().clone();
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].delta, -5);
+        assert_eq!(applied[0].source_event_id, Some(created[0].id.0.clone()));
+
+        assert_eq!(*unit_of_work.committed.lock().unwrap(), 1);
+        assert_eq!(*unit_of_work.rolled_back.lock().unwrap(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = ListJankenEventResponse {
+            events: vec![JankenEvent {
+                id: JankenEventId("janken-1".to_string()),
+                user_id: UserId("user-1".to_string()),
+                hand: JankenHand::Paper,
+                created_at: UnixTime(1600000000),
+                status: JankenStatus::Won,
+                point: 10,
+                opponent_user_id: Some(UserId("user-2".to_string())),
+                opponent_user_screen_name: Some("opponent".to_string()),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "events": [{
+                    "id": "janken-1",
+                    "user_id": "user-1",
+                    "hand": "paper",
+                    "created_at": 1600000000,
+                    "status": "won",
+                    "point": 10,
+                    "opponent_user_id": "user-2",
+                    "opponent_user_screen_name": "opponent",
+                }]
+            })
+        );
     }
 }
@@ -1,7 +1,10 @@
 use crate::domain::interface::{IPointEventRepository, IUserRepository};
-use crate::domain::model::PointEvent;
+use crate::domain::model::{PointEvent, UserId};
+use crate::shutdown::Shutdown;
+use crate::wrapper::daily_reset::DailyReset;
 use crate::wrapper::error::ServiceError;
 use crate::wrapper::unixtime::UnixTime;
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
@@ -9,9 +12,10 @@ use std::sync::Arc;
 pub struct PointProcessService {
     user_repo: Arc<dyn IUserRepository + Sync + Send>,
     point_repo: Arc<dyn IPointEventRepository + Sync + Send>,
+    daily_reset: DailyReset,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct StartProcessOutput {
     executed: bool,
 }
@@ -20,53 +24,211 @@ impl PointProcessService {
     pub fn new(
         user_repo: Arc<dyn IUserRepository + Sync + Send>,
         point_repo: Arc<dyn IPointEventRepository + Sync + Send>,
+        daily_reset: DailyReset,
     ) -> Self {
         PointProcessService {
             user_repo,
             point_repo,
+            daily_reset,
         }
     }
 
-    pub async fn run(&self) -> Result<(), ServiceError> {
+    // 今日すでに記録したユーザーは飛ばす, 記録したときはtrueを返す
+    async fn snapshot(&self, user_id: UserId, now: &UnixTime) -> Result<bool, ServiceError> {
+        match self.point_repo.find_by_id(&user_id).await {
+            Err(err) if err.status_code == http::StatusCode::NOT_FOUND => {
+                let user = self.user_repo.find_by_id(&user_id).await?;
+                self.point_repo
+                    .save(PointEvent::new(user.id, user.point))
+                    .await?;
+            }
+            // ランキングの集計は1日1回, 同じ日のうちに記録し直すと前日との差分が消えてしまう
+            Ok(event) if self.daily_reset.is_same_day(&event.updated_at, now) => return Ok(false),
+            Ok(mut event) => {
+                let user = self.user_repo.find_by_id(&user_id).await?;
+                event.update(user.point);
+                self.point_repo.save(event).await?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        Ok(true)
+    }
+
+    // 止める合図があったら, 書き込み中のユーザーを終えたところで抜ける
+    // 今日の記録がまだないユーザーだけを集計するので, 途中で止まっても次に実行したときに残りから続ける
+    // 記録したユーザーの数を返す
+    pub async fn run(&self, shutdown: Shutdown) -> Result<usize, ServiceError> {
+        let now = UnixTime::now();
         let user_ids = self.user_repo.list_id().await?;
-        for user_id in user_ids {
-            match self.point_repo.find_by_id(&user_id).await {
-                Err(err) if err.status_code == http::StatusCode::NOT_FOUND => {
-                    let user = self.user_repo.find_by_id(&user_id).await?;
-                    self.point_repo
-                        .save(PointEvent::new(user.id, user.point))
-                        .await?;
-                }
-                Ok(mut event) => {
-                    let user = self.user_repo.find_by_id(&user_id).await?;
-                    event.update(user.point);
-                    self.point_repo.save(event).await?;
-                }
-                Err(err) => return Err(err),
+        let total = user_ids.len();
+        let mut recorded = 0;
+        for (index, user_id) in user_ids.into_iter().enumerate() {
+            if shutdown.is_triggered() {
+                warn!(
+                    "Ranking batch stopped after {}/{} users, the rest will be recorded on the next run",
+                    index, total
+                );
+                break;
+            }
+
+            if self.snapshot(user_id, &now).await? {
+                recorded += 1;
             }
         }
 
-        Ok(())
+        Ok(recorded)
     }
 
     pub async fn start(&self) -> Result<StartProcessOutput, ServiceError> {
-        let user_id = self.user_repo.find_oldest_user().await?;
-        match self.point_repo.find_by_id(&user_id).await {
-            Ok(point) => {
-                // 23時間より短い間隔でリトライはしない
-                if (UnixTime::now().datetime_jst() - point.updated_at.datetime_jst()).num_hours()
-                    < 23
-                {
-                    return Ok(StartProcessOutput { executed: false });
-                }
+        let recorded = self.run(Shutdown::never()).await?;
+
+        Ok(StartProcessOutput {
+            executed: recorded > 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::User;
+    use crate::infra::{InMemoryPointEventRepository, InMemoryStore, InMemoryUserRepository};
+    use crate::shutdown::ShutdownTrigger;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    async fn service_with_users(count: usize) -> Result<PointProcessService, ServiceError> {
+        service_with_store(Arc::new(InMemoryStore::new()), count).await
+    }
+
+    async fn service_with_store(
+        store: Arc<InMemoryStore>,
+        count: usize,
+    ) -> Result<PointProcessService, ServiceError> {
+        let user_repo = Arc::new(InMemoryUserRepository::new(store.clone()));
+        for index in 0..count {
+            user_repo
+                .create(User {
+                    id: UserId::new(),
+                    subject: format!("auth0|{}", index),
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        Ok(PointProcessService::new(
+            user_repo,
+            Arc::new(InMemoryPointEventRepository::new(store)),
+            DailyReset::default(),
+        ))
+    }
+
+    async fn snapshot_count(service: &PointProcessService) -> Result<usize, ServiceError> {
+        let mut count = 0;
+        for user_id in service.user_repo.list_id().await? {
+            if service.point_repo.find_by_id(&user_id).await.is_ok() {
+                count += 1;
             }
-            // 404のときは最古のユーザーに記録がないので実行してしまう(サービスで初めて実行するときしかこれにはならないはず…)
-            Err(err) if err.status_code == http::StatusCode::NOT_FOUND => (),
-            Err(err) => return Err(err),
         }
 
-        self.run().await?;
+        Ok(count)
+    }
+
+    #[tokio::test]
+    async fn run_should_stop_on_shutdown() -> Result<(), ServiceError> {
+        let service = service_with_users(3).await?;
+        service.run(Shutdown::never()).await?;
+        assert_eq!(snapshot_count(&service).await?, 3);
+
+        // 合図が出ていたら次のユーザーには進まない
+        let service = service_with_users(3).await?;
+        let (trigger, shutdown) = crate::shutdown::channel();
+        trigger.trigger();
+        service.run(shutdown).await?;
+        assert_eq!(snapshot_count(&service).await?, 0);
+
+        Ok(())
+    }
+
+    // 指定した回数だけ書き込んだら止める合図を出す
+    struct TriggerAfterSaves {
+        inner: InMemoryPointEventRepository,
+        trigger: ShutdownTrigger,
+        remaining: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl IPointEventRepository for TriggerAfterSaves {
+        async fn find_by_id(&self, user_id: &UserId) -> Result<PointEvent, ServiceError> {
+            self.inner.find_by_id(user_id).await
+        }
+
+        async fn save(&self, event: PointEvent) -> Result<(), ServiceError> {
+            self.inner.save(event).await?;
+
+            let mut remaining = self.remaining.lock().unwrap();
+            *remaining = remaining.saturating_sub(1);
+            if *remaining == 0 {
+                self.trigger.trigger();
+            }
+
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn start_should_resume_interrupted_batch() -> Result<(), ServiceError> {
+        let store = Arc::new(InMemoryStore::new());
+        let service = service_with_store(store.clone(), 5).await?;
+        let (trigger, shutdown) = crate::shutdown::channel();
+        let interrupted = PointProcessService::new(
+            service.user_repo.clone(),
+            Arc::new(TriggerAfterSaves {
+                inner: InMemoryPointEventRepository::new(store),
+                trigger,
+                remaining: Mutex::new(2),
+            }),
+            DailyReset::default(),
+        );
+
+        // 2人目を書き込んだところで止まる
+        assert_eq!(interrupted.run(shutdown).await?, 2);
+        assert_eq!(snapshot_count(&service).await?, 2);
+
+        // 続きから残りの3人だけを記録する
+        let output = service.start().await?;
+        assert!(output.executed);
+        assert_eq!(snapshot_count(&service).await?, 5);
+
+        // 全員の記録が済んだら同じ日のうちは何もしない
+        let output = service.start().await?;
+        assert!(!output.executed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn run_should_not_record_twice_a_day() -> Result<(), ServiceError> {
+        let service = service_with_users(1).await?;
+        assert_eq!(service.run(Shutdown::never()).await?, 1);
+        assert_eq!(service.run(Shutdown::never()).await?, 0);
+
+        let user_id = service.user_repo.list_id().await?.remove(0);
+        assert_eq!(
+            service.point_repo.find_by_id(&user_id).await?.previous,
+            None
+        );
+
+        Ok(())
+    }
 
-        Ok(StartProcessOutput { executed: true })
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = StartProcessOutput { executed: true };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({ "executed": true })
+        );
     }
 }
@@ -30,3 +30,38 @@ impl PointRankingService {
         self.ranking_repo.list_top_point_diffs(10).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::{User, UserId};
+    use crate::wrapper::unixtime::UnixTime;
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let user = User {
+            id: UserId("user-1".to_string()),
+            screen_name: Some("myuon".to_string()),
+            display_name: "みょん".to_string(),
+            point: 120,
+            created_at: UnixTime(1600000000),
+            subject: "auth0|me".to_string(),
+            picture_url: None,
+            last_tried_daily_gacha: UnixTime(0),
+            daily_streak: Default::default(),
+        };
+        let records = vec![PointDiffRankingRecord::new(user, 120, 20)];
+        assert_eq!(
+            serde_json::to_value(records).unwrap(),
+            serde_json::json!([{
+                "user_id": "user-1",
+                "screen_name": "myuon",
+                "display_name": "みょん",
+                "point": 120,
+                "picture_url": null,
+                "current": 120,
+                "diff": 20,
+            }])
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::domain::interface::{IPointTransactionRepository, IUserRepository};
+use crate::domain::model::{Authorization, PointTransaction};
+use crate::wrapper::error::ServiceError;
+use schemars::JsonSchema;
+use serde::*;
+use std::sync::Arc;
+
+pub struct PointTransactionService {
+    user_repo: Arc<dyn IUserRepository + Sync + Send>,
+    point_transaction_repo: Arc<dyn IPointTransactionRepository + Sync + Send>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ListPointTransactionResponse {
+    data: Vec<PointTransaction>,
+}
+
+impl PointTransactionService {
+    pub fn new(
+        user_repo: Arc<dyn IUserRepository + Sync + Send>,
+        point_transaction_repo: Arc<dyn IPointTransactionRepository + Sync + Send>,
+    ) -> Self {
+        PointTransactionService {
+            user_repo,
+            point_transaction_repo,
+        }
+    }
+
+    pub async fn list_by_user(
+        &self,
+        auth: Authorization,
+        limit: i32,
+    ) -> Result<ListPointTransactionResponse, ServiceError> {
+        let auth_user = auth.require_auth()?;
+        let user = self.user_repo.find_by_subject(&auth_user.subject).await?;
+
+        let transactions = self
+            .point_transaction_repo
+            .find_by_user_id(&user.id, limit)
+            .await?;
+
+        Ok(ListPointTransactionResponse { data: transactions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::{PointTransactionId, PointTransactionReason, UserId};
+    use crate::wrapper::unixtime::UnixTime;
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = ListPointTransactionResponse {
+            data: vec![PointTransaction {
+                id: PointTransactionId("transaction-1".to_string()),
+                user_id: UserId("user-1".to_string()),
+                reason: PointTransactionReason::JankenBet,
+                source_event_id: Some("janken-1".to_string()),
+                delta: -10,
+                balance_after: 90,
+                created_at: UnixTime(1600000000),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "data": [{
+                    "id": "transaction-1",
+                    "user_id": "user-1",
+                    "reason": "janken_bet",
+                    "source_event_id": "janken-1",
+                    "delta": -10,
+                    "balance_after": 90,
+                    "created_at": 1600000000,
+                }]
+            })
+        );
+    }
+}
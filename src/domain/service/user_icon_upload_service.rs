@@ -2,6 +2,8 @@ use crate::domain::interface::{IUserIconUploader, IUserRepository};
 use crate::domain::model::Authorization;
 use crate::wrapper::base64::Base64;
 use crate::wrapper::error::ServiceError;
+use crate::wrapper::url::Url;
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
@@ -10,11 +12,17 @@ pub struct UserIconUploadService {
     user_icon_uploader: Arc<dyn IUserIconUploader + Sync + Send>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct UploadInput {
     data: Base64,
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct UploadIconResponse {
+    // アップロードした画像のURL, picture_urlにも設定される
+    url: Url,
+}
+
 impl UserIconUploadService {
     pub fn new(
         user_repo: Arc<dyn IUserRepository + Sync + Send>,
@@ -30,7 +38,7 @@ impl UserIconUploadService {
         &self,
         auth: Authorization,
         input: UploadInput,
-    ) -> Result<serde_json::Value, ServiceError> {
+    ) -> Result<UploadIconResponse, ServiceError> {
         let auth_user = auth.require_auth()?;
 
         let mut user = self.user_repo.find_by_subject(&auth_user.subject).await?;
@@ -41,6 +49,22 @@ impl UserIconUploadService {
         user.picture_url = Some(url.clone());
         self.user_repo.save(user).await?;
 
-        Ok(serde_json::json!({ "url": url }))
+        Ok(UploadIconResponse { url })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = UploadIconResponse {
+            url: Url("https://example.com/icon.png".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({ "url": "https://example.com/icon.png" })
+        );
     }
 }
@@ -1,7 +1,8 @@
 use crate::domain::interface::IUserRepository;
 use crate::domain::model::{Authorization, Role, User};
-use crate::wrapper::error::ServiceError;
+use crate::wrapper::error::{ErrorCode, ServiceError};
 use crate::wrapper::url::Url;
+use schemars::JsonSchema;
 use serde::*;
 use std::sync::Arc;
 
@@ -15,14 +16,14 @@ pub struct UserCreateInput {
     display_name: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct UpdateMeInput {
     picture_url: String,
     screen_name: String,
     display_name: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct UserProfile {
     #[serde(flatten)]
     user: User,
@@ -68,9 +69,10 @@ impl UserMeService {
 
         let r = regex::Regex::new(r"^[a-zA-Z0-9_]{3,}$").unwrap();
         if !r.is_match(&input.screen_name) {
-            return Err(ServiceError::bad_request(failure::err_msg(
-                "screen_name does not match the policy",
-            )));
+            return Err(ServiceError::bad_request(
+                ErrorCode::InvalidScreenName,
+                failure::err_msg("screen_name does not match the policy"),
+            ));
         }
 
         let mut user = self.user_repo.find_by_subject(&auth_user.subject).await?;
@@ -84,3 +86,42 @@ impl UserMeService {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::UserId;
+    use crate::wrapper::unixtime::UnixTime;
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let profile = UserProfile {
+            user: User {
+                id: UserId("user-1".to_string()),
+                screen_name: None,
+                display_name: "みょん".to_string(),
+                point: 100,
+                created_at: UnixTime(1600000000),
+                subject: "auth0|me".to_string(),
+                picture_url: None,
+                last_tried_daily_gacha: UnixTime(0),
+                daily_streak: Default::default(),
+            },
+            roles: vec![Role::Admin],
+        };
+        assert_eq!(
+            serde_json::to_value(profile).unwrap(),
+            serde_json::json!({
+                "id": "user-1",
+                "screen_name": null,
+                "display_name": "みょん",
+                "point": 100,
+                "created_at": 1600000000,
+                "subject": "auth0|me",
+                "picture_url": null,
+                "last_tried_daily_gacha": 0,
+                "roles": ["admin"],
+            })
+        );
+    }
+}
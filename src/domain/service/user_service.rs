@@ -1,8 +1,15 @@
 use crate::domain::interface::IUserRepository;
 use crate::domain::model::{Authorization, User};
 use crate::wrapper::error::ServiceError;
+use schemars::JsonSchema;
+use serde::*;
 use std::sync::Arc;
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ScreenNameAvailabilityResponse {
+    availability: bool,
+}
+
 pub struct UserService {
     user_repository: Arc<dyn IUserRepository + Sync + Send>,
 }
@@ -24,7 +31,7 @@ impl UserService {
         &self,
         auth: Authorization,
         screen_name: String,
-    ) -> Result<serde_json::Value, ServiceError> {
+    ) -> Result<ScreenNameAvailabilityResponse, ServiceError> {
         auth.require_auth()?;
 
         let available = match self.user_repository.find_by_screen_name(&screen_name).await {
@@ -33,8 +40,130 @@ impl UserService {
             Err(err) => return Err(err),
         };
 
-        Ok(serde_json::json!({
-            "availability": available,
-        }))
+        Ok(ScreenNameAvailabilityResponse {
+            availability: available,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::{DailyStreak, UserId};
+    use crate::infra::{InMemoryStore, InMemoryUserRepository, INJECTION_PAYLOADS};
+    use crate::wrapper::unixtime::UnixTime;
+    use crate::wrapper::url::Url;
+
+    // 渡したscreen_nameを持つユーザーを作ったリポジトリ, screen_nameは完全に一致するものだけを探す
+    async fn user_repo_with(screen_names: &[&str]) -> Arc<InMemoryUserRepository> {
+        let user_repo = Arc::new(InMemoryUserRepository::new(Arc::new(InMemoryStore::new())));
+        for screen_name in screen_names {
+            user_repo
+                .create(User {
+                    id: UserId::new(),
+                    subject: UserId::new().0,
+                    screen_name: Some(screen_name.to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        user_repo
+    }
+
+    #[tokio::test]
+    async fn find_by_screen_name_should_not_match_by_payload() {
+        let service = UserService::new(user_repo_with(&["myuon"]).await);
+
+        for payload in INJECTION_PAYLOADS {
+            let err = service
+                .find_by_screen_name(payload.to_string())
+                .await
+                .expect_err("expect error");
+            assert_eq!(err.status_code, http::StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn find_by_screen_name_should_find_user_with_quotes() -> Result<(), ServiceError> {
+        let service = UserService::new(user_repo_with(INJECTION_PAYLOADS).await);
+
+        for payload in INJECTION_PAYLOADS {
+            let user = service.find_by_screen_name(payload.to_string()).await?;
+            assert_eq!(user.screen_name, Some(payload.to_string()));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_screen_name_available_with_payload() -> Result<(), ServiceError> {
+        let service = UserService::new(user_repo_with(&["myuon", "it's_me\\"]).await);
+
+        for payload in INJECTION_PAYLOADS {
+            let result = service
+                .is_screen_name_available(
+                    Authorization::new(Ok(Default::default())),
+                    payload.to_string(),
+                )
+                .await?;
+            assert_eq!(
+                serde_json::to_value(result).unwrap(),
+                serde_json::json!({ "availability": true })
+            );
+        }
+
+        let result = service
+            .is_screen_name_available(
+                Authorization::new(Ok(Default::default())),
+                "it's_me\\".to_string(),
+            )
+            .await?;
+        assert_eq!(
+            serde_json::to_value(result).unwrap(),
+            serde_json::json!({ "availability": false })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn responses_should_keep_json_shape() {
+        let response = ScreenNameAvailabilityResponse { availability: true };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({ "availability": true })
+        );
+
+        let user = User {
+            id: UserId("user-1".to_string()),
+            screen_name: Some("myuon".to_string()),
+            display_name: "みょん".to_string(),
+            point: 100,
+            created_at: UnixTime(1600000000),
+            subject: "auth0|me".to_string(),
+            picture_url: Some(Url("https://example.com/icon.png".to_string())),
+            last_tried_daily_gacha: UnixTime(1600000000),
+            // 他のユーザーからは見えない
+            daily_streak: DailyStreak {
+                current: 3,
+                longest: 10,
+                last_date: Some("2020-09-13".to_string()),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(user).unwrap(),
+            serde_json::json!({
+                "id": "user-1",
+                "screen_name": "myuon",
+                "display_name": "みょん",
+                "point": 100,
+                "created_at": 1600000000,
+                "subject": "auth0|me",
+                "picture_url": "https://example.com/icon.png",
+                "last_tried_daily_gacha": 1600000000,
+            })
+        );
     }
 }
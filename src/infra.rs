@@ -4,6 +4,9 @@ pub use user_repository::*;
 mod db_connector;
 pub use db_connector::*;
 
+mod schema_migration;
+pub use schema_migration::*;
+
 mod jwt_handler;
 pub use jwt_handler::*;
 
@@ -13,6 +16,12 @@ pub use dynamo_client::*;
 mod gacha_event_repository;
 pub use gacha_event_repository::*;
 
+mod gacha_state_repository;
+pub use gacha_state_repository::*;
+
+mod pending_gacha_event_repository;
+pub use pending_gacha_event_repository::*;
+
 mod gift_repository;
 pub use gift_repository::*;
 
@@ -30,3 +39,24 @@ pub use point_repository::*;
 
 mod ranking_repository;
 pub use ranking_repository::*;
+
+mod point_transaction_repository;
+pub use point_transaction_repository::*;
+
+mod unit_of_work;
+pub use unit_of_work::*;
+
+mod in_memory_repository;
+pub use in_memory_repository::*;
+
+mod dev_token_signer;
+pub use dev_token_signer::*;
+
+mod health_check;
+pub use health_check::*;
+
+mod instrumented_repository;
+pub use instrumented_repository::*;
+
+mod rate_limit_store;
+pub use rate_limit_store::*;
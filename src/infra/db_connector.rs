@@ -1,22 +1,227 @@
-use crate::wrapper::error::ServiceError;
-use debil_mysql::DebilConn;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use debil::{HasNotFound, QueryBuilder, SQLConn, SQLMapper, SQLTable};
+use debil_mysql::{DebilConn, MySQLValue};
+use std::ops::{Deref, DerefMut};
 
-pub struct ConnPool(pub mysql_async::Pool);
+// main.rsのmigrateで作るテーブルの定義を変えたり, 一度だけ行うデータの移行を足したら上げる
+pub const SCHEMA_VERSION: u32 = 8;
+
+enum Connection {
+    Pool(mysql_async::Pool),
+    // トランザクション中は1つのコネクションを全てのリポジトリで使い回す
+    Transaction(tokio::sync::Mutex<DebilConn>),
+}
+
+pub struct ConnPool {
+    connection: Connection,
+}
+
+// リポジトリから見たコネクション
+// トランザクション中のコネクションを借りている場合は、トランザクションの開始・終了を外側に任せる
+pub enum ConnGuard<'a> {
+    Owned(DebilConn),
+    Shared(tokio::sync::MutexGuard<'a, DebilConn>),
+}
+
+impl<'a> Deref for ConnGuard<'a> {
+    type Target = DebilConn;
+
+    fn deref(&self) -> &DebilConn {
+        match self {
+            ConnGuard::Owned(conn) => conn,
+            ConnGuard::Shared(conn) => conn,
+        }
+    }
+}
+
+impl<'a> DerefMut for ConnGuard<'a> {
+    fn deref_mut(&mut self) -> &mut DebilConn {
+        match self {
+            ConnGuard::Owned(conn) => conn,
+            ConnGuard::Shared(conn) => conn,
+        }
+    }
+}
+
+// load_with2_paramsで実行するSELECT文, 条件中の :name はそのまま残る
+pub fn select_query<T: SQLTable<ValueType = MySQLValue>>(builder: QueryBuilder) -> String {
+    let table_name = debil::table_name::<T>();
+
+    builder
+        .table(table_name.clone())
+        .append_selects(
+            debil::schema_of::<T>()
+                .iter()
+                .map(|(k, _, _)| format!("{}.{}", table_name, k))
+                .collect::<Vec<_>>(),
+        )
+        .build()
+}
+
+// クォートやバックスラッシュを含み, クエリに埋め込むと構造が変わってしまう値
+#[cfg(test)]
+pub const INJECTION_PAYLOADS: &[&str] = &[
+    "' OR '1'='1",
+    "' OR 1=1 -- ",
+    "\\' OR 1=1 #",
+    "myuon\\",
+    "\"; DROP TABLE user; --",
+    "myuon' AND SLEEP(5) AND '",
+];
+
+// valueがqueryに埋め込まれず, エスケープや加工をされずにnameのパラメータとして渡ることを確かめる
+#[cfg(test)]
+pub fn assert_bound<K: AsRef<str>>(
+    query: &str,
+    params: &[(K, MySQLValue)],
+    name: &str,
+    value: &str,
+) {
+    assert!(!query.contains(value), "{} contains {}", query, value);
+
+    let (_, bound) = params
+        .iter()
+        .find(|(k, _)| k.as_ref() == name)
+        .unwrap_or_else(|| panic!("{} is not bound", name));
+    assert_eq!(bound.0, mysql_async::Value::from(value.to_string()));
+}
+
+impl<'a> ConnGuard<'a> {
+    pub async fn start_transaction(&mut self) -> Result<(), debil_mysql::Error> {
+        match self {
+            ConnGuard::Owned(conn) => conn.start_transaction().await,
+            ConnGuard::Shared(_) => Ok(()),
+        }
+    }
+
+    pub async fn commit(&mut self) -> Result<(), debil_mysql::Error> {
+        match self {
+            ConnGuard::Owned(conn) => conn.commit().await,
+            ConnGuard::Shared(_) => Ok(()),
+        }
+    }
+
+    pub async fn rollback(&mut self) -> Result<(), debil_mysql::Error> {
+        match self {
+            ConnGuard::Owned(conn) => conn.rollback().await,
+            ConnGuard::Shared(_) => Ok(()),
+        }
+    }
+
+    // load_with2と同じクエリを組み立て、条件中の :name にparamsの値を束縛して実行する
+    // ユーザーが入力した値は必ずこちらを経由させ、クエリの文字列に直接埋め込まない
+    pub async fn load_with2_params<
+        T: SQLTable<ValueType = MySQLValue>,
+        U: SQLMapper<ValueType = MySQLValue> + Sync + Send,
+    >(
+        &mut self,
+        builder: QueryBuilder,
+        params: Vec<(&str, MySQLValue)>,
+    ) -> Result<Vec<U>, debil_mysql::Error> {
+        self.sql_query::<U>(
+            select_query::<T>(builder),
+            debil::Params::<MySQLValue>(
+                params
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+            ),
+        )
+        .await
+    }
+
+    pub async fn load_with_params<T: SQLTable<ValueType = MySQLValue> + Sync + Send>(
+        &mut self,
+        builder: QueryBuilder,
+        params: Vec<(&str, MySQLValue)>,
+    ) -> Result<Vec<T>, debil_mysql::Error> {
+        self.load_with2_params::<T, T>(builder, params).await
+    }
+
+    pub async fn first_with_params<T: SQLTable<ValueType = MySQLValue> + Sync + Send>(
+        &mut self,
+        builder: QueryBuilder,
+        params: Vec<(&str, MySQLValue)>,
+    ) -> Result<T, debil_mysql::Error> {
+        self.load_with_params::<T>(builder.limit(1), params)
+            .await
+            .and_then(|mut vs| vs.pop().ok_or_else(HasNotFound::not_found))
+    }
+}
 
 impl ConnPool {
     pub fn new(db_url: &str) -> Result<Self, ServiceError> {
-        let pool = ConnPool(mysql_async::Pool::from_url(db_url).map_err(|err| {
-            ServiceError::bad_request(failure::Error::from_boxed_compat(Box::new(err)))
-        })?);
+        let pool = mysql_async::Pool::from_url(db_url).map_err(|err| {
+            ServiceError::bad_request(
+                ErrorCode::InvalidConfiguration,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        })?;
 
-        Ok(pool)
+        Ok(ConnPool {
+            connection: Connection::Pool(pool),
+        })
     }
 
-    pub async fn get_conn(&self) -> Result<DebilConn, ServiceError> {
-        let conn = self.0.get_conn().await.map_err(|err| {
-            ServiceError::internal_server_error(failure::Error::from_boxed_compat(Box::new(err)))
-        })?;
+    pub async fn get_conn(&self) -> Result<ConnGuard<'_>, ServiceError> {
+        match &self.connection {
+            Connection::Pool(pool) => {
+                let conn = pool.get_conn().await.map_err(|err| {
+                    ServiceError::service_unavailable(
+                        ErrorCode::DatabaseUnavailable,
+                        failure::Error::from_boxed_compat(Box::new(err)),
+                    )
+                })?;
+
+                Ok(ConnGuard::Owned(DebilConn::from_conn(conn)))
+            }
+            Connection::Transaction(conn) => Ok(ConnGuard::Shared(conn.lock().await)),
+        }
+    }
+
+    // 1つのコネクションでトランザクションを開始し、そのコネクションだけを使うConnPoolを返す
+    pub async fn begin(&self) -> Result<ConnPool, ServiceError> {
+        let mut conn = match self.get_conn().await? {
+            ConnGuard::Owned(conn) => conn,
+            ConnGuard::Shared(_) => {
+                return Err(ServiceError::internal_server_error(
+                    ErrorCode::DatabaseError,
+                    failure::err_msg("nested transaction is not supported"),
+                ))
+            }
+        };
+        conn.start_transaction().await?;
+
+        Ok(ConnPool {
+            connection: Connection::Transaction(tokio::sync::Mutex::new(conn)),
+        })
+    }
+
+    pub async fn commit(&self) -> Result<(), ServiceError> {
+        match &self.connection {
+            Connection::Transaction(conn) => conn.lock().await.commit().await?,
+            Connection::Pool(_) => {
+                return Err(ServiceError::internal_server_error(
+                    ErrorCode::DatabaseError,
+                    failure::err_msg("no transaction to commit"),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn rollback(&self) -> Result<(), ServiceError> {
+        match &self.connection {
+            Connection::Transaction(conn) => conn.lock().await.rollback().await?,
+            Connection::Pool(_) => {
+                return Err(ServiceError::internal_server_error(
+                    ErrorCode::DatabaseError,
+                    failure::err_msg("no transaction to rollback"),
+                ))
+            }
+        }
 
-        Ok(DebilConn::from_conn(conn))
+        Ok(())
     }
 }
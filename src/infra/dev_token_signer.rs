@@ -0,0 +1,171 @@
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use ring::signature::KeyPair;
+use std::sync::Arc;
+
+const DEV_KEY_ID: &str = "dev";
+
+// 開発用にIdPを使わずにトークンを発行する
+// 共有シークレット(HS256)か手元のRSA秘密鍵(RS256)で署名する
+pub struct DevTokenSigner {
+    secret: biscuit::jws::Secret,
+    algorithm: biscuit::jwa::SignatureAlgorithm,
+    public_key: Arc<biscuit::jwk::JWKSet<biscuit::Empty>>,
+}
+
+impl DevTokenSigner {
+    pub fn from_secret(secret: &str) -> Result<Self, ServiceError> {
+        let public_key = DevTokenSigner::jwks(serde_json::json!({
+            "kty": "oct",
+            "alg": "HS256",
+            "kid": DEV_KEY_ID,
+            "k": base64::encode_config(secret, base64::URL_SAFE_NO_PAD),
+        }))?;
+
+        Ok(DevTokenSigner {
+            secret: biscuit::jws::Secret::bytes_from_str(secret),
+            algorithm: biscuit::jwa::SignatureAlgorithm::HS256,
+            public_key,
+        })
+    }
+
+    // DER形式のRSA秘密鍵を読む
+    pub fn from_rsa_key_file(path: &str) -> Result<Self, ServiceError> {
+        let secret = biscuit::jws::Secret::rsa_keypair_from_file(path).map_err(|err| {
+            ServiceError::bad_request(
+                ErrorCode::InvalidConfiguration,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        })?;
+
+        // 検証用の公開鍵は秘密鍵から作る
+        let public_key = match &secret {
+            biscuit::jws::Secret::RsaKeyPair(key_pair) => {
+                let public_key = key_pair.public_key();
+                DevTokenSigner::jwks(serde_json::json!({
+                    "kty": "RSA",
+                    "alg": "RS256",
+                    "use": "sig",
+                    "kid": DEV_KEY_ID,
+                    "n": base64::encode_config(
+                        public_key.modulus().big_endian_without_leading_zero(),
+                        base64::URL_SAFE_NO_PAD,
+                    ),
+                    "e": base64::encode_config(
+                        public_key.exponent().big_endian_without_leading_zero(),
+                        base64::URL_SAFE_NO_PAD,
+                    ),
+                }))?
+            }
+            _ => unreachable!(),
+        };
+
+        Ok(DevTokenSigner {
+            secret,
+            algorithm: biscuit::jwa::SignatureAlgorithm::RS256,
+            public_key,
+        })
+    }
+
+    fn jwks(
+        key: serde_json::Value,
+    ) -> Result<Arc<biscuit::jwk::JWKSet<biscuit::Empty>>, ServiceError> {
+        Ok(Arc::new(serde_json::from_value(serde_json::json!({
+            "keys": [key],
+        }))?))
+    }
+
+    // JWTHandlerに渡す検証用の鍵
+    pub fn public_key(&self) -> Arc<biscuit::jwk::JWKSet<biscuit::Empty>> {
+        self.public_key.clone()
+    }
+
+    pub fn sign(
+        &self,
+        subject: &str,
+        roles: &[String],
+        expires_in: chrono::Duration,
+    ) -> Result<String, ServiceError> {
+        let claims = biscuit::ClaimsSet::<serde_json::Value> {
+            registered: biscuit::RegisteredClaims {
+                subject: Some(std::str::FromStr::from_str(subject)?),
+                issued_at: Some(From::from(chrono::Utc::now())),
+                expiry: Some(From::from(chrono::Utc::now() + expires_in)),
+                ..Default::default()
+            },
+            private: serde_json::json!({
+                "https://jitome.ramda.io/roles": roles,
+            }),
+        };
+
+        let token = biscuit::JWT::new_decoded(
+            From::from(biscuit::jws::RegisteredHeader {
+                algorithm: self.algorithm,
+                key_id: Some(DEV_KEY_ID.to_string()),
+                ..Default::default()
+            }),
+            claims,
+        )
+        .into_encoded(&self.secret)
+        .map_err(|err| {
+            ServiceError::internal_server_error(
+                ErrorCode::InternalError,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        })?;
+
+        Ok(token.unwrap_encoded().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::Role;
+    use crate::infra::JWTHandler;
+
+    async fn assert_roundtrip(signer: DevTokenSigner) -> Result<(), ServiceError> {
+        let token = signer.sign(
+            "auth0|dev",
+            &["admin".to_string()],
+            chrono::Duration::hours(1),
+        )?;
+        let user = JWTHandler::new(signer.public_key())
+            .authorize(&format!("Bearer {}", token))
+            .await?;
+        assert_eq!(user.subject, "auth0|dev");
+        assert!(user.roles == vec![Role::Admin]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hs256_token_should_be_accepted() -> Result<(), ServiceError> {
+        assert_roundtrip(DevTokenSigner::from_secret("dev-secret")?).await
+    }
+
+    #[tokio::test]
+    async fn rs256_token_should_be_accepted() -> Result<(), ServiceError> {
+        assert_roundtrip(DevTokenSigner::from_rsa_key_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/jwt/private_key.der"
+        ))?)
+        .await
+    }
+
+    #[tokio::test]
+    async fn token_signed_with_other_secret_should_be_rejected() -> Result<(), ServiceError> {
+        let token = DevTokenSigner::from_secret("other-secret")?.sign(
+            "auth0|dev",
+            &[],
+            chrono::Duration::hours(1),
+        )?;
+        let err = JWTHandler::new(DevTokenSigner::from_secret("dev-secret")?.public_key())
+            .authorize(&format!("Bearer {}", token))
+            .await
+            .err()
+            .expect("expect error");
+        assert_eq!(err.status_code, http::StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::wrapper::error::ServiceError;
+use crate::wrapper::error::{ErrorCode, ServiceError};
 use debil::SQLTable;
 use debil_dynamodb::{into_item, DynamoType};
 use rusoto_dynamodb::DynamoDb;
@@ -60,18 +60,18 @@ impl DynamoClient {
             })
             .await?;
 
-        let items = body.items.ok_or(ServiceError::not_found(failure::err_msg(
-            "record not found",
-        )))?;
+        let items = body.items.ok_or(ServiceError::not_found(
+            ErrorCode::NotFound,
+            failure::err_msg("record not found"),
+        ))?;
 
         Ok(debil_dynamodb::from_items(items))
     }
 
-    pub async fn create<T: SQLTable<ValueType = DynamoType> + Send + 'static>(
+    pub async fn save<T: SQLTable<ValueType = DynamoType> + Send + 'static>(
         &self,
         table_name: String,
         item: T,
-        pk_name: String,
     ) -> Result<(), ServiceError> {
         let client = self.client.clone();
 
@@ -79,7 +79,6 @@ impl DynamoClient {
             .put_item(rusoto_dynamodb::PutItemInput {
                 table_name,
                 item: into_item(item),
-                condition_expression: Some(format!("attribute_not_exists({})", pk_name)),
                 ..Default::default()
             })
             .await?;
@@ -87,19 +86,10 @@ impl DynamoClient {
         Ok(())
     }
 
-    pub async fn save<T: SQLTable<ValueType = DynamoType> + Send + 'static>(
-        &self,
-        table_name: String,
-        item: T,
-    ) -> Result<(), ServiceError> {
-        let client = self.client.clone();
-
-        client
-            .put_item(rusoto_dynamodb::PutItemInput {
-                table_name,
-                item: into_item(item),
-                ..Default::default()
-            })
+    // テーブルが存在して読めるかどうか
+    pub async fn describe_table(&self, table_name: String) -> Result<(), ServiceError> {
+        self.client
+            .describe_table(rusoto_dynamodb::DescribeTableInput { table_name })
             .await?;
 
         Ok(())
@@ -2,24 +2,88 @@ use crate::domain::interface::IGachaEventRepository;
 use crate::domain::model::{GachaEvent, GachaEventId, GachaType, UserId};
 use crate::infra::{DynamoClient, QueryInput, ScanOrder};
 use crate::unixtime::UnixTime;
-use crate::wrapper::error::ServiceError;
+use crate::wrapper::error::{ErrorCode, ServiceError};
 use async_trait::async_trait;
 use debil::*;
 use debil_dynamodb::Attribute;
+use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::sync::Arc;
 
-#[derive(Clone, Table)]
-#[sql(
-    table_name = "gacha_events",
-    sql_type = "debil_dynamodb::DynamoType",
-    primary_key = "id"
-)]
+#[derive(Clone)]
 pub struct GachaEventRecord {
     id: String,
     user_id: String,
     gacha_type: String,
     created_at: i64,
     gsi_user_id_gacha_type: String,
+    // GachaResultのJSON
+    result: Option<String>,
+    pity_count: Option<u32>,
+}
+
+// 後から足したresultを持たないアイテムも読めるように, deriveせずに実装する
+impl SQLMapper for GachaEventRecord {
+    type ValueType = debil_dynamodb::DynamoType;
+
+    fn map_from_sql(values: HashMap<String, Self::ValueType>) -> Self {
+        let get = |name: &str| values.get(name).unwrap().clone();
+
+        GachaEventRecord {
+            id: SQLValue::deserialize(get("id")),
+            user_id: SQLValue::deserialize(get("user_id")),
+            gacha_type: SQLValue::deserialize(get("gacha_type")),
+            created_at: SQLValue::deserialize(get("created_at")),
+            gsi_user_id_gacha_type: SQLValue::deserialize(get("gsi_user_id_gacha_type")),
+            result: values
+                .get("result")
+                .cloned()
+                .and_then(SQLValue::deserialize),
+            pity_count: values
+                .get("pity_count")
+                .cloned()
+                .and_then(SQLValue::deserialize),
+        }
+    }
+}
+
+impl SQLTable for GachaEventRecord {
+    fn table_name(_: PhantomData<Self>) -> String {
+        "gacha_events".to_string()
+    }
+
+    // テーブルはDynamoDB側で作るので使わない
+    fn schema_of(_: PhantomData<Self>) -> Vec<(String, String, FieldAttribute)> {
+        Vec::new()
+    }
+
+    fn primary_key_columns(_: PhantomData<Self>) -> Vec<String> {
+        vec!["id".to_string()]
+    }
+
+    fn map_to_sql(self) -> Vec<(String, Self::ValueType)> {
+        vec![
+            ("id".to_string(), SQLValue::serialize(self.id)),
+            ("user_id".to_string(), SQLValue::serialize(self.user_id)),
+            (
+                "gacha_type".to_string(),
+                SQLValue::serialize(self.gacha_type),
+            ),
+            (
+                "created_at".to_string(),
+                SQLValue::serialize(self.created_at),
+            ),
+            (
+                "gsi_user_id_gacha_type".to_string(),
+                SQLValue::serialize(self.gsi_user_id_gacha_type),
+            ),
+            ("result".to_string(), SQLValue::serialize(self.result)),
+            (
+                "pity_count".to_string(),
+                SQLValue::serialize(self.pity_count),
+            ),
+        ]
+    }
 }
 
 impl GachaEventRecord {
@@ -29,6 +93,10 @@ impl GachaEventRecord {
             user_id: UserId(self.user_id),
             gacha_type: GachaType::new(self.gacha_type.as_str()),
             created_at: UnixTime(self.created_at),
+            result: self
+                .result
+                .and_then(|result| serde_json::from_str(&result).ok()),
+            pity_count: self.pity_count.unwrap_or(0),
         }
     }
 
@@ -42,6 +110,10 @@ impl GachaEventRecord {
             gacha_type: model.gacha_type.to_string(),
             created_at: model.created_at.0,
             gsi_user_id_gacha_type,
+            result: model
+                .result
+                .map(|result| serde_json::to_string(&result).unwrap()),
+            pity_count: Some(model.pity_count),
         }
     }
 
@@ -85,25 +157,64 @@ impl IGachaEventRepository for GachaEventRepository {
             .await?;
 
         if events.len() == 0 {
-            return Err(ServiceError::not_found(failure::err_msg("event not found")));
+            return Err(ServiceError::not_found(
+                ErrorCode::NotFound,
+                failure::err_msg("event not found"),
+            ));
         }
 
         Ok(events[0].clone().into_model())
     }
 
-    async fn create(&self, event: GachaEvent) -> Result<(), ServiceError> {
+    async fn save(&self, event: GachaEvent) -> Result<(), ServiceError> {
         self.dynamo_client
-            .create(
-                self.table_name.clone(),
-                GachaEventRecord::from_model(event),
-                "id".to_string(),
-            )
+            .save(self.table_name.clone(), GachaEventRecord::from_model(event))
             .await?;
 
         Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::{GachaResult, Rarity};
+
+    #[test]
+    fn record_should_read_items_without_result() {
+        let event = GachaEvent {
+            id: GachaEventId("gacha-1".to_string()),
+            user_id: UserId("user-1".to_string()),
+            gacha_type: GachaType::new("premium"),
+            created_at: UnixTime(1600000000),
+            result: Some(GachaResult {
+                rarity: Rarity::Rare,
+                point: 20,
+                item: None,
+            }),
+            pity_count: 3,
+        };
+        let item = debil_dynamodb::into_item(GachaEventRecord::from_model(event.clone()));
+        assert_eq!(
+            debil_dynamodb::from_item::<GachaEventRecord>(item.clone()).into_model(),
+            event
+        );
+
+        // resultを記録する前のアイテム
+        let mut item = item;
+        item.remove("result");
+        item.remove("pity_count");
+        assert_eq!(
+            debil_dynamodb::from_item::<GachaEventRecord>(item).into_model(),
+            GachaEvent {
+                result: None,
+                pity_count: 0,
+                ..event
+            }
+        );
+    }
+}
+
 #[cfg(test)]
 pub mod gacha_event_repository_mock {
     use super::*;
@@ -111,20 +222,32 @@ pub mod gacha_event_repository_mock {
 
     pub struct GachaEventRepositoryStub {
         item: Arc<Mutex<Option<GachaEvent>>>,
+        pub saved: Arc<Mutex<Vec<GachaEvent>>>,
+        // 残りこの回数だけsaveを失敗させる
+        failures: Arc<Mutex<u32>>,
     }
 
     impl GachaEventRepositoryStub {
         pub fn new(item: GachaEvent) -> Self {
             GachaEventRepositoryStub {
                 item: Arc::new(Mutex::new(Some(item))),
+                saved: Arc::new(Mutex::new(Vec::new())),
+                failures: Arc::new(Mutex::new(0)),
             }
         }
 
         pub fn new_empty() -> Self {
             GachaEventRepositoryStub {
                 item: Arc::new(Mutex::new(None)),
+                saved: Arc::new(Mutex::new(Vec::new())),
+                failures: Arc::new(Mutex::new(0)),
             }
         }
+
+        pub fn failing(self, times: u32) -> Self {
+            *self.failures.lock().unwrap() = times;
+            self
+        }
     }
 
     #[async_trait]
@@ -136,11 +259,29 @@ pub mod gacha_event_repository_mock {
         ) -> Result<GachaEvent, ServiceError> {
             match self.item.lock().unwrap().clone() {
                 Some(event) => Ok(event),
-                None => Err(ServiceError::not_found(failure::err_msg("error"))),
+                None => Err(ServiceError::not_found(
+                    ErrorCode::NotFound,
+                    failure::err_msg("error"),
+                )),
             }
         }
 
-        async fn create(&self, event: GachaEvent) -> Result<(), ServiceError> {
+        async fn save(&self, event: GachaEvent) -> Result<(), ServiceError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(ServiceError::service_unavailable(
+                    ErrorCode::DatabaseUnavailable,
+                    failure::err_msg("error"),
+                ));
+            }
+
+            let mut saved = self.saved.lock().unwrap();
+            match saved.iter_mut().find(|e| e.id == event.id) {
+                Some(e) => *e = event,
+                None => saved.push(event),
+            }
+
             Ok(())
         }
     }
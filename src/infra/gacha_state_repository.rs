@@ -0,0 +1,215 @@
+use crate::domain::interface::IGachaStateRepository;
+use crate::domain::model::{GachaState, GachaType, UserId};
+use crate::infra::ConnPool;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use crate::wrapper::unixtime::UnixTime;
+use async_trait::async_trait;
+use debil::*;
+use debil_mysql::*;
+use std::sync::Arc;
+
+#[derive(Table, Clone, Accessor)]
+#[sql(
+    table_name = "gacha_state",
+    sql_type = "MySQLValue",
+    primary_key = "id"
+)]
+pub struct GachaStateRecord {
+    // user_idとgacha_typeを繋げたもの
+    #[sql(size = 200)]
+    id: String,
+    #[sql(size = 100)]
+    user_id: String,
+    #[sql(size = 100)]
+    gacha_type: String,
+    last_drawn_at: i64,
+    draw_count: u64,
+    pity_count: u32,
+}
+
+impl GachaStateRecord {
+    fn id_of(user_id: &UserId, gacha_type: &GachaType) -> String {
+        format!("{}#{}", user_id.0, gacha_type.to_string())
+    }
+
+    pub fn from_model(model: GachaState) -> Self {
+        GachaStateRecord {
+            id: GachaStateRecord::id_of(&model.user_id, &model.gacha_type),
+            user_id: model.user_id.0,
+            gacha_type: model.gacha_type.to_string(),
+            last_drawn_at: model.last_drawn_at.0,
+            draw_count: model.draw_count,
+            pity_count: model.pity_count,
+        }
+    }
+
+    pub fn into_model(self) -> GachaState {
+        GachaState {
+            user_id: UserId(self.user_id),
+            gacha_type: GachaType::new(&self.gacha_type),
+            last_drawn_at: UnixTime(self.last_drawn_at),
+            draw_count: self.draw_count,
+            pity_count: self.pity_count,
+        }
+    }
+}
+
+fn condition_not_met() -> ServiceError {
+    ServiceError::conflict(
+        ErrorCode::ConditionNotMet,
+        failure::err_msg("ConditionNotMet"),
+    )
+}
+
+pub struct GachaStateRepository {
+    pool: Arc<ConnPool>,
+}
+
+impl GachaStateRepository {
+    pub fn new(pool: Arc<ConnPool>) -> Self {
+        GachaStateRepository { pool }
+    }
+}
+
+#[async_trait]
+impl IGachaStateRepository for GachaStateRepository {
+    async fn find_by_user_type(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<GachaState, ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        let record = conn
+            .first_with_params::<GachaStateRecord>(
+                QueryBuilder::new().filter(format!("{} = :id", accessor!(GachaStateRecord::id))),
+                vec![(
+                    "id",
+                    MySQLValue::serialize(GachaStateRecord::id_of(user_id, gacha_type)),
+                )],
+            )
+            .await?;
+
+        Ok(record.into_model())
+    }
+
+    async fn conditional_save(
+        &self,
+        state: GachaState,
+        prev: Option<GachaState>,
+    ) -> Result<(), ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        let record = GachaStateRecord::from_model(state);
+
+        let prev = match prev {
+            Some(prev) => prev,
+            // 同時に初めて引いた場合は後の方が主キーの重複で失敗する
+            None => {
+                return match conn.create(record).await {
+                    Ok(_) => Ok(()),
+                    Err(err) => match ServiceError::from(err) {
+                        err if err.code == ErrorCode::DuplicateEntry => Err(condition_not_met()),
+                        err => Err(err),
+                    },
+                };
+            }
+        };
+
+        // 先に更新したトランザクションがあれば, その終わりを待ってから条件を確かめる
+        let rows = conn
+            .sql_exec(
+                format!(
+                    "UPDATE {} SET {} = :last_drawn_at, {} = :draw_count, {} = :pity_count WHERE {} = :id AND {} = :prev_draw_count",
+                    table_name::<GachaStateRecord>(),
+                    accessor!(GachaStateRecord::last_drawn_at),
+                    accessor!(GachaStateRecord::draw_count),
+                    accessor!(GachaStateRecord::pity_count),
+                    accessor!(GachaStateRecord::id),
+                    accessor!(GachaStateRecord::draw_count),
+                ),
+                debil::Params::<MySQLValue>(vec![
+                    (
+                        "last_drawn_at".to_string(),
+                        MySQLValue::serialize(record.last_drawn_at),
+                    ),
+                    (
+                        "draw_count".to_string(),
+                        MySQLValue::serialize(record.draw_count),
+                    ),
+                    (
+                        "pity_count".to_string(),
+                        MySQLValue::serialize(record.pity_count),
+                    ),
+                    ("id".to_string(), MySQLValue::serialize(record.id)),
+                    (
+                        "prev_draw_count".to_string(),
+                        MySQLValue::serialize(prev.draw_count),
+                    ),
+                ]),
+            )
+            .await?;
+        if rows == 0 {
+            return Err(condition_not_met());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod gacha_state_repository_mock {
+    use super::*;
+    use std::sync::Mutex;
+
+    // 保存された状態をそのまま持つ, 条件はdraw_countだけを見る
+    pub struct GachaStateRepositoryMock {
+        pub states: Arc<Mutex<Vec<GachaState>>>,
+    }
+
+    impl GachaStateRepositoryMock {
+        pub fn new(states: Vec<GachaState>) -> Self {
+            GachaStateRepositoryMock {
+                states: Arc::new(Mutex::new(states)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IGachaStateRepository for GachaStateRepositoryMock {
+        async fn find_by_user_type(
+            &self,
+            user_id: &UserId,
+            gacha_type: &GachaType,
+        ) -> Result<GachaState, ServiceError> {
+            self.states
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|s| &s.user_id == user_id && &s.gacha_type == gacha_type)
+                .cloned()
+                .ok_or(ServiceError::not_found(
+                    ErrorCode::NotFound,
+                    failure::err_msg("record not found"),
+                ))
+        }
+
+        async fn conditional_save(
+            &self,
+            state: GachaState,
+            prev: Option<GachaState>,
+        ) -> Result<(), ServiceError> {
+            let mut states = self.states.lock().unwrap();
+            let current = states
+                .iter()
+                .position(|s| s.user_id == state.user_id && s.gacha_type == state.gacha_type);
+            match (current, prev) {
+                (None, None) => states.push(state),
+                (Some(i), Some(prev)) if states[i].draw_count == prev.draw_count => {
+                    states[i] = state
+                }
+                _ => return Err(condition_not_met()),
+            }
+
+            Ok(())
+        }
+    }
+}
@@ -126,27 +126,44 @@ impl GiftRepository {
     pub fn new(pool: Arc<ConnPool>) -> Self {
         GiftRepository { pool }
     }
+
+    // gift_idはパスパラメータなので, クエリには埋め込まずにパラメータで渡す
+    fn relation_query(
+        gift_id: &GiftId,
+        user_id: &UserId,
+    ) -> (debil::QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            debil::QueryBuilder::new().filter(format!(
+                "{} = :id and {} = :user_id",
+                accessor!(GiftUserRelation::id),
+                accessor!(GiftUserRelation::user_id),
+            )),
+            vec![
+                ("id", MySQLValue::serialize(gift_id.0.clone())),
+                ("user_id", MySQLValue::serialize(user_id.0.clone())),
+            ],
+        )
+    }
+
+    fn gift_query(gift_id: &GiftId) -> (debil::QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            debil::QueryBuilder::new().filter(format!("{} = :id", accessor!(GiftRecord::id))),
+            vec![("id", MySQLValue::serialize(gift_id.0.clone()))],
+        )
+    }
 }
 
 #[async_trait]
 impl IGiftRepository for GiftRepository {
     async fn find_by_id(&self, gift_id: &GiftId, user_id: &UserId) -> Result<Gift, ServiceError> {
         let mut conn = self.pool.get_conn().await?;
+        let (builder, params) = GiftRepository::gift_query(gift_id);
         let gift = conn
-            .first_with::<GiftRecord>(debil::QueryBuilder::new().filter(format!(
-                "{} = '{}'",
-                accessor!(GiftRecord::id),
-                gift_id.0
-            )))
+            .first_with_params::<GiftRecord>(builder, params)
             .await?;
+        let (builder, params) = GiftRepository::relation_query(gift_id, user_id);
         let user_relation = conn
-            .first_with::<GiftUserRelation>(debil::QueryBuilder::new().filter(format!(
-                "{} = '{}' and {} = '{}'",
-                accessor!(GiftUserRelation::id),
-                gift_id.0,
-                accessor!(GiftUserRelation::user_id),
-                user_id.0,
-            )))
+            .first_with_params::<GiftUserRelation>(builder, params)
             .await?;
 
         JoinedGiftRecordUserRelationView {
@@ -163,21 +180,23 @@ impl IGiftRepository for GiftRepository {
     ) -> Result<Vec<Gift>, ServiceError> {
         let mut conn = self.pool.get_conn().await?;
         let records = conn
-            .load_with2::<GiftRecord, JoinedGiftRecordUserRelationView>(
+            .load_with2_params::<GiftRecord, JoinedGiftRecordUserRelationView>(
                 debil::QueryBuilder::new()
                     .inner_join(table_name::<GiftUserRelation>(), ("id", "id"))
                     .filter(format!(
-                        "{} = '{}' AND {} = '{}'",
+                        "{} = :user_id AND {} = :status",
                         accessor!(GiftUserRelation::user_id),
-                        user_id.0,
                         accessor!(GiftUserRelation::status),
-                        status.to_string()
                     ))
                     .append_selects(vec![
                         accessor!(GiftUserRelation::user_id),
                         accessor!(GiftUserRelation::status),
                     ])
                     .order_by(accessor!(GiftRecord::created_at), Ordering::Descending),
+                vec![
+                    ("user_id", MySQLValue::serialize(user_id.0.clone())),
+                    ("status", MySQLValue::serialize(status.to_string())),
+                ],
             )
             .await?;
 
@@ -365,7 +384,36 @@ pub mod gift_repository_mock {
             users: Vec<UserId>,
             status: GiftStatus,
         ) -> Result<(), ServiceError> {
-            unimplemented!()
+            self.created.lock().unwrap().push(gift);
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::{assert_bound, select_query, INJECTION_PAYLOADS};
+
+    #[test]
+    fn find_by_id_should_bind_parameters() {
+        for payload in INJECTION_PAYLOADS {
+            let gift_id = GiftId(payload.to_string());
+            let user_id = UserId(payload.to_string());
+
+            let (builder, params) = GiftRepository::gift_query(&gift_id);
+            let query = select_query::<GiftRecord>(builder.limit(1));
+            assert!(query.ends_with("FROM gift WHERE gift.id = :id LIMIT 1"));
+            assert_bound(&query, &params, "id", payload);
+
+            let (builder, params) = GiftRepository::relation_query(&gift_id, &user_id);
+            let query = select_query::<GiftUserRelation>(builder.limit(1));
+            assert!(query.ends_with(
+                "WHERE gift_user_relation.id = :id and gift_user_relation.user_id = :user_id LIMIT 1"
+            ));
+            assert_bound(&query, &params, "id", payload);
+            assert_bound(&query, &params, "user_id", payload);
         }
     }
 }
@@ -0,0 +1,80 @@
+use crate::domain::interface::IHealthCheck;
+use crate::infra::{ConnPool, DynamoClient, S3Client};
+use crate::wrapper::error::ServiceError;
+use async_trait::async_trait;
+use debil::*;
+use debil_mysql::*;
+use std::sync::Arc;
+
+// プールからコネクションを取り出して実際にクエリを投げる
+pub struct MySQLHealthCheck {
+    pool: Arc<ConnPool>,
+}
+
+impl MySQLHealthCheck {
+    pub fn new(pool: Arc<ConnPool>) -> Self {
+        MySQLHealthCheck { pool }
+    }
+}
+
+#[async_trait]
+impl IHealthCheck for MySQLHealthCheck {
+    fn name(&self) -> &str {
+        "mysql"
+    }
+
+    async fn check(&self) -> Result<(), ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.sql_exec("SELECT 1".to_string(), debil::Params::<MySQLValue>(vec![]))
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub struct DynamoTableHealthCheck {
+    client: Arc<DynamoClient>,
+    table_name: String,
+}
+
+impl DynamoTableHealthCheck {
+    pub fn new(client: Arc<DynamoClient>, table_name: String) -> Self {
+        DynamoTableHealthCheck { client, table_name }
+    }
+}
+
+#[async_trait]
+impl IHealthCheck for DynamoTableHealthCheck {
+    fn name(&self) -> &str {
+        "dynamodb"
+    }
+
+    async fn check(&self) -> Result<(), ServiceError> {
+        self.client.describe_table(self.table_name.clone()).await
+    }
+}
+
+pub struct S3BucketHealthCheck {
+    client: Arc<S3Client>,
+    bucket_name: String,
+}
+
+impl S3BucketHealthCheck {
+    pub fn new(client: Arc<S3Client>, bucket_name: String) -> Self {
+        S3BucketHealthCheck {
+            client,
+            bucket_name,
+        }
+    }
+}
+
+#[async_trait]
+impl IHealthCheck for S3BucketHealthCheck {
+    fn name(&self) -> &str {
+        "s3"
+    }
+
+    async fn check(&self) -> Result<(), ServiceError> {
+        self.client.head_bucket(self.bucket_name.clone()).await
+    }
+}
@@ -0,0 +1,1262 @@
+use crate::domain::interface::{
+    IGachaEventRepository, IGachaStateRepository, IGiftRepository, IJankenEventRepository,
+    IPendingGachaEventRepository, IPointEventRepository, IPointTransactionRepository,
+    IRankingRepository, ITransaction, IUnitOfWork, IUserIconUploader, IUserRepository,
+};
+use crate::domain::model::{
+    GachaEvent, GachaEventId, GachaState, GachaType, Gift, GiftId, GiftStatus, JankenEvent,
+    JankenStatus, PointDiffRankingRecord, PointEvent, PointTransaction, User, UserId,
+};
+use crate::wrapper::base64::Base64;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use crate::wrapper::unixtime::UnixTime;
+use crate::wrapper::url::Url;
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// MySQL, DynamoDB, S3の代わりに全てのデータをメモリ上に持つ
+// ローカルでの開発やE2Eテストのためのもので、プロセスが終了するとデータは消える
+#[derive(Clone, Default)]
+struct InMemoryTables {
+    users: Vec<User>,
+    gacha_events: Vec<GachaEvent>,
+    gacha_states: Vec<GachaState>,
+    pending_gacha_events: Vec<GachaEvent>,
+    // Giftのstatusはユーザーごとにgift_relationsで持つ
+    gifts: Vec<Gift>,
+    gift_relations: Vec<(GiftId, UserId, GiftStatus)>,
+    janken_events: Vec<JankenEvent>,
+    point_events: Vec<PointEvent>,
+    point_transactions: Vec<PointTransaction>,
+}
+
+#[derive(Default)]
+pub struct InMemoryStore {
+    tables: Mutex<InMemoryTables>,
+    // SELECT ... FOR UPDATEの代わりに, トランザクションの終わりまでusersの読み込みを待たせる
+    user_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, InMemoryTables> {
+        self.tables.lock().unwrap()
+    }
+}
+
+// トランザクション中の書き込みを取り消す操作
+type Undo = Box<dyn FnOnce(&mut InMemoryTables) + Send>;
+
+// トランザクションの中で行った書き込みだけを記録し, ロールバックでは新しいものから順に取り消す
+// 他のリクエストが同時に書き込んだ内容は巻き戻さない
+#[derive(Clone, Default)]
+struct UndoLog(Option<Arc<Mutex<Vec<Undo>>>>);
+
+impl UndoLog {
+    fn new() -> Self {
+        UndoLog(Some(Arc::new(Mutex::new(Vec::new()))))
+    }
+
+    // トランザクションの外では何もしない
+    fn push(&self, undo: impl FnOnce(&mut InMemoryTables) + Send + 'static) {
+        if let Some(log) = &self.0 {
+            log.lock().unwrap().push(Box::new(undo));
+        }
+    }
+
+    fn clear(&self) {
+        if let Some(log) = &self.0 {
+            log.lock().unwrap().clear();
+        }
+    }
+
+    fn undo(&self, tables: &mut InMemoryTables) {
+        if let Some(log) = &self.0 {
+            let undos = std::mem::take(&mut *log.lock().unwrap());
+            for undo in undos.into_iter().rev() {
+                undo(tables);
+            }
+        }
+    }
+}
+
+fn not_found() -> ServiceError {
+    ServiceError::not_found(ErrorCode::NotFound, failure::err_msg("record not found"))
+}
+
+// MySQLのunique制約に違反したときと同じくconflictにする
+fn duplicate_entry(key: &str) -> ServiceError {
+    ServiceError::conflict(
+        ErrorCode::DuplicateEntry,
+        failure::err_msg(format!("Duplicate entry for key '{}'", key)),
+    )
+}
+
+pub struct InMemoryUserRepository {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+    // トランザクションの中でだけ持ち, 取ったuser_lockをトランザクションの終わりまで保持する
+    user_lock: Option<Arc<Mutex<Option<tokio::sync::OwnedMutexGuard<()>>>>>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryUserRepository {
+            store,
+            undo_log: UndoLog::default(),
+            user_lock: None,
+        }
+    }
+}
+
+#[async_trait]
+impl IUserRepository for InMemoryUserRepository {
+    async fn list_id(&self) -> Result<Vec<UserId>, ServiceError> {
+        Ok(self
+            .store
+            .lock()
+            .users
+            .iter()
+            .map(|user| user.id.clone())
+            .collect())
+    }
+
+    async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError> {
+        self.store
+            .lock()
+            .users
+            .iter()
+            .find(|user| &user.id == user_id)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn find_by_screen_name(&self, screen_name: &String) -> Result<User, ServiceError> {
+        self.store
+            .lock()
+            .users
+            .iter()
+            .find(|user| user.screen_name.as_ref() == Some(screen_name))
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn find_by_subject(&self, subject: &str) -> Result<User, ServiceError> {
+        self.store
+            .lock()
+            .users
+            .iter()
+            .find(|user| user.subject == subject)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn find_by_subject_for_update(&self, subject: &str) -> Result<User, ServiceError> {
+        if let Some(held) = &self.user_lock {
+            let locked = held.lock().unwrap().is_some();
+            if !locked {
+                let guard = self.store.user_lock.clone().lock_owned().await;
+                *held.lock().unwrap() = Some(guard);
+            }
+        }
+
+        self.find_by_subject(subject).await
+    }
+
+    async fn create(&self, user: User) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        if tables.users.iter().any(|u| u.id == user.id) {
+            return Err(duplicate_entry("PRIMARY"));
+        }
+        if tables.users.iter().any(|u| u.subject == user.subject) {
+            return Err(duplicate_entry("subject"));
+        }
+        if user.screen_name.is_some()
+            && tables
+                .users
+                .iter()
+                .any(|u| u.screen_name == user.screen_name)
+        {
+            return Err(duplicate_entry("screen_name"));
+        }
+
+        let user_id = user.id.clone();
+        tables.users.push(user);
+        self.undo_log.push(move |tables| {
+            tables.users.retain(|user| user.id != user_id);
+        });
+
+        Ok(())
+    }
+
+    async fn save(&self, user: User) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        if user.screen_name.is_some()
+            && tables
+                .users
+                .iter()
+                .any(|u| u.id != user.id && u.screen_name == user.screen_name)
+        {
+            return Err(duplicate_entry("screen_name"));
+        }
+
+        // UserRepositoryと同じく、プロフィール以外の項目はpoint_transaction経由でしか更新しない
+        if let Some(record) = tables.users.iter_mut().find(|u| u.id == user.id) {
+            let prev = (
+                std::mem::replace(&mut record.screen_name, user.screen_name),
+                std::mem::replace(&mut record.display_name, user.display_name),
+                std::mem::replace(&mut record.picture_url, user.picture_url),
+            );
+            let user_id = user.id;
+            self.undo_log.push(move |tables| {
+                if let Some(record) = tables.users.iter_mut().find(|u| u.id == user_id) {
+                    record.screen_name = prev.0;
+                    record.display_name = prev.1;
+                    record.picture_url = prev.2;
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryGachaEventRepository {
+    store: Arc<InMemoryStore>,
+}
+
+impl InMemoryGachaEventRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryGachaEventRepository { store }
+    }
+}
+
+#[async_trait]
+impl IGachaEventRepository for InMemoryGachaEventRepository {
+    async fn find_by_user_type(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<GachaEvent, ServiceError> {
+        self.store
+            .lock()
+            .gacha_events
+            .iter()
+            .filter(|event| &event.user_id == user_id && &event.gacha_type == gacha_type)
+            .max_by_key(|event| event.created_at.0)
+            .cloned()
+            .ok_or(ServiceError::not_found(
+                ErrorCode::NotFound,
+                failure::err_msg("event not found"),
+            ))
+    }
+
+    async fn save(&self, event: GachaEvent) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        match tables.gacha_events.iter_mut().find(|e| e.id == event.id) {
+            Some(e) => *e = event,
+            None => tables.gacha_events.push(event),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryPendingGachaEventRepository {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+}
+
+impl InMemoryPendingGachaEventRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryPendingGachaEventRepository {
+            store,
+            undo_log: UndoLog::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl IPendingGachaEventRepository for InMemoryPendingGachaEventRepository {
+    async fn find_by_user_id(&self, user_id: &UserId) -> Result<Vec<GachaEvent>, ServiceError> {
+        Ok(self
+            .store
+            .lock()
+            .pending_gacha_events
+            .iter()
+            .filter(|event| &event.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn create(&self, event: GachaEvent) -> Result<(), ServiceError> {
+        let id = event.id.clone();
+        self.store.lock().pending_gacha_events.push(event);
+        self.undo_log.push(move |tables| {
+            tables.pending_gacha_events.retain(|e| e.id != id);
+        });
+
+        Ok(())
+    }
+
+    async fn delete(&self, event_id: &GachaEventId) -> Result<(), ServiceError> {
+        self.store
+            .lock()
+            .pending_gacha_events
+            .retain(|e| &e.id != event_id);
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryGachaStateRepository {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+}
+
+impl InMemoryGachaStateRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryGachaStateRepository {
+            store,
+            undo_log: UndoLog::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl IGachaStateRepository for InMemoryGachaStateRepository {
+    async fn find_by_user_type(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<GachaState, ServiceError> {
+        self.store
+            .lock()
+            .gacha_states
+            .iter()
+            .find(|state| &state.user_id == user_id && &state.gacha_type == gacha_type)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn conditional_save(
+        &self,
+        state: GachaState,
+        prev: Option<GachaState>,
+    ) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        let current = tables
+            .gacha_states
+            .iter_mut()
+            .find(|s| s.user_id == state.user_id && s.gacha_type == state.gacha_type);
+
+        let user_id = state.user_id.clone();
+        let gacha_type = state.gacha_type.clone();
+        match (current, prev) {
+            (None, None) => {
+                tables.gacha_states.push(state);
+                self.undo_log.push(move |tables| {
+                    tables
+                        .gacha_states
+                        .retain(|s| !(s.user_id == user_id && s.gacha_type == gacha_type));
+                });
+            }
+            (Some(current), Some(prev)) if current.draw_count == prev.draw_count => {
+                let prev = std::mem::replace(current, state);
+                self.undo_log.push(move |tables| {
+                    if let Some(current) = tables
+                        .gacha_states
+                        .iter_mut()
+                        .find(|s| s.user_id == user_id && s.gacha_type == gacha_type)
+                    {
+                        *current = prev;
+                    }
+                });
+            }
+            _ => {
+                return Err(ServiceError::conflict(
+                    ErrorCode::ConditionNotMet,
+                    failure::err_msg("ConditionNotMet"),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryGiftRepository {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+}
+
+impl InMemoryGiftRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryGiftRepository {
+            store,
+            undo_log: UndoLog::default(),
+        }
+    }
+
+    fn create_gift(&self, tables: &mut InMemoryTables, gift: Gift) {
+        let gift_id = gift.id.clone();
+        tables.gifts.push(gift);
+        self.undo_log.push(move |tables| {
+            tables.gifts.retain(|gift| gift.id != gift_id);
+        });
+    }
+
+    fn save_relation(
+        &self,
+        tables: &mut InMemoryTables,
+        gift_id: GiftId,
+        user_id: UserId,
+        status: GiftStatus,
+    ) {
+        let prev = match tables
+            .gift_relations
+            .iter_mut()
+            .find(|(g, u, _)| g == &gift_id && u == &user_id)
+        {
+            Some(relation) => Some(std::mem::replace(&mut relation.2, status)),
+            None => {
+                tables
+                    .gift_relations
+                    .push((gift_id.clone(), user_id.clone(), status));
+                None
+            }
+        };
+
+        self.undo_log.push(move |tables| match prev {
+            Some(prev) => {
+                if let Some(relation) = tables
+                    .gift_relations
+                    .iter_mut()
+                    .find(|(g, u, _)| g == &gift_id && u == &user_id)
+                {
+                    relation.2 = prev;
+                }
+            }
+            None => tables
+                .gift_relations
+                .retain(|(g, u, _)| !(g == &gift_id && u == &user_id)),
+        });
+    }
+}
+
+#[async_trait]
+impl IGiftRepository for InMemoryGiftRepository {
+    async fn find_by_id(&self, gift_id: &GiftId, user_id: &UserId) -> Result<Gift, ServiceError> {
+        let tables = self.store.lock();
+        let mut gift = tables
+            .gifts
+            .iter()
+            .find(|gift| &gift.id == gift_id)
+            .cloned()
+            .ok_or_else(not_found)?;
+        let (_, _, status) = tables
+            .gift_relations
+            .iter()
+            .find(|(g, u, _)| g == gift_id && u == user_id)
+            .ok_or_else(not_found)?;
+        gift.status = status.clone();
+
+        Ok(gift)
+    }
+
+    async fn find_by_user_id_status(
+        &self,
+        user_id: &UserId,
+        status: GiftStatus,
+    ) -> Result<Vec<Gift>, ServiceError> {
+        let tables = self.store.lock();
+        let mut gifts = tables
+            .gift_relations
+            .iter()
+            .filter(|(_, u, s)| u == user_id && s == &status)
+            .filter_map(|(gift_id, _, status)| {
+                tables
+                    .gifts
+                    .iter()
+                    .find(|gift| &gift.id == gift_id)
+                    .map(|gift| Gift {
+                        status: status.clone(),
+                        ..gift.clone()
+                    })
+            })
+            .collect::<Vec<_>>();
+        gifts.sort_by_key(|gift| -gift.created_at.0);
+
+        Ok(gifts)
+    }
+
+    async fn create(&self, gift: Gift) -> Result<(), ServiceError> {
+        self.create_gift(&mut self.store.lock(), gift);
+
+        Ok(())
+    }
+
+    async fn save_status(
+        &self,
+        gift_id: GiftId,
+        user_id: UserId,
+        status: GiftStatus,
+    ) -> Result<(), ServiceError> {
+        self.save_relation(&mut self.store.lock(), gift_id, user_id, status);
+
+        Ok(())
+    }
+
+    async fn create_for(
+        &self,
+        gift: Gift,
+        users: Vec<UserId>,
+        status: GiftStatus,
+    ) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+
+        let gift_id = gift.id.clone();
+        self.create_gift(&mut tables, gift);
+        for user_id in users {
+            self.save_relation(&mut tables, gift_id.clone(), user_id, status.clone());
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryUserIconUploader;
+
+impl InMemoryUserIconUploader {
+    pub fn new() -> Self {
+        InMemoryUserIconUploader
+    }
+}
+
+#[async_trait]
+impl IUserIconUploader for InMemoryUserIconUploader {
+    async fn upload_user_icon(
+        &self,
+        _user_id: &UserId,
+        image: Base64,
+    ) -> Result<Url, ServiceError> {
+        // 保存先がないので、画像をそのまま埋め込んだdata URLを返す
+        let image_data = image.decode()?;
+
+        Ok(Url(format!(
+            "data:image;base64,{}",
+            Base64::encode(image_data).0
+        )))
+    }
+}
+
+pub struct InMemoryJankenEventRepository {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+}
+
+impl InMemoryJankenEventRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryJankenEventRepository {
+            store,
+            undo_log: UndoLog::default(),
+        }
+    }
+
+    fn save_event(&self, tables: &mut InMemoryTables, janken_event: JankenEvent) {
+        let id = janken_event.id.clone();
+        let prev = match tables
+            .janken_events
+            .iter_mut()
+            .find(|event| event.id == janken_event.id)
+        {
+            Some(event) => Some(std::mem::replace(event, janken_event)),
+            None => {
+                tables.janken_events.push(janken_event);
+                None
+            }
+        };
+
+        self.undo_log.push(move |tables| match prev {
+            Some(prev) => {
+                if let Some(event) = tables.janken_events.iter_mut().find(|e| e.id == id) {
+                    *event = prev;
+                }
+            }
+            None => tables.janken_events.retain(|event| event.id != id),
+        });
+    }
+}
+
+#[async_trait]
+impl IJankenEventRepository for InMemoryJankenEventRepository {
+    async fn find_by_user_id_status(
+        &self,
+        user_id: &UserId,
+        status: JankenStatus,
+    ) -> Result<Vec<JankenEvent>, ServiceError> {
+        let mut events = self
+            .store
+            .lock()
+            .janken_events
+            .iter()
+            .filter(|event| &event.user_id == user_id && event.status == status)
+            .cloned()
+            .collect::<Vec<_>>();
+        events.sort_by_key(|event| -event.created_at.0);
+
+        Ok(events)
+    }
+
+    async fn find_by_user_id(
+        &self,
+        user_id: &UserId,
+        limit: i32,
+    ) -> Result<Vec<JankenEvent>, ServiceError> {
+        let mut events = self
+            .store
+            .lock()
+            .janken_events
+            .iter()
+            .filter(|event| &event.user_id == user_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        events.sort_by_key(|event| -event.created_at.0);
+        events.truncate(limit as usize);
+
+        Ok(events)
+    }
+
+    async fn scan_by_status(
+        &self,
+        status: JankenStatus,
+        limit: i32,
+    ) -> Result<Vec<JankenEvent>, ServiceError> {
+        Ok(self
+            .store
+            .lock()
+            .janken_events
+            .iter()
+            .filter(|event| event.status == status)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn create(&self, janken_event: JankenEvent) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        if tables
+            .janken_events
+            .iter()
+            .any(|event| event.id == janken_event.id)
+        {
+            return Err(duplicate_entry("PRIMARY"));
+        }
+
+        self.save_event(&mut tables, janken_event);
+
+        Ok(())
+    }
+
+    async fn save(&self, janken_event: JankenEvent) -> Result<(), ServiceError> {
+        self.save_event(&mut self.store.lock(), janken_event);
+
+        Ok(())
+    }
+
+    async fn save_all(&self, janken_events: Vec<JankenEvent>) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        for event in janken_events {
+            self.save_event(&mut tables, event);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryPointEventRepository {
+    store: Arc<InMemoryStore>,
+}
+
+impl InMemoryPointEventRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryPointEventRepository { store }
+    }
+}
+
+#[async_trait]
+impl IPointEventRepository for InMemoryPointEventRepository {
+    async fn find_by_id(&self, user_id: &UserId) -> Result<PointEvent, ServiceError> {
+        self.store
+            .lock()
+            .point_events
+            .iter()
+            .find(|event| &event.user_id == user_id)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn save(&self, event: PointEvent) -> Result<(), ServiceError> {
+        let mut tables = self.store.lock();
+        match tables
+            .point_events
+            .iter_mut()
+            .find(|e| e.user_id == event.user_id)
+        {
+            Some(e) => *e = event,
+            None => tables.point_events.push(event),
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InMemoryRankingRepository {
+    store: Arc<InMemoryStore>,
+}
+
+impl InMemoryRankingRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryRankingRepository { store }
+    }
+
+    // RankingRepositoryと同じく、point_eventとuserをjoinしたものを返す
+    // 前回の値がないときのdiffはNoneになる
+    fn joined_events(&self) -> Vec<(User, u64, Option<i64>)> {
+        let tables = self.store.lock();
+
+        tables
+            .point_events
+            .iter()
+            .filter_map(|event| {
+                tables
+                    .users
+                    .iter()
+                    .find(|user| user.id == event.user_id)
+                    .map(|user| {
+                        (
+                            user.clone(),
+                            event.current,
+                            event.previous.map(|p| event.current as i64 - p as i64),
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl IRankingRepository for InMemoryRankingRepository {
+    async fn list_top_points(
+        &self,
+        limit: u64,
+    ) -> Result<Vec<PointDiffRankingRecord>, ServiceError> {
+        let mut views = self.joined_events();
+        views.sort_by_key(|(_, current, _)| std::cmp::Reverse(*current));
+
+        Ok(views
+            .into_iter()
+            .take(limit as usize)
+            .map(|(user, current, diff)| {
+                PointDiffRankingRecord::new(user, current, diff.unwrap_or(0))
+            })
+            .collect())
+    }
+
+    async fn list_top_point_diffs(
+        &self,
+        limit: u64,
+    ) -> Result<Vec<PointDiffRankingRecord>, ServiceError> {
+        let mut views = self.joined_events();
+        // MySQLの降順と同じくNULLは最後に並べる
+        views.sort_by_key(|(_, _, diff)| std::cmp::Reverse(*diff));
+
+        Ok(views
+            .into_iter()
+            .take(limit as usize)
+            .map(|(user, current, diff)| {
+                PointDiffRankingRecord::new(user, current, diff.unwrap_or(0))
+            })
+            .collect())
+    }
+}
+
+pub struct InMemoryPointTransactionRepository {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+}
+
+impl InMemoryPointTransactionRepository {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryPointTransactionRepository {
+            store,
+            undo_log: UndoLog::default(),
+        }
+    }
+
+    // daily_gachaには(更新後のタイムスタンプ, 現在のタイムスタンプ)を渡す
+    fn apply_to_tables(
+        &self,
+        mut transaction: PointTransaction,
+        daily_gacha: Option<(User, UnixTime)>,
+    ) -> Result<PointTransaction, ServiceError> {
+        let mut tables = self.store.lock();
+        let user = tables
+            .users
+            .iter_mut()
+            .find(|user| user.id == transaction.user_id)
+            .ok_or_else(not_found)?;
+
+        if let Some((_, current)) = &daily_gacha {
+            if &user.last_tried_daily_gacha != current {
+                return Err(ServiceError::conflict(
+                    ErrorCode::ConditionNotMet,
+                    failure::err_msg("ConditionNotMet"),
+                ));
+            }
+        }
+
+        // 残高が負になる更新は行わない
+        let balance = user.point as i64 + transaction.delta;
+        if balance < 0 {
+            return Err(ServiceError::bad_request(
+                ErrorCode::NotEnoughPoint,
+                failure::err_msg("You do not have enough myon point"),
+            ));
+        }
+
+        // 残高は差分で戻し, 同時に行われた他の取引の分は残す
+        let user_id = user.id.clone();
+        let delta = transaction.delta;
+        let prev_daily_gacha = daily_gacha.as_ref().map(|_| {
+            (
+                user.last_tried_daily_gacha.clone(),
+                user.daily_streak.clone(),
+            )
+        });
+        let transaction_id = transaction.id.clone();
+        self.undo_log.push(move |tables| {
+            if let Some(user) = tables.users.iter_mut().find(|user| user.id == user_id) {
+                user.point = (user.point as i64 - delta) as u64;
+                if let Some((last_tried_daily_gacha, daily_streak)) = prev_daily_gacha {
+                    user.last_tried_daily_gacha = last_tried_daily_gacha;
+                    user.daily_streak = daily_streak;
+                }
+            }
+            tables
+                .point_transactions
+                .retain(|transaction| transaction.id != transaction_id);
+        });
+
+        user.point = balance as u64;
+        if let Some((next, _)) = daily_gacha {
+            user.last_tried_daily_gacha = next.last_tried_daily_gacha;
+            user.daily_streak = next.daily_streak;
+        }
+
+        transaction.balance_after = user.point;
+        tables.point_transactions.push(transaction.clone());
+
+        Ok(transaction)
+    }
+}
+
+#[async_trait]
+impl IPointTransactionRepository for InMemoryPointTransactionRepository {
+    async fn find_by_user_id(
+        &self,
+        user_id: &UserId,
+        limit: i32,
+    ) -> Result<Vec<PointTransaction>, ServiceError> {
+        let mut transactions = self
+            .store
+            .lock()
+            .point_transactions
+            .iter()
+            .filter(|transaction| &transaction.user_id == user_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        transactions.sort_by_key(|transaction| -transaction.created_at.0);
+        transactions.truncate(limit as usize);
+
+        Ok(transactions)
+    }
+
+    async fn apply(&self, transaction: PointTransaction) -> Result<PointTransaction, ServiceError> {
+        self.apply_to_tables(transaction, None)
+    }
+
+    async fn conditional_apply(
+        &self,
+        user: User,
+        transaction: PointTransaction,
+        daily_gacha_timestamp: UnixTime,
+    ) -> Result<PointTransaction, ServiceError> {
+        self.apply_to_tables(transaction, Some((user, daily_gacha_timestamp)))
+    }
+}
+
+pub struct InMemoryUnitOfWork {
+    store: Arc<InMemoryStore>,
+}
+
+impl InMemoryUnitOfWork {
+    pub fn new(store: Arc<InMemoryStore>) -> Self {
+        InMemoryUnitOfWork { store }
+    }
+}
+
+#[async_trait]
+impl IUnitOfWork for InMemoryUnitOfWork {
+    async fn begin(&self) -> Result<Box<dyn ITransaction + Sync + Send>, ServiceError> {
+        let undo_log = UndoLog::new();
+
+        Ok(Box::new(InMemoryTransaction {
+            store: self.store.clone(),
+            undo_log: undo_log.clone(),
+            user_repository: InMemoryUserRepository {
+                store: self.store.clone(),
+                undo_log: undo_log.clone(),
+                user_lock: Some(Arc::new(Mutex::new(None))),
+            },
+            gift_repository: InMemoryGiftRepository {
+                store: self.store.clone(),
+                undo_log: undo_log.clone(),
+            },
+            janken_repository: InMemoryJankenEventRepository {
+                store: self.store.clone(),
+                undo_log: undo_log.clone(),
+            },
+            gacha_state_repository: InMemoryGachaStateRepository {
+                store: self.store.clone(),
+                undo_log: undo_log.clone(),
+            },
+            pending_gacha_event_repository: InMemoryPendingGachaEventRepository {
+                store: self.store.clone(),
+                undo_log: undo_log.clone(),
+            },
+            point_transaction_repository: InMemoryPointTransactionRepository {
+                store: self.store.clone(),
+                undo_log,
+            },
+        }))
+    }
+}
+
+// 書き込みは直接ストアに行い, 取り消し方をundo_logに記録しておく
+pub struct InMemoryTransaction {
+    store: Arc<InMemoryStore>,
+    undo_log: UndoLog,
+    user_repository: InMemoryUserRepository,
+    gift_repository: InMemoryGiftRepository,
+    janken_repository: InMemoryJankenEventRepository,
+    gacha_state_repository: InMemoryGachaStateRepository,
+    pending_gacha_event_repository: InMemoryPendingGachaEventRepository,
+    point_transaction_repository: InMemoryPointTransactionRepository,
+}
+
+#[async_trait]
+impl ITransaction for InMemoryTransaction {
+    fn user_repository(&self) -> &(dyn IUserRepository + Sync + Send) {
+        &self.user_repository
+    }
+
+    fn gift_repository(&self) -> &(dyn IGiftRepository + Sync + Send) {
+        &self.gift_repository
+    }
+
+    fn janken_repository(&self) -> &(dyn IJankenEventRepository + Sync + Send) {
+        &self.janken_repository
+    }
+
+    fn gacha_state_repository(&self) -> &(dyn IGachaStateRepository + Sync + Send) {
+        &self.gacha_state_repository
+    }
+
+    fn pending_gacha_event_repository(&self) -> &(dyn IPendingGachaEventRepository + Sync + Send) {
+        &self.pending_gacha_event_repository
+    }
+
+    fn point_transaction_repository(&self) -> &(dyn IPointTransactionRepository + Sync + Send) {
+        &self.point_transaction_repository
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), ServiceError> {
+        self.undo_log.clear();
+
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), ServiceError> {
+        self.undo_log.undo(&mut self.store.lock());
+
+        Ok(())
+    }
+}
+
+// commitされずに捨てられたら, user_lockを離す前にロールバックする
+impl Drop for InMemoryTransaction {
+    fn drop(&mut self) {
+        self.undo_log.undo(&mut self.store.lock());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::{GiftType, PointTransactionReason};
+
+    fn store_with_user(point: u64) -> (Arc<InMemoryStore>, User) {
+        let store = Arc::new(InMemoryStore::new());
+        let user = User {
+            id: UserId::new(),
+            point,
+            ..Default::default()
+        };
+        store.lock().users.push(user.clone());
+
+        (store, user)
+    }
+
+    #[tokio::test]
+    async fn apply_should_not_make_balance_negative() -> Result<(), ServiceError> {
+        let (store, user) = store_with_user(3);
+        let repo = InMemoryPointTransactionRepository::new(store.clone());
+
+        let err = repo
+            .apply(PointTransaction::new(
+                user.id.clone(),
+                PointTransactionReason::JankenBet,
+                None,
+                -5,
+            ))
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.status_code, http::StatusCode::BAD_REQUEST);
+
+        let applied = repo
+            .apply(PointTransaction::new(
+                user.id.clone(),
+                PointTransactionReason::GiftOpened,
+                None,
+                10,
+            ))
+            .await?;
+        assert_eq!(applied.balance_after, 13);
+        assert_eq!(
+            InMemoryUserRepository::new(store.clone())
+                .find_by_id(&user.id)
+                .await?
+                .point,
+            13
+        );
+        assert_eq!(repo.find_by_user_id(&user.id, 10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rollback_should_undo_only_own_writes() -> Result<(), ServiceError> {
+        let (store, user) = store_with_user(10);
+        let unit_of_work = InMemoryUnitOfWork::new(store.clone());
+
+        let tx = unit_of_work.begin().await?;
+        tx.point_transaction_repository()
+            .apply(PointTransaction::new(
+                user.id.clone(),
+                PointTransactionReason::JankenBet,
+                None,
+                -5,
+            ))
+            .await?;
+        let janken_event = JankenEvent {
+            id: crate::domain::model::JankenEventId::new(),
+            user_id: user.id.clone(),
+            hand: crate::domain::model::JankenHand::Rock,
+            created_at: UnixTime::now(),
+            status: JankenStatus::Ready,
+            point: 5,
+            opponent_user_id: None,
+            opponent_user_screen_name: None,
+        };
+        tx.janken_repository().create(janken_event).await?;
+
+        // ロールバックまでの間に他のリクエストが書き込む
+        let gift = Gift::new(GiftType::Point(3), "other request".to_string());
+        InMemoryGiftRepository::new(store.clone())
+            .create_for(gift.clone(), vec![user.id.clone()], GiftStatus::Ready)
+            .await?;
+        InMemoryPointTransactionRepository::new(store.clone())
+            .apply(PointTransaction::new(
+                user.id.clone(),
+                PointTransactionReason::GiftOpened,
+                None,
+                3,
+            ))
+            .await?;
+
+        tx.rollback().await?;
+
+        let user = InMemoryUserRepository::new(store.clone())
+            .find_by_id(&user.id)
+            .await?;
+        assert_eq!(user.point, 13);
+        let tables = store.lock();
+        assert_eq!(tables.point_transactions.len(), 1);
+        assert_eq!(tables.point_transactions[0].delta, 3);
+        assert!(tables.janken_events.is_empty());
+        assert_eq!(tables.gifts.len(), 1);
+        assert_eq!(tables.gift_relations.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dropped_transaction_should_rollback() -> Result<(), ServiceError> {
+        let (store, user) = store_with_user(10);
+
+        let tx = InMemoryUnitOfWork::new(store.clone()).begin().await?;
+        tx.point_transaction_repository()
+            .apply(PointTransaction::new(
+                user.id.clone(),
+                PointTransactionReason::JankenBet,
+                None,
+                -5,
+            ))
+            .await?;
+        drop(tx);
+
+        let user = InMemoryUserRepository::new(store.clone())
+            .find_by_id(&user.id)
+            .await?;
+        assert_eq!(user.point, 10);
+        assert!(store.lock().point_transactions.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_for_update_should_wait_until_transaction_ends() -> Result<(), ServiceError> {
+        let (store, user) = store_with_user(10);
+        let unit_of_work = InMemoryUnitOfWork::new(store.clone());
+
+        let tx = unit_of_work.begin().await?;
+        tx.user_repository()
+            .find_by_subject_for_update(&user.subject)
+            .await?;
+
+        let other = unit_of_work.begin().await?;
+        {
+            let waiting = other
+                .user_repository()
+                .find_by_subject_for_update(&user.subject);
+            futures::pin_mut!(waiting);
+            assert!(futures::poll!(waiting.as_mut()).is_pending());
+
+            tx.commit().await?;
+            waiting.await?;
+        }
+        other.commit().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rollback_should_restore_updated_rows() -> Result<(), ServiceError> {
+        let (store, user) = store_with_user(0);
+        let gift = Gift::new(GiftType::Point(3), "gift".to_string());
+        InMemoryGiftRepository::new(store.clone())
+            .create_for(gift.clone(), vec![user.id.clone()], GiftStatus::Ready)
+            .await?;
+
+        let tx = InMemoryUnitOfWork::new(store.clone()).begin().await?;
+        tx.gift_repository()
+            .save_status(gift.id.clone(), user.id.clone(), GiftStatus::Opened)
+            .await?;
+        tx.rollback().await?;
+
+        let gift = InMemoryGiftRepository::new(store.clone())
+            .find_by_id(&gift.id, &user.id)
+            .await?;
+        assert_eq!(gift.status, GiftStatus::Ready);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gacha_state_should_be_saved_only_from_prev() -> Result<(), ServiceError> {
+        let store = Arc::new(InMemoryStore::new());
+        let repo = InMemoryGachaStateRepository::new(store.clone());
+        let state = GachaState::new(UserId::new(), GachaType::new("hourly"));
+        let drawn = state.drawn(1, 0, UnixTime(100));
+        repo.conditional_save(drawn.clone(), None).await?;
+
+        // 同じ状態から引いた2回目は失敗する
+        let err = repo
+            .conditional_save(drawn.clone(), None)
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.code, ErrorCode::ConditionNotMet);
+        let err = repo
+            .conditional_save(state.drawn(2, 0, UnixTime(100)), Some(state.clone()))
+            .await
+            .expect_err("expect error");
+        assert_eq!(err.code, ErrorCode::ConditionNotMet);
+
+        let tx = InMemoryUnitOfWork::new(store.clone()).begin().await?;
+        tx.gacha_state_repository()
+            .conditional_save(drawn.drawn(1, 1, UnixTime(200)), Some(drawn.clone()))
+            .await?;
+        tx.rollback().await?;
+
+        let saved = repo
+            .find_by_user_type(&state.user_id, &state.gacha_type)
+            .await?;
+        assert_eq!(saved, drawn);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_top_point_diffs_should_put_no_previous_last() -> Result<(), ServiceError> {
+        let store = Arc::new(InMemoryStore::new());
+        let user_repo = InMemoryUserRepository::new(store.clone());
+        let point_repo = InMemoryPointEventRepository::new(store.clone());
+
+        for (subject, current, previous) in [("a", 10, None), ("b", 5, Some(1)), ("c", 8, Some(7))]
+        {
+            let user = User::new(subject.to_string(), None, subject.to_string(), None);
+            point_repo
+                .save(PointEvent {
+                    user_id: user.id.clone(),
+                    current,
+                    previous,
+                    updated_at: UnixTime::now(),
+                })
+                .await?;
+            user_repo.create(user).await?;
+        }
+
+        let ranking_repo = InMemoryRankingRepository::new(store.clone());
+        let diffs = ranking_repo
+            .list_top_point_diffs(10)
+            .await?
+            .into_iter()
+            .map(|record| (record.current, record.diff))
+            .collect::<Vec<_>>();
+        assert_eq!(diffs, vec![(5, 4), (8, 1), (10, 0)]);
+
+        let tops = ranking_repo
+            .list_top_points(2)
+            .await?
+            .into_iter()
+            .map(|record| record.current)
+            .collect::<Vec<_>>();
+        assert_eq!(tops, vec![10, 8]);
+
+        Ok(())
+    }
+}
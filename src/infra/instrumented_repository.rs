@@ -0,0 +1,325 @@
+use crate::domain::interface::{
+    IGachaEventRepository, IGachaStateRepository, IGiftRepository, IJankenEventRepository,
+    IPendingGachaEventRepository, IPointEventRepository, IPointTransactionRepository,
+    IRankingRepository, IUserRepository,
+};
+use crate::domain::model::{
+    GachaEvent, GachaEventId, GachaState, GachaType, Gift, GiftId, GiftStatus, JankenEvent,
+    JankenStatus, PointDiffRankingRecord, PointEvent, PointTransaction, User, UserId,
+};
+use crate::metrics::observe_repository_call;
+use crate::unixtime::UnixTime;
+use crate::wrapper::error::ServiceError;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+// リポジトリの呼び出しをメソッドごとにメトリクスへ記録する
+pub struct Instrumented<T: ?Sized> {
+    name: &'static str,
+    inner: Arc<T>,
+}
+
+impl<T: ?Sized> Instrumented<T> {
+    pub fn new(name: &'static str, inner: Arc<T>) -> Self {
+        Instrumented { name, inner }
+    }
+}
+
+#[async_trait]
+impl IUserRepository for Instrumented<dyn IUserRepository + Sync + Send> {
+    async fn list_id(&self) -> Result<Vec<UserId>, ServiceError> {
+        observe_repository_call(self.name, "list_id", self.inner.list_id()).await
+    }
+
+    async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError> {
+        observe_repository_call(self.name, "find_by_id", self.inner.find_by_id(user_id)).await
+    }
+
+    async fn find_by_screen_name(&self, screen_name: &String) -> Result<User, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_screen_name",
+            self.inner.find_by_screen_name(screen_name),
+        )
+        .await
+    }
+
+    async fn find_by_subject(&self, subject: &str) -> Result<User, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_subject",
+            self.inner.find_by_subject(subject),
+        )
+        .await
+    }
+
+    async fn find_by_subject_for_update(&self, subject: &str) -> Result<User, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_subject_for_update",
+            self.inner.find_by_subject_for_update(subject),
+        )
+        .await
+    }
+
+    async fn create(&self, user: User) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "create", self.inner.create(user)).await
+    }
+
+    async fn save(&self, user: User) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "save", self.inner.save(user)).await
+    }
+}
+
+#[async_trait]
+impl IGachaEventRepository for Instrumented<dyn IGachaEventRepository + Sync + Send> {
+    async fn find_by_user_type(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<GachaEvent, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_type",
+            self.inner.find_by_user_type(user_id, gacha_type),
+        )
+        .await
+    }
+
+    async fn save(&self, event: GachaEvent) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "save", self.inner.save(event)).await
+    }
+}
+
+#[async_trait]
+impl IGachaStateRepository for Instrumented<dyn IGachaStateRepository + Sync + Send> {
+    async fn find_by_user_type(
+        &self,
+        user_id: &UserId,
+        gacha_type: &GachaType,
+    ) -> Result<GachaState, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_type",
+            self.inner.find_by_user_type(user_id, gacha_type),
+        )
+        .await
+    }
+
+    async fn conditional_save(
+        &self,
+        state: GachaState,
+        prev: Option<GachaState>,
+    ) -> Result<(), ServiceError> {
+        observe_repository_call(
+            self.name,
+            "conditional_save",
+            self.inner.conditional_save(state, prev),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl IPendingGachaEventRepository for Instrumented<dyn IPendingGachaEventRepository + Sync + Send> {
+    async fn find_by_user_id(&self, user_id: &UserId) -> Result<Vec<GachaEvent>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_id",
+            self.inner.find_by_user_id(user_id),
+        )
+        .await
+    }
+
+    async fn create(&self, event: GachaEvent) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "create", self.inner.create(event)).await
+    }
+
+    async fn delete(&self, event_id: &GachaEventId) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "delete", self.inner.delete(event_id)).await
+    }
+}
+
+#[async_trait]
+impl IGiftRepository for Instrumented<dyn IGiftRepository + Sync + Send> {
+    async fn find_by_id(&self, gift_id: &GiftId, user_id: &UserId) -> Result<Gift, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_id",
+            self.inner.find_by_id(gift_id, user_id),
+        )
+        .await
+    }
+
+    async fn find_by_user_id_status(
+        &self,
+        user_id: &UserId,
+        status: GiftStatus,
+    ) -> Result<Vec<Gift>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_id_status",
+            self.inner.find_by_user_id_status(user_id, status),
+        )
+        .await
+    }
+
+    async fn create(&self, gift: Gift) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "create", self.inner.create(gift)).await
+    }
+
+    async fn save_status(
+        &self,
+        gift_id: GiftId,
+        user_id: UserId,
+        status: GiftStatus,
+    ) -> Result<(), ServiceError> {
+        observe_repository_call(
+            self.name,
+            "save_status",
+            self.inner.save_status(gift_id, user_id, status),
+        )
+        .await
+    }
+
+    async fn create_for(
+        &self,
+        gift: Gift,
+        users: Vec<UserId>,
+        status: GiftStatus,
+    ) -> Result<(), ServiceError> {
+        observe_repository_call(
+            self.name,
+            "create_for",
+            self.inner.create_for(gift, users, status),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl IJankenEventRepository for Instrumented<dyn IJankenEventRepository + Sync + Send> {
+    async fn find_by_user_id_status(
+        &self,
+        user_id: &UserId,
+        status: JankenStatus,
+    ) -> Result<Vec<JankenEvent>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_id_status",
+            self.inner.find_by_user_id_status(user_id, status),
+        )
+        .await
+    }
+
+    async fn find_by_user_id(
+        &self,
+        user_id: &UserId,
+        limit: i32,
+    ) -> Result<Vec<JankenEvent>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_id",
+            self.inner.find_by_user_id(user_id, limit),
+        )
+        .await
+    }
+
+    async fn scan_by_status(
+        &self,
+        status: JankenStatus,
+        limit: i32,
+    ) -> Result<Vec<JankenEvent>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "scan_by_status",
+            self.inner.scan_by_status(status, limit),
+        )
+        .await
+    }
+
+    async fn create(&self, janken_event: JankenEvent) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "create", self.inner.create(janken_event)).await
+    }
+
+    async fn save(&self, janken_event: JankenEvent) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "save", self.inner.save(janken_event)).await
+    }
+
+    async fn save_all(&self, janken_events: Vec<JankenEvent>) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "save_all", self.inner.save_all(janken_events)).await
+    }
+}
+
+#[async_trait]
+impl IPointEventRepository for Instrumented<dyn IPointEventRepository + Sync + Send> {
+    async fn find_by_id(&self, user_id: &UserId) -> Result<PointEvent, ServiceError> {
+        observe_repository_call(self.name, "find_by_id", self.inner.find_by_id(user_id)).await
+    }
+
+    async fn save(&self, event: PointEvent) -> Result<(), ServiceError> {
+        observe_repository_call(self.name, "save", self.inner.save(event)).await
+    }
+}
+
+#[async_trait]
+impl IRankingRepository for Instrumented<dyn IRankingRepository + Sync + Send> {
+    async fn list_top_points(
+        &self,
+        limit: u64,
+    ) -> Result<Vec<PointDiffRankingRecord>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "list_top_points",
+            self.inner.list_top_points(limit),
+        )
+        .await
+    }
+
+    async fn list_top_point_diffs(
+        &self,
+        limit: u64,
+    ) -> Result<Vec<PointDiffRankingRecord>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "list_top_point_diffs",
+            self.inner.list_top_point_diffs(limit),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl IPointTransactionRepository for Instrumented<dyn IPointTransactionRepository + Sync + Send> {
+    async fn find_by_user_id(
+        &self,
+        user_id: &UserId,
+        limit: i32,
+    ) -> Result<Vec<PointTransaction>, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "find_by_user_id",
+            self.inner.find_by_user_id(user_id, limit),
+        )
+        .await
+    }
+
+    async fn apply(&self, transaction: PointTransaction) -> Result<PointTransaction, ServiceError> {
+        observe_repository_call(self.name, "apply", self.inner.apply(transaction)).await
+    }
+
+    async fn conditional_apply(
+        &self,
+        user: User,
+        transaction: PointTransaction,
+        daily_gacha_timestamp: UnixTime,
+    ) -> Result<PointTransaction, ServiceError> {
+        observe_repository_call(
+            self.name,
+            "conditional_apply",
+            self.inner
+                .conditional_apply(user, transaction, daily_gacha_timestamp),
+        )
+        .await
+    }
+}
@@ -67,6 +67,46 @@ impl JankenEventRepository {
     pub fn new(pool: Arc<ConnPool>) -> Self {
         JankenEventRepository { pool }
     }
+
+    // 値はクエリに埋め込まずにパラメータで渡す
+    fn user_id_status_query(
+        user_id: &UserId,
+        status: &JankenStatus,
+    ) -> (debil::QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            debil::QueryBuilder::new().filter(format!(
+                "{} = :user_id and {} = :status",
+                accessor!(JankenEventRecord::user_id),
+                accessor!(JankenEventRecord::status),
+            )),
+            vec![
+                ("user_id", MySQLValue::serialize(user_id.0.clone())),
+                ("status", MySQLValue::serialize(status.to_string())),
+            ],
+        )
+    }
+
+    fn user_id_query(user_id: &UserId) -> (debil::QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            debil::QueryBuilder::new().filter(format!(
+                "{} = :user_id",
+                accessor!(JankenEventRecord::user_id),
+            )),
+            vec![("user_id", MySQLValue::serialize(user_id.0.clone()))],
+        )
+    }
+
+    fn status_query(
+        status: &JankenStatus,
+    ) -> (debil::QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            debil::QueryBuilder::new().filter(format!(
+                "{} = :status",
+                accessor!(JankenEventRecord::status)
+            )),
+            vec![("status", MySQLValue::serialize(status.to_string()))],
+        )
+    }
 }
 
 #[async_trait]
@@ -76,21 +116,15 @@ impl IJankenEventRepository for JankenEventRepository {
         user_id: &UserId,
         status: JankenStatus,
     ) -> Result<Vec<JankenEvent>, ServiceError> {
+        let (builder, params) = JankenEventRepository::user_id_status_query(user_id, &status);
         let mut conn = self.pool.get_conn().await?;
         let records = conn
-            .load_with::<JankenEventRecord>(
-                debil::QueryBuilder::new()
-                    .filter(format!(
-                        "{} = '{}' and {} = '{}'",
-                        accessor!(JankenEventRecord::user_id),
-                        user_id.0,
-                        accessor!(JankenEventRecord::status),
-                        status.to_string()
-                    ))
-                    .order_by(
-                        accessor!(JankenEventRecord::created_at),
-                        Ordering::Descending,
-                    ),
+            .load_with_params::<JankenEventRecord>(
+                builder.order_by(
+                    accessor!(JankenEventRecord::created_at),
+                    Ordering::Descending,
+                ),
+                params,
             )
             .await?;
 
@@ -102,20 +136,17 @@ impl IJankenEventRepository for JankenEventRepository {
         user_id: &UserId,
         limit: i32,
     ) -> Result<Vec<JankenEvent>, ServiceError> {
+        let (builder, params) = JankenEventRepository::user_id_query(user_id);
         let mut conn = self.pool.get_conn().await?;
         let records = conn
-            .load_with::<JankenEventRecord>(
-                debil::QueryBuilder::new()
-                    .filter(format!(
-                        "{} = '{}'",
-                        accessor!(JankenEventRecord::user_id),
-                        user_id.0,
-                    ))
+            .load_with_params::<JankenEventRecord>(
+                builder
                     .order_by(
                         accessor!(JankenEventRecord::created_at),
                         Ordering::Descending,
                     )
                     .limit(limit),
+                params,
             )
             .await?;
 
@@ -127,17 +158,10 @@ impl IJankenEventRepository for JankenEventRepository {
         status: JankenStatus,
         limit: i32,
     ) -> Result<Vec<JankenEvent>, ServiceError> {
+        let (builder, params) = JankenEventRepository::status_query(&status);
         let mut conn = self.pool.get_conn().await?;
         let records = conn
-            .load_with::<JankenEventRecord>(
-                debil::QueryBuilder::new()
-                    .filter(format!(
-                        "{} = '{}'",
-                        accessor!(JankenEventRecord::status),
-                        status.to_string()
-                    ))
-                    .limit(limit),
-            )
+            .load_with_params::<JankenEventRecord>(builder.limit(limit), params)
             .await?;
 
         records.into_iter().map(|rec| rec.into_model()).collect()
@@ -183,12 +207,15 @@ pub mod janken_event_repository_mock {
     use super::*;
     use crate::domain::interface::IJankenEventRepository;
     use crate::domain::model::{JankenStatus, UserId};
+    use crate::shutdown::ShutdownTrigger;
     use std::sync::Mutex;
 
     pub struct JankenEventRepositoryMock {
         pub events: Vec<JankenEvent>,
         pub created: Arc<Mutex<Vec<JankenEvent>>>,
         pub saved: Arc<Mutex<Vec<JankenEvent>>>,
+        // scan_by_statusが呼ばれたら止める合図を出す, バッチの途中で止められたときのテスト用
+        pub shutdown_on_scan: Mutex<Option<ShutdownTrigger>>,
     }
 
     impl JankenEventRepositoryMock {
@@ -197,8 +224,15 @@ pub mod janken_event_repository_mock {
                 events,
                 created: Arc::new(Mutex::new(Vec::new())),
                 saved: Arc::new(Mutex::new(Vec::new())),
+                shutdown_on_scan: Mutex::new(None),
             }
         }
+
+        pub fn with_shutdown_on_scan(self, trigger: ShutdownTrigger) -> Self {
+            *self.shutdown_on_scan.lock().unwrap() = Some(trigger);
+
+            self
+        }
     }
 
     #[async_trait]
@@ -224,7 +258,11 @@ pub mod janken_event_repository_mock {
             status: JankenStatus,
             limit: i32,
         ) -> Result<Vec<JankenEvent>, ServiceError> {
-            unimplemented!()
+            if let Some(trigger) = self.shutdown_on_scan.lock().unwrap().take() {
+                trigger.trigger();
+            }
+
+            Ok(self.events.clone())
         }
 
         async fn create(&self, janken_event: JankenEvent) -> Result<(), ServiceError> {
@@ -248,3 +286,35 @@ pub mod janken_event_repository_mock {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::{assert_bound, select_query, INJECTION_PAYLOADS};
+
+    #[test]
+    fn filters_should_bind_parameters() {
+        for payload in INJECTION_PAYLOADS {
+            let user_id = UserId(payload.to_string());
+
+            let (builder, params) =
+                JankenEventRepository::user_id_status_query(&user_id, &JankenStatus::Ready);
+            let query = select_query::<JankenEventRecord>(builder);
+            assert!(query.ends_with(
+                "WHERE janken_event.user_id = :user_id and janken_event.status = :status"
+            ));
+            assert_bound(&query, &params, "user_id", payload);
+            assert_bound(&query, &params, "status", "ready");
+
+            let (builder, params) = JankenEventRepository::user_id_query(&user_id);
+            let query = select_query::<JankenEventRecord>(builder);
+            assert!(query.ends_with("WHERE janken_event.user_id = :user_id"));
+            assert_bound(&query, &params, "user_id", payload);
+        }
+
+        let (builder, params) = JankenEventRepository::status_query(&JankenStatus::Timeout);
+        let query = select_query::<JankenEventRecord>(builder);
+        assert!(query.ends_with("WHERE janken_event.status = :status"));
+        assert_bound(&query, &params, "status", "timeout");
+    }
+}
@@ -1,12 +1,131 @@
 use crate::domain::model::{AuthUser, Role};
-use crate::wrapper::error::ServiceError;
+use crate::wrapper::error::{ErrorCode, ServiceError};
 use biscuit::errors::Error;
 use serde::*;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 
 impl From<biscuit::errors::Error> for ServiceError {
     fn from(err: Error) -> Self {
-        ServiceError::unauthorized(failure::Error::from_boxed_compat(Box::new(err)))
+        use biscuit::errors::ValidationError::*;
+
+        let code = match &err {
+            Error::ValidationError(KidMissing) | Error::ValidationError(KeyNotFound) => {
+                ErrorCode::UnknownSigningKey
+            }
+            Error::ValidationError(Expired(_)) | Error::ValidationError(TooOld(_)) => {
+                ErrorCode::TokenExpired
+            }
+            Error::ValidationError(NotYetValid(_)) => ErrorCode::TokenNotYetValid,
+            Error::ValidationError(InvalidIssuer(_)) => ErrorCode::InvalidIssuer,
+            Error::ValidationError(InvalidAudience(_)) => ErrorCode::InvalidAudience,
+            Error::ValidationError(MissingRequiredClaims(_)) => ErrorCode::MissingRequiredClaim,
+            _ => ErrorCode::InvalidToken,
+        };
+        let details = match &err {
+            Error::ValidationError(MissingRequiredClaims(claims)) => {
+                Some(serde_json::json!({ "claims": claims }))
+            }
+            _ => None,
+        };
+
+        let mut err =
+            ServiceError::unauthorized(code, failure::Error::from_boxed_compat(Box::new(err)));
+        err.details = details;
+
+        err
+    }
+}
+
+// JWTの検証条件
+// issuers, audiencesが空のときはその値を検証しない
+#[derive(Clone, Default)]
+pub struct JWTValidation {
+    pub issuers: Vec<String>,
+    pub audiences: Vec<String>,
+    // exp, nbf, iatの検証で許容する時計のずれ
+    pub leeway: Duration,
+    pub required_claims: biscuit::ClaimPresenceOptions,
+}
+
+impl JWTValidation {
+    // "exp,sub"のようなカンマ区切りのクレーム名から必須クレームを作る
+    pub fn parse_required_claims(
+        names: &str,
+    ) -> Result<biscuit::ClaimPresenceOptions, ServiceError> {
+        let mut options = biscuit::ClaimPresenceOptions::default();
+        for name in names
+            .split(',')
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+        {
+            let presence = match name {
+                "iat" => &mut options.issued_at,
+                "nbf" => &mut options.not_before,
+                "exp" => &mut options.expiry,
+                "iss" => &mut options.issuer,
+                "aud" => &mut options.audience,
+                "sub" => &mut options.subject,
+                "jti" => &mut options.id,
+                _ => {
+                    return Err(ServiceError::bad_request(
+                        ErrorCode::InvalidConfiguration,
+                        failure::err_msg(format!("Unsupported claim: {}", name)),
+                    ))
+                }
+            };
+            *presence = biscuit::Presence::Required;
+        }
+
+        Ok(options)
+    }
+
+    fn validate(&self, claims: &biscuit::RegisteredClaims) -> Result<(), ServiceError> {
+        let leeway = chrono::Duration::from_std(self.leeway).map_err(|err| {
+            ServiceError::internal_server_error(
+                ErrorCode::InvalidConfiguration,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        })?;
+        claims
+            .validate(biscuit::ValidationOptions {
+                claim_presence_options: self.required_claims,
+                temporal_options: biscuit::TemporalOptions {
+                    epsilon: leeway,
+                    now: None,
+                },
+                ..Default::default()
+            })
+            .map_err(Error::ValidationError)?;
+
+        // biscuitは1つの値としか比較できないので、候補のどれかに一致するかをここで見る
+        if !self.issuers.is_empty() {
+            let issuer = claims.issuer.as_ref().map(|iss| iss.to_string());
+            if !issuer.iter().any(|iss| self.issuers.contains(iss)) {
+                return Err(ServiceError::unauthorized(
+                    ErrorCode::InvalidIssuer,
+                    failure::err_msg(format!("Invalid issuer: {:?}", issuer)),
+                ));
+            }
+        }
+
+        if !self.audiences.is_empty() {
+            let audience = claims
+                .audience
+                .iter()
+                .flat_map(|aud| aud.iter())
+                .map(|aud| aud.to_string())
+                .collect::<Vec<_>>();
+            if !audience.iter().any(|aud| self.audiences.contains(aud)) {
+                return Err(ServiceError::unauthorized(
+                    ErrorCode::InvalidAudience,
+                    failure::err_msg(format!("Invalid audience: {:?}", audience)),
+                ));
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -17,40 +136,155 @@ struct CustomPayload {
     roles: Option<Vec<String>>,
 }
 
+// JWKSの取得元
+// IdPが鍵をローテーションしても再デプロイせずに追従できるように取り直す
+struct JWKSource {
+    url: String,
+    client: reqwest::Client,
+    // 未知のkidによる取り直しはこの間隔より短くは行わない
+    min_refresh_interval: Duration,
+    last_refreshed: Mutex<Instant>,
+}
+
 pub struct JWTHandler {
-    public_key: Arc<biscuit::jwk::JWKSet<biscuit::Empty>>,
+    public_key: RwLock<Arc<biscuit::jwk::JWKSet<biscuit::Empty>>>,
+    source: Option<JWKSource>,
+    validation: JWTValidation,
 }
 
 impl JWTHandler {
+    // 鍵を固定して使う, 取り直しは行わない
     pub fn new(public_key: Arc<biscuit::jwk::JWKSet<biscuit::Empty>>) -> Self {
-        JWTHandler { public_key }
+        JWTHandler {
+            public_key: RwLock::new(public_key),
+            source: None,
+            validation: Default::default(),
+        }
+    }
+
+    pub fn with_validation(mut self, validation: JWTValidation) -> Self {
+        self.validation = validation;
+
+        self
     }
 
-    pub async fn load_from_jwk(jwk_url: &str) -> biscuit::jwk::JWKSet<biscuit::Empty> {
-        reqwest::get(jwk_url).await.unwrap().json().await.unwrap()
+    pub async fn from_url(
+        jwk_url: &str,
+        min_refresh_interval: Duration,
+    ) -> Result<Self, ServiceError> {
+        let client = reqwest::Client::new();
+        let public_key = JWTHandler::load_from_jwk(&client, jwk_url).await?;
+
+        let mut handler = JWTHandler::new(Arc::new(public_key));
+        handler.source = Some(JWKSource {
+            url: jwk_url.to_string(),
+            client,
+            min_refresh_interval,
+            last_refreshed: Mutex::new(Instant::now()),
+        });
+
+        Ok(handler)
     }
 
-    fn get_key_from_jwk(
-        &self,
-        kid: &str,
-    ) -> (biscuit::jws::Secret, biscuit::jwa::SignatureAlgorithm) {
-        let key = self.public_key.find(kid).unwrap().clone();
-
-        match key.algorithm {
-            biscuit::jwk::AlgorithmParameters::RSA(params) => (
-                params.jws_public_key_secret(),
-                biscuit::jwa::SignatureAlgorithm::RS256,
-            ),
-            _ => unimplemented!(),
+    async fn load_from_jwk(
+        client: &reqwest::Client,
+        jwk_url: &str,
+    ) -> Result<biscuit::jwk::JWKSet<biscuit::Empty>, ServiceError> {
+        let map_err = |err: reqwest::Error| {
+            ServiceError::service_unavailable(
+                ErrorCode::ExternalServiceUnavailable,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        };
+
+        client
+            .get(jwk_url)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(map_err)?
+            .json()
+            .await
+            .map_err(map_err)
+    }
+
+    fn current_key(&self) -> Arc<biscuit::jwk::JWKSet<biscuit::Empty>> {
+        self.public_key.read().unwrap().clone()
+    }
+
+    // JWKSを取り直す
+    pub async fn refresh(&self) -> Result<(), ServiceError> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+
+        let mut last_refreshed = source.last_refreshed.lock().await;
+        self.refresh_with(source, &mut last_refreshed).await
+    }
+
+    // 直近に取り直したばかりのときは何もしない
+    // 同時に来たリクエストが揃ってIdPに問い合わせないようにロックを取ったまま取得する
+    async fn refresh_if_stale(&self) -> Result<(), ServiceError> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+
+        let mut last_refreshed = source.last_refreshed.lock().await;
+        if last_refreshed.elapsed() < source.min_refresh_interval {
+            return Ok(());
         }
+
+        self.refresh_with(source, &mut last_refreshed).await
     }
 
-    pub fn authorize(&self, auth_token: &str) -> Result<AuthUser, ServiceError> {
+    async fn refresh_with(
+        &self,
+        source: &JWKSource,
+        last_refreshed: &mut Instant,
+    ) -> Result<(), ServiceError> {
+        let public_key = JWTHandler::load_from_jwk(&source.client, &source.url).await?;
+        *self.public_key.write().unwrap() = Arc::new(public_key);
+        *last_refreshed = Instant::now();
+
+        Ok(())
+    }
+
+    // 一定間隔でJWKSを取り直すタスクを起動する
+    // 取得に失敗しても手元の鍵をそのまま使い続ける
+    pub fn spawn_refresh_task(self: &Arc<Self>, interval: Duration) {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let mut timer =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                timer.tick().await;
+                if let Err(err) = handler.refresh().await {
+                    warn!("Failed to refresh JWKS: {:?}", err);
+                }
+            }
+        });
+    }
+
+    pub async fn authorize(&self, auth_token: &str) -> Result<AuthUser, ServiceError> {
         let token = auth_token.split("Bearer ").collect::<Vec<&str>>();
         if token.len() != 2 {
-            return Err(ServiceError::unauthorized(failure::err_msg(
-                "access denied",
-            )));
+            return Err(ServiceError::unauthorized(
+                ErrorCode::InvalidAuthorizationHeader,
+                failure::err_msg("access denied"),
+            ));
+        }
+
+        // 知らないkidで署名されていたら鍵がローテーションされた可能性があるので取り直す
+        let jwt = biscuit::JWT::<CustomPayload, biscuit::Empty>::new_encoded(token[1]);
+        let header = jwt.unverified_header()?;
+        if let Some(kid) = header.registered.key_id {
+            if self.current_key().find(&kid).is_none() {
+                if let Err(err) = self.refresh_if_stale().await {
+                    warn!("Failed to refresh JWKS: {:?}", err);
+                }
+            }
         }
 
         self.verify(token[1])
@@ -58,16 +292,19 @@ impl JWTHandler {
 
     fn verify(&self, jwt: &str) -> Result<AuthUser, ServiceError> {
         let jwt = biscuit::JWT::<CustomPayload, biscuit::Empty>::new_encoded(jwt);
-        let jwt = jwt.decode_with_jwks(self.public_key.as_ref())?;
-        jwt.validate(Default::default())?;
-
+        let jwt = jwt.decode_with_jwks(self.current_key().as_ref())?;
         let payload = jwt.payload()?.clone();
+        self.validation.validate(&payload.registered)?;
+
         Ok(AuthUser {
             subject: payload
                 .registered
                 .subject
                 .as_ref()
-                .ok_or(ServiceError::bad_request(failure::err_msg("no subject")))?
+                .ok_or(ServiceError::unauthorized(
+                    ErrorCode::MissingSubject,
+                    failure::err_msg("no subject"),
+                ))?
                 .to_string(),
             roles: payload
                 .private
@@ -80,3 +317,259 @@ impl JWTHandler {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::test_harness::{test_jwks, TestSigner};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // IdPのJWKSエンドポイントの代わりにローカルでJWKSを返すサーバー
+    struct JWKSServer {
+        url: String,
+        body: Arc<std::sync::Mutex<String>>,
+        hits: Arc<AtomicUsize>,
+    }
+
+    impl JWKSServer {
+        fn start(body: &str) -> Self {
+            let body = Arc::new(std::sync::Mutex::new(body.to_string()));
+            let hits = Arc::new(AtomicUsize::new(0));
+
+            let make_service = {
+                let body = body.clone();
+                let hits = hits.clone();
+                hyper::service::make_service_fn(move |_| {
+                    let body = body.clone();
+                    let hits = hits.clone();
+                    async move {
+                        Ok::<_, hyper::Error>(hyper::service::service_fn(move |_| {
+                            hits.fetch_add(1, Ordering::SeqCst);
+                            let body = body.lock().unwrap().clone();
+                            async move {
+                                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(body)))
+                            }
+                        }))
+                    }
+                })
+            };
+            let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+            let url = format!("http://{}/.well-known/jwks.json", server.local_addr());
+            tokio::spawn(server);
+
+            JWKSServer { url, body, hits }
+        }
+
+        fn set_body(&self, body: &str) {
+            *self.body.lock().unwrap() = body.to_string();
+        }
+
+        fn hits(&self) -> usize {
+            self.hits.load(Ordering::SeqCst)
+        }
+    }
+
+    const EMPTY_JWKS: &str = r#"{"keys":[]}"#;
+
+    fn rotated_jwks() -> String {
+        serde_json::to_string(&test_jwks()).unwrap()
+    }
+
+    fn bearer(token: &str) -> String {
+        format!("Bearer {}", token)
+    }
+
+    #[tokio::test]
+    async fn authorize_should_refresh_on_unknown_kid() -> Result<(), ServiceError> {
+        let server = JWKSServer::start(EMPTY_JWKS);
+        let handler = JWTHandler::from_url(&server.url, Duration::from_secs(0)).await?;
+
+        // 鍵がローテーションされた
+        server.set_body(&rotated_jwks());
+
+        let token = TestSigner::new().sign("auth0|me", Vec::new());
+        let user = handler.authorize(&bearer(&token)).await?;
+        assert_eq!(user.subject, "auth0|me");
+        assert_eq!(server.hits(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authorize_should_not_refresh_too_often() -> Result<(), ServiceError> {
+        let server = JWKSServer::start(EMPTY_JWKS);
+        let handler = JWTHandler::from_url(&server.url, Duration::from_secs(3600)).await?;
+
+        let token = TestSigner::new().sign("auth0|me", Vec::new());
+        for _ in 0..3 {
+            let err = handler
+                .authorize(&bearer(&token))
+                .await
+                .err()
+                .expect("expect error");
+            assert_eq!(err.status_code, http::StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(server.hits(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_task_should_pick_up_rotated_keys() -> Result<(), ServiceError> {
+        let server = JWKSServer::start(EMPTY_JWKS);
+        let handler = Arc::new(JWTHandler::from_url(&server.url, Duration::from_secs(3600)).await?);
+        handler.spawn_refresh_task(Duration::from_millis(20));
+
+        server.set_body(&rotated_jwks());
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+
+        let token = TestSigner::new().sign("auth0|me", Vec::new());
+        handler.authorize(&bearer(&token)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_should_keep_current_keys() -> Result<(), ServiceError> {
+        let server = JWKSServer::start(&rotated_jwks());
+        let handler = JWTHandler::from_url(&server.url, Duration::from_secs(0)).await?;
+
+        server.set_body("not a json");
+        assert_eq!(
+            handler
+                .refresh()
+                .await
+                .expect_err("expect error")
+                .status_code,
+            http::StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let token = TestSigner::new().sign("auth0|me", Vec::new());
+        handler.authorize(&bearer(&token)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn from_url_should_fail_without_panic() {
+        let server = JWKSServer::start("not a json");
+        let err = JWTHandler::from_url(&server.url, Duration::from_secs(0))
+            .await
+            .err()
+            .expect("expect error");
+        assert_eq!(err.status_code, http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    const ISSUER: &str = "https://myuon.auth0.com/";
+    const AUDIENCE: &str = "https://jitome.ramda.io/api";
+
+    fn validating_handler() -> JWTHandler {
+        JWTHandler::new(Arc::new(test_jwks())).with_validation(JWTValidation {
+            issuers: vec![ISSUER.to_string()],
+            audiences: vec![AUDIENCE.to_string()],
+            leeway: Duration::from_secs(60),
+            required_claims: JWTValidation::parse_required_claims("exp,sub").unwrap(),
+        })
+    }
+
+    fn claims(issuer: &str, audience: &str) -> biscuit::RegisteredClaims {
+        biscuit::RegisteredClaims {
+            issuer: Some(std::str::FromStr::from_str(issuer).unwrap()),
+            audience: Some(biscuit::SingleOrMultiple::Multiple(vec![
+                std::str::FromStr::from_str("https://myuon.auth0.com/userinfo").unwrap(),
+                std::str::FromStr::from_str(audience).unwrap(),
+            ])),
+            subject: Some(std::str::FromStr::from_str("auth0|me").unwrap()),
+            expiry: Some(From::from(chrono::Utc::now() + chrono::Duration::hours(1))),
+            ..Default::default()
+        }
+    }
+
+    async fn authorize_error(
+        handler: &JWTHandler,
+        claims: biscuit::RegisteredClaims,
+    ) -> ServiceError {
+        let token = TestSigner::new().sign_claims(claims, Vec::new());
+        let err = handler
+            .authorize(&bearer(&token))
+            .await
+            .err()
+            .expect("expect error");
+        assert_eq!(err.status_code, http::StatusCode::UNAUTHORIZED);
+
+        err
+    }
+
+    #[tokio::test]
+    async fn authorize_should_accept_expected_issuer_and_audience() -> Result<(), ServiceError> {
+        let token = TestSigner::new().sign_claims(claims(ISSUER, AUDIENCE), Vec::new());
+        let user = validating_handler().authorize(&bearer(&token)).await?;
+        assert_eq!(user.subject, "auth0|me");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authorize_should_reject_other_issuer_or_audience() {
+        let handler = validating_handler();
+
+        let err = authorize_error(&handler, claims("https://other.auth0.com/", AUDIENCE)).await;
+        assert_eq!(err.code, ErrorCode::InvalidIssuer);
+
+        let err = authorize_error(&handler, claims(ISSUER, "https://other.example.com/api")).await;
+        assert_eq!(err.code, ErrorCode::InvalidAudience);
+    }
+
+    #[tokio::test]
+    async fn authorize_should_allow_clock_skew_within_leeway() -> Result<(), ServiceError> {
+        let handler = validating_handler();
+
+        let mut within = claims(ISSUER, AUDIENCE);
+        within.expiry = Some(From::from(
+            chrono::Utc::now() - chrono::Duration::seconds(30),
+        ));
+        let token = TestSigner::new().sign_claims(within, Vec::new());
+        handler.authorize(&bearer(&token)).await?;
+
+        let mut expired = claims(ISSUER, AUDIENCE);
+        expired.expiry = Some(From::from(
+            chrono::Utc::now() - chrono::Duration::seconds(120),
+        ));
+        let err = authorize_error(&handler, expired).await;
+        assert_eq!(err.code, ErrorCode::TokenExpired);
+
+        let mut not_yet = claims(ISSUER, AUDIENCE);
+        not_yet.not_before = Some(From::from(
+            chrono::Utc::now() + chrono::Duration::seconds(120),
+        ));
+        let err = authorize_error(&handler, not_yet).await;
+        assert_eq!(err.code, ErrorCode::TokenNotYetValid);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authorize_should_require_configured_claims() {
+        let mut no_expiry = claims(ISSUER, AUDIENCE);
+        no_expiry.expiry = None;
+        let err = authorize_error(&validating_handler(), no_expiry).await;
+        assert_eq!(err.code, ErrorCode::MissingRequiredClaim);
+        assert_eq!(err.details, Some(serde_json::json!({ "claims": ["exp"] })));
+    }
+
+    #[tokio::test]
+    async fn authorize_should_reject_token_without_subject() {
+        // subを必須にしていなくてもユーザーを特定できないので認証エラー
+        let handler = JWTHandler::new(Arc::new(test_jwks()));
+        let mut no_subject = claims(ISSUER, AUDIENCE);
+        no_subject.subject = None;
+        let err = authorize_error(&handler, no_subject).await;
+        assert_eq!(err.code, ErrorCode::MissingSubject);
+    }
+
+    #[test]
+    fn parse_required_claims_should_reject_unknown_claim() {
+        assert!(JWTValidation::parse_required_claims("exp, sub").is_ok());
+        assert!(JWTValidation::parse_required_claims("exp,unknown").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::domain::interface::IPendingGachaEventRepository;
+use crate::domain::model::{GachaEvent, GachaEventId, GachaType, UserId};
+use crate::infra::ConnPool;
+use crate::wrapper::error::ServiceError;
+use crate::wrapper::unixtime::UnixTime;
+use async_trait::async_trait;
+use debil::*;
+use debil_mysql::*;
+use std::sync::Arc;
+
+// DynamoDBに書き込めたら消すので, 普段はほとんど空のテーブル
+#[derive(Table, Clone, Accessor)]
+#[sql(
+    table_name = "pending_gacha_event",
+    sql_type = "MySQLValue",
+    primary_key = "id"
+)]
+pub struct PendingGachaEventRecord {
+    #[sql(size = 100)]
+    id: String,
+    #[sql(size = 100)]
+    user_id: String,
+    #[sql(size = 100)]
+    gacha_type: String,
+    created_at: i64,
+    // GachaResultのJSON
+    result: Option<String>,
+    pity_count: u32,
+}
+
+impl PendingGachaEventRecord {
+    pub fn from_model(model: GachaEvent) -> Result<Self, ServiceError> {
+        Ok(PendingGachaEventRecord {
+            id: model.id.0,
+            user_id: model.user_id.0,
+            gacha_type: model.gacha_type.to_string(),
+            created_at: model.created_at.0,
+            result: match model.result {
+                Some(result) => Some(serde_json::to_string(&result)?),
+                None => None,
+            },
+            pity_count: model.pity_count,
+        })
+    }
+
+    pub fn into_model(self) -> Result<GachaEvent, ServiceError> {
+        Ok(GachaEvent {
+            id: GachaEventId(self.id),
+            user_id: UserId(self.user_id),
+            gacha_type: GachaType::new(&self.gacha_type),
+            created_at: UnixTime(self.created_at),
+            result: match self.result {
+                Some(result) => Some(serde_json::from_str(&result)?),
+                None => None,
+            },
+            pity_count: self.pity_count,
+        })
+    }
+}
+
+pub struct PendingGachaEventRepository {
+    pool: Arc<ConnPool>,
+}
+
+impl PendingGachaEventRepository {
+    pub fn new(pool: Arc<ConnPool>) -> Self {
+        PendingGachaEventRepository { pool }
+    }
+
+    fn user_id_query(user_id: &UserId) -> (QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            QueryBuilder::new().filter(format!(
+                "{} = :user_id",
+                accessor!(PendingGachaEventRecord::user_id)
+            )),
+            vec![("user_id", MySQLValue::serialize(user_id.0.clone()))],
+        )
+    }
+}
+
+#[async_trait]
+impl IPendingGachaEventRepository for PendingGachaEventRepository {
+    async fn find_by_user_id(&self, user_id: &UserId) -> Result<Vec<GachaEvent>, ServiceError> {
+        let (builder, params) = PendingGachaEventRepository::user_id_query(user_id);
+        let mut conn = self.pool.get_conn().await?;
+        let records = conn
+            .load_with_params::<PendingGachaEventRecord>(
+                builder.order_by(
+                    accessor!(PendingGachaEventRecord::created_at),
+                    Ordering::Ascending,
+                ),
+                params,
+            )
+            .await?;
+
+        records
+            .into_iter()
+            .map(PendingGachaEventRecord::into_model)
+            .collect()
+    }
+
+    async fn create(&self, event: GachaEvent) -> Result<(), ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.create(PendingGachaEventRecord::from_model(event)?)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, event_id: &GachaEventId) -> Result<(), ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.sql_exec(
+            format!(
+                "DELETE FROM {} WHERE {} = :id",
+                table_name::<PendingGachaEventRecord>(),
+                accessor!(PendingGachaEventRecord::id),
+            ),
+            debil::Params::<MySQLValue>(vec![(
+                "id".to_string(),
+                MySQLValue::serialize(event_id.0.clone()),
+            )]),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod pending_gacha_event_repository_mock {
+    use super::*;
+    use std::sync::Mutex;
+
+    pub struct PendingGachaEventRepositoryMock {
+        pub events: Arc<Mutex<Vec<GachaEvent>>>,
+    }
+
+    impl PendingGachaEventRepositoryMock {
+        pub fn new() -> Self {
+            PendingGachaEventRepositoryMock {
+                events: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IPendingGachaEventRepository for PendingGachaEventRepositoryMock {
+        async fn find_by_user_id(&self, user_id: &UserId) -> Result<Vec<GachaEvent>, ServiceError> {
+            Ok(self
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|e| &e.user_id == user_id)
+                .cloned()
+                .collect())
+        }
+
+        async fn create(&self, event: GachaEvent) -> Result<(), ServiceError> {
+            self.events.lock().unwrap().push(event);
+
+            Ok(())
+        }
+
+        async fn delete(&self, event_id: &GachaEventId) -> Result<(), ServiceError> {
+            self.events.lock().unwrap().retain(|e| &e.id != event_id);
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::{GachaResult, Rarity};
+    use crate::infra::{assert_bound, select_query, INJECTION_PAYLOADS};
+
+    #[test]
+    fn user_id_should_be_bound_as_parameter() {
+        for payload in INJECTION_PAYLOADS {
+            let (builder, params) =
+                PendingGachaEventRepository::user_id_query(&UserId(payload.to_string()));
+            let query = select_query::<PendingGachaEventRecord>(builder);
+
+            assert_bound(&query, &params, "user_id", payload);
+        }
+    }
+
+    #[test]
+    fn record_should_keep_result() -> Result<(), ServiceError> {
+        let event = GachaEvent {
+            id: GachaEventId("gacha-1".to_string()),
+            user_id: UserId("user-1".to_string()),
+            gacha_type: GachaType::new("premium"),
+            created_at: UnixTime(1600000000),
+            result: Some(GachaResult {
+                rarity: Rarity::Rare,
+                point: 20,
+                item: None,
+            }),
+            pity_count: 3,
+        };
+
+        assert_eq!(
+            PendingGachaEventRecord::from_model(event.clone())?.into_model()?,
+            event
+        );
+
+        Ok(())
+    }
+}
@@ -57,11 +57,13 @@ impl IPointEventRepository for PointEventRepository {
     async fn find_by_id(&self, user_id: &UserId) -> Result<PointEvent, ServiceError> {
         let mut conn = self.pool.get_conn().await?;
         let record = conn
-            .first_with::<PointEventRecord>(QueryBuilder::new().filter(format!(
-                "{} = '{}'",
-                accessor!(PointEventRecord::user_id),
-                user_id.0
-            )))
+            .first_with_params::<PointEventRecord>(
+                QueryBuilder::new().filter(format!(
+                    "{} = :user_id",
+                    accessor!(PointEventRecord::user_id)
+                )),
+                vec![("user_id", MySQLValue::serialize(user_id.0.clone()))],
+            )
             .await?;
 
         Ok(record.into_model())
@@ -0,0 +1,414 @@
+use crate::domain::interface::IPointTransactionRepository;
+use crate::domain::model::{
+    PointTransaction, PointTransactionId, PointTransactionReason, User, UserId,
+};
+use crate::infra::{ConnPool, UserRecord};
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use crate::wrapper::unixtime::UnixTime;
+use async_trait::async_trait;
+use debil::*;
+use debil_mysql::*;
+use std::sync::Arc;
+
+#[derive(Table, Clone, Accessor)]
+#[sql(
+    table_name = "point_transaction",
+    sql_type = "MySQLValue",
+    primary_key = "id"
+)]
+pub struct PointTransactionRecord {
+    #[sql(size = 100)]
+    id: String,
+    #[sql(size = 100)]
+    user_id: String,
+    #[sql(size = 50)]
+    reason: String,
+    #[sql(size = 100)]
+    source_event_id: Option<String>,
+    delta: i64,
+    balance_after: u64,
+    created_at: i64,
+}
+
+impl PointTransactionRecord {
+    pub fn from_model(model: PointTransaction) -> Self {
+        PointTransactionRecord {
+            id: model.id.0,
+            user_id: model.user_id.0,
+            reason: model.reason.to_string(),
+            source_event_id: model.source_event_id,
+            delta: model.delta,
+            balance_after: model.balance_after,
+            created_at: model.created_at.0,
+        }
+    }
+
+    pub fn into_model(self) -> PointTransaction {
+        PointTransaction {
+            id: PointTransactionId(self.id),
+            user_id: UserId(self.user_id),
+            reason: PointTransactionReason::from_str(&self.reason),
+            source_event_id: self.source_event_id,
+            delta: self.delta,
+            balance_after: self.balance_after,
+            created_at: UnixTime(self.created_at),
+        }
+    }
+}
+
+pub struct PointTransactionRepository {
+    pool: Arc<ConnPool>,
+}
+
+impl PointTransactionRepository {
+    pub fn new(pool: Arc<ConnPool>) -> Self {
+        PointTransactionRepository { pool }
+    }
+
+    // ledger導入前の残高を、まだ取引が1つもないユーザーについて記録する
+    // idはユーザーごとに決まるので, 同時に実行されても1人に1つしか記録されない
+    pub async fn record_opening_balances(conn: &mut DebilConn) -> Result<u64, ServiceError> {
+        let (query, params) = PointTransactionRepository::opening_balances_query();
+        let rows = conn
+            .sql_exec(query, debil::Params::<MySQLValue>(params))
+            .await?;
+
+        Ok(rows)
+    }
+
+    fn opening_balances_query() -> (String, Vec<(String, MySQLValue)>) {
+        (
+            format!(
+                "INSERT INTO {} ({}, {}, {}, {}, {}, {}, {}) SELECT CONCAT(:id_prefix, {}), {}, :reason, NULL, {}, {}, UNIX_TIMESTAMP() FROM {} WHERE {} > 0 AND {} NOT IN (SELECT DISTINCT {} FROM {}) ON DUPLICATE KEY UPDATE {} = {}",
+                table_name::<PointTransactionRecord>(),
+                accessor_name!(PointTransactionRecord::id),
+                accessor_name!(PointTransactionRecord::user_id),
+                accessor_name!(PointTransactionRecord::reason),
+                accessor_name!(PointTransactionRecord::source_event_id),
+                accessor_name!(PointTransactionRecord::delta),
+                accessor_name!(PointTransactionRecord::balance_after),
+                accessor_name!(PointTransactionRecord::created_at),
+                accessor_name!(UserRecord::id),
+                accessor_name!(UserRecord::id),
+                accessor_name!(UserRecord::point),
+                accessor_name!(UserRecord::point),
+                table_name::<UserRecord>(),
+                accessor_name!(UserRecord::point),
+                accessor_name!(UserRecord::id),
+                accessor_name!(PointTransactionRecord::user_id),
+                table_name::<PointTransactionRecord>(),
+                accessor_name!(PointTransactionRecord::id),
+                accessor_name!(PointTransactionRecord::id),
+            ),
+            vec![
+                (
+                    "id_prefix".to_string(),
+                    MySQLValue::serialize(format!("{}#", PointTransactionReason::OpeningBalance)),
+                ),
+                (
+                    "reason".to_string(),
+                    MySQLValue::serialize(PointTransactionReason::OpeningBalance.to_string()),
+                ),
+            ],
+        )
+    }
+
+    async fn apply_in_transaction(
+        &self,
+        transaction: PointTransaction,
+        daily_gacha: Option<(User, UnixTime)>,
+    ) -> Result<PointTransaction, ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.start_transaction().await?;
+
+        match PointTransactionRepository::apply_with_conn(&mut conn, transaction, daily_gacha).await
+        {
+            Ok(transaction) => {
+                conn.commit().await?;
+
+                Ok(transaction)
+            }
+            Err(err) => {
+                conn.rollback().await?;
+
+                Err(err)
+            }
+        }
+    }
+
+    // 残高を更新するUPDATE文, 値はクエリに埋め込まずにパラメータで渡す
+    fn apply_query(
+        transaction: &PointTransaction,
+        daily_gacha: Option<(User, UnixTime)>,
+    ) -> (String, Vec<(String, MySQLValue)>) {
+        // 残高が負になる更新は行わない
+        let mut query = format!(
+            "UPDATE {} SET {} = CAST({} AS SIGNED) + :delta",
+            table_name::<UserRecord>(),
+            accessor!(UserRecord::point),
+            accessor!(UserRecord::point),
+        );
+        let mut conditions = format!(
+            "WHERE {} = :user_id AND CAST({} AS SIGNED) + :delta >= 0",
+            accessor!(UserRecord::id),
+            accessor!(UserRecord::point),
+        );
+        let mut params = vec![
+            (
+                "delta".to_string(),
+                MySQLValue::serialize(transaction.delta),
+            ),
+            (
+                "user_id".to_string(),
+                MySQLValue::serialize(transaction.user_id.0.clone()),
+            ),
+        ];
+
+        if let Some((next, current)) = daily_gacha {
+            // last_tried_daily_gachaがNULLも許容する必要がある
+            // 連続記録もタイムスタンプと一緒に更新する
+            query = format!(
+                "{}, {} = :next_daily_gacha_timestamp, {} = :daily_streak_current, {} = :daily_streak_longest, {} = :daily_streak_last_date",
+                query,
+                accessor!(UserRecord::last_tried_daily_gacha),
+                accessor!(UserRecord::daily_streak_current),
+                accessor!(UserRecord::daily_streak_longest),
+                accessor!(UserRecord::daily_streak_last_date),
+            );
+            conditions = format!(
+                "{} AND ({} = :daily_gacha_timestamp OR {} IS NULL)",
+                conditions,
+                accessor!(UserRecord::last_tried_daily_gacha),
+                accessor!(UserRecord::last_tried_daily_gacha),
+            );
+            params.push((
+                "next_daily_gacha_timestamp".to_string(),
+                MySQLValue::serialize(next.last_tried_daily_gacha.0),
+            ));
+            params.push((
+                "daily_streak_current".to_string(),
+                MySQLValue::serialize(next.daily_streak.current),
+            ));
+            params.push((
+                "daily_streak_longest".to_string(),
+                MySQLValue::serialize(next.daily_streak.longest),
+            ));
+            params.push((
+                "daily_streak_last_date".to_string(),
+                MySQLValue::serialize(next.daily_streak.last_date),
+            ));
+            params.push((
+                "daily_gacha_timestamp".to_string(),
+                MySQLValue::serialize(current.0),
+            ));
+        }
+
+        (format!("{} {}", query, conditions), params)
+    }
+
+    // daily_gachaには(デイリーガチャを引いた後のユーザー, 現在のタイムスタンプ)を渡す
+    async fn apply_with_conn(
+        conn: &mut DebilConn,
+        mut transaction: PointTransaction,
+        daily_gacha: Option<(User, UnixTime)>,
+    ) -> Result<PointTransaction, ServiceError> {
+        let is_conditional = daily_gacha.is_some();
+        let (query, params) = PointTransactionRepository::apply_query(&transaction, daily_gacha);
+        let rows = conn
+            .sql_exec(query, debil::Params::<MySQLValue>(params))
+            .await?;
+        if rows == 0 {
+            if is_conditional {
+                return Err(ServiceError::conflict(
+                    ErrorCode::ConditionNotMet,
+                    failure::err_msg("ConditionNotMet"),
+                ));
+            }
+
+            return Err(ServiceError::bad_request(
+                ErrorCode::NotEnoughPoint,
+                failure::err_msg("You do not have enough myon point"),
+            ));
+        }
+
+        let user = conn
+            .sql_query::<UserRecord>(
+                format!(
+                    "SELECT * FROM {} WHERE {} = :user_id",
+                    table_name::<UserRecord>(),
+                    accessor!(UserRecord::id),
+                ),
+                debil::Params::<MySQLValue>(vec![(
+                    "user_id".to_string(),
+                    MySQLValue::serialize(transaction.user_id.0.clone()),
+                )]),
+            )
+            .await?
+            .pop()
+            .ok_or(ServiceError::not_found(
+                ErrorCode::NotFound,
+                failure::err_msg("record not found"),
+            ))?
+            .into_model();
+
+        transaction.balance_after = user.point;
+        conn.create(PointTransactionRecord::from_model(transaction.clone()))
+            .await?;
+
+        Ok(transaction)
+    }
+}
+
+#[async_trait]
+impl IPointTransactionRepository for PointTransactionRepository {
+    async fn find_by_user_id(
+        &self,
+        user_id: &UserId,
+        limit: i32,
+    ) -> Result<Vec<PointTransaction>, ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        let records = conn
+            .load_with_params::<PointTransactionRecord>(
+                QueryBuilder::new()
+                    .filter(format!(
+                        "{} = :user_id",
+                        accessor!(PointTransactionRecord::user_id)
+                    ))
+                    .order_by(
+                        accessor!(PointTransactionRecord::created_at),
+                        Ordering::Descending,
+                    )
+                    .limit(limit),
+                vec![("user_id", MySQLValue::serialize(user_id.0.clone()))],
+            )
+            .await?;
+
+        Ok(records.into_iter().map(|r| r.into_model()).collect())
+    }
+
+    async fn apply(&self, transaction: PointTransaction) -> Result<PointTransaction, ServiceError> {
+        self.apply_in_transaction(transaction, None).await
+    }
+
+    async fn conditional_apply(
+        &self,
+        user: User,
+        transaction: PointTransaction,
+        daily_gacha_timestamp: UnixTime,
+    ) -> Result<PointTransaction, ServiceError> {
+        self.apply_in_transaction(transaction, Some((user, daily_gacha_timestamp)))
+            .await
+    }
+}
+
+#[cfg(test)]
+pub mod point_transaction_repository_mock {
+    use super::*;
+    use std::sync::Mutex;
+
+    pub struct PointTransactionRepositoryMock {
+        pub balance: Arc<Mutex<u64>>,
+        pub applied: Arc<Mutex<Vec<PointTransaction>>>,
+    }
+
+    impl PointTransactionRepositoryMock {
+        pub fn new(balance: u64) -> Self {
+            PointTransactionRepositoryMock {
+                balance: Arc::new(Mutex::new(balance)),
+                applied: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IPointTransactionRepository for PointTransactionRepositoryMock {
+        async fn find_by_user_id(
+            &self,
+            _user_id: &UserId,
+            _limit: i32,
+        ) -> Result<Vec<PointTransaction>, ServiceError> {
+            Ok(self.applied.lock().unwrap().clone())
+        }
+
+        async fn apply(
+            &self,
+            mut transaction: PointTransaction,
+        ) -> Result<PointTransaction, ServiceError> {
+            let mut balance = self.balance.lock().unwrap();
+            if (*balance as i64) + transaction.delta < 0 {
+                return Err(ServiceError::bad_request(
+                    ErrorCode::NotEnoughPoint,
+                    failure::err_msg("You do not have enough myon point"),
+                ));
+            }
+
+            *balance = ((*balance as i64) + transaction.delta) as u64;
+            transaction.balance_after = *balance;
+            self.applied.lock().unwrap().push(transaction.clone());
+
+            Ok(transaction)
+        }
+
+        async fn conditional_apply(
+            &self,
+            _user: User,
+            transaction: PointTransaction,
+            _daily_gacha_timestamp: UnixTime,
+        ) -> Result<PointTransaction, ServiceError> {
+            self.apply(transaction).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::model::DailyStreak;
+    use crate::infra::{assert_bound, INJECTION_PAYLOADS};
+
+    #[test]
+    fn opening_balances_should_be_recorded_once_per_user() {
+        let (query, params) = PointTransactionRepository::opening_balances_query();
+        assert!(query.starts_with("INSERT INTO point_transaction (id, user_id, reason, source_event_id, delta, balance_after, created_at) SELECT CONCAT(:id_prefix, id), id, :reason, NULL, point, point, UNIX_TIMESTAMP() FROM user"));
+        assert!(query.ends_with("ON DUPLICATE KEY UPDATE id = id"));
+        assert_bound(&query, &params, "id_prefix", "opening_balance#");
+        assert_bound(&query, &params, "reason", "opening_balance");
+    }
+
+    #[test]
+    fn conditional_apply_should_bind_parameters() {
+        for payload in INJECTION_PAYLOADS {
+            let transaction = PointTransaction::new(
+                UserId(payload.to_string()),
+                PointTransactionReason::DailyGacha,
+                Some(payload.to_string()),
+                10,
+            );
+            let user = User {
+                id: UserId(payload.to_string()),
+                daily_streak: DailyStreak {
+                    current: 1,
+                    longest: 1,
+                    last_date: Some(payload.to_string()),
+                },
+                ..Default::default()
+            };
+
+            let (query, params) =
+                PointTransactionRepository::apply_query(&transaction, Some((user, UnixTime(100))));
+            assert!(query
+                .starts_with("UPDATE user SET user.point = CAST(user.point AS SIGNED) + :delta"));
+            assert!(query.ends_with(
+                "WHERE user.id = :user_id AND CAST(user.point AS SIGNED) + :delta >= 0 AND (user.last_tried_daily_gacha = :daily_gacha_timestamp OR user.last_tried_daily_gacha IS NULL)"
+            ));
+            assert_bound(&query, &params, "user_id", payload);
+            assert_bound(&query, &params, "daily_streak_last_date", payload);
+
+            let (_, value) = params
+                .iter()
+                .find(|(name, _)| name == "daily_gacha_timestamp")
+                .unwrap();
+            assert_eq!(value.0, mysql_async::Value::from(100_i64));
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::infra::ConnPool;
+use crate::server::{
+    IRateLimitStore, RateLimitDecision, RateLimitPolicy, SweepSchedule, TokenBucket,
+};
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use async_trait::async_trait;
+use debil::*;
+use debil_mysql::*;
+use std::sync::Arc;
+
+// 複数のインスタンスで共有するトークンバケット
+// 小数を持てないのでトークン数は1000倍して持つ
+#[derive(Table, Accessor, Clone)]
+#[sql(
+    table_name = "rate_limit_bucket",
+    sql_type = "MySQLValue",
+    primary_key = "bucket_key"
+)]
+pub struct RateLimitBucketRecord {
+    #[sql(size = 200)]
+    bucket_key: String,
+    milli_tokens: i64,
+    updated_at_ms: i64,
+    // これより後は満タンのバケットと同じなので行を消してよい
+    full_at_ms: Option<i64>,
+}
+
+impl RateLimitBucketRecord {
+    fn into_bucket(self) -> TokenBucket {
+        TokenBucket {
+            tokens: self.milli_tokens as f64 / 1000.0,
+            updated_at_ms: self.updated_at_ms,
+        }
+    }
+}
+
+pub struct MySQLRateLimitStore {
+    pool: Arc<ConnPool>,
+    sweep: SweepSchedule,
+}
+
+impl MySQLRateLimitStore {
+    pub fn new(pool: Arc<ConnPool>) -> Self {
+        MySQLRateLimitStore {
+            pool,
+            sweep: Default::default(),
+        }
+    }
+
+    // 満タンに戻った行を消す, full_at_msがない行は列を追加する前のもの
+    async fn sweep(&self, now_ms: i64) -> Result<(), ServiceError> {
+        let mut conn = self.pool.get_conn().await?;
+        conn.sql_exec(
+            format!(
+                "DELETE FROM {} WHERE {} IS NULL OR {} <= :now_ms",
+                table_name::<RateLimitBucketRecord>(),
+                accessor!(RateLimitBucketRecord::full_at_ms),
+                accessor!(RateLimitBucketRecord::full_at_ms),
+            ),
+            debil::Params::<MySQLValue>(vec![(
+                "now_ms".to_string(),
+                MySQLValue::serialize(now_ms),
+            )]),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn take_with_conn(
+        conn: &mut DebilConn,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now_ms: i64,
+    ) -> Result<RateLimitDecision, ServiceError> {
+        let params = |bucket: TokenBucket| {
+            debil::Params::<MySQLValue>(vec![
+                ("key".to_string(), MySQLValue::serialize(key.to_string())),
+                (
+                    "milli_tokens".to_string(),
+                    MySQLValue::serialize((bucket.tokens * 1000.0).floor() as i64),
+                ),
+                (
+                    "updated_at_ms".to_string(),
+                    MySQLValue::serialize(bucket.updated_at_ms),
+                ),
+                (
+                    "full_at_ms".to_string(),
+                    MySQLValue::serialize(bucket.full_at_ms(policy)),
+                ),
+            ])
+        };
+
+        // 初めてのキーは満タンのバケットを作ってから行をロックする
+        conn.sql_exec(
+            format!(
+                "INSERT IGNORE INTO {} ({}, {}, {}, {}) VALUES (:key, :milli_tokens, :updated_at_ms, :full_at_ms)",
+                table_name::<RateLimitBucketRecord>(),
+                accessor!(RateLimitBucketRecord::bucket_key),
+                accessor!(RateLimitBucketRecord::milli_tokens),
+                accessor!(RateLimitBucketRecord::updated_at_ms),
+                accessor!(RateLimitBucketRecord::full_at_ms),
+            ),
+            params(TokenBucket::full(policy, now_ms)),
+        )
+        .await?;
+
+        let mut bucket = conn
+            .sql_query::<RateLimitBucketRecord>(
+                format!(
+                    "SELECT * FROM {} WHERE {} = :key FOR UPDATE",
+                    table_name::<RateLimitBucketRecord>(),
+                    accessor!(RateLimitBucketRecord::bucket_key),
+                ),
+                debil::Params::<MySQLValue>(vec![(
+                    "key".to_string(),
+                    MySQLValue::serialize(key.to_string()),
+                )]),
+            )
+            .await?
+            .pop()
+            .ok_or(ServiceError::internal_server_error(
+                ErrorCode::DatabaseError,
+                failure::err_msg("rate limit bucket not found"),
+            ))?
+            .into_bucket();
+        let decision = bucket.take(policy, now_ms);
+
+        conn.sql_exec(
+            format!(
+                "UPDATE {} SET {} = :milli_tokens, {} = :updated_at_ms, {} = :full_at_ms WHERE {} = :key",
+                table_name::<RateLimitBucketRecord>(),
+                accessor!(RateLimitBucketRecord::milli_tokens),
+                accessor!(RateLimitBucketRecord::updated_at_ms),
+                accessor!(RateLimitBucketRecord::full_at_ms),
+                accessor!(RateLimitBucketRecord::bucket_key),
+            ),
+            params(bucket),
+        )
+        .await?;
+
+        Ok(decision)
+    }
+}
+
+#[async_trait]
+impl IRateLimitStore for MySQLRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now_ms: i64,
+    ) -> Result<RateLimitDecision, ServiceError> {
+        if self.sweep.is_due(now_ms) {
+            if let Err(err) = self.sweep(now_ms).await {
+                warn!("Failed to sweep rate limit buckets: {:?}", err);
+            }
+        }
+
+        let mut conn = self.pool.get_conn().await?;
+        conn.start_transaction().await?;
+
+        match MySQLRateLimitStore::take_with_conn(&mut conn, key, policy, now_ms).await {
+            Ok(decision) => {
+                conn.commit().await?;
+
+                Ok(decision)
+            }
+            Err(err) => {
+                conn.rollback().await?;
+
+                Err(err)
+            }
+        }
+    }
+}
@@ -31,4 +31,15 @@ impl S3Client {
 
         Ok(())
     }
+
+    // バケットが存在してアクセスできるかどうか
+    pub async fn head_bucket(&self, bucket_name: String) -> Result<(), ServiceError> {
+        self.client
+            .head_bucket(rusoto_s3::HeadBucketRequest {
+                bucket: bucket_name,
+            })
+            .await?;
+
+        Ok(())
+    }
 }
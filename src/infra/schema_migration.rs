@@ -0,0 +1,62 @@
+use crate::infra::select_query;
+use crate::wrapper::error::ServiceError;
+use crate::wrapper::unixtime::UnixTime;
+use debil::*;
+use debil_mysql::*;
+
+// テーブルの定義だけでは済まないデータの移行を, どのバージョンまで行ったかを記録する
+#[derive(Table, Clone, Accessor)]
+#[sql(
+    table_name = "schema_migration",
+    sql_type = "MySQLValue",
+    primary_key = "version"
+)]
+pub struct SchemaMigrationRecord {
+    version: u32,
+    applied_at: i64,
+}
+
+pub struct SchemaMigration;
+
+impl SchemaMigration {
+    pub async fn is_applied(conn: &mut DebilConn, version: u32) -> Result<bool, ServiceError> {
+        let applied = conn
+            .sql_query::<SchemaMigrationRecord>(
+                select_query::<SchemaMigrationRecord>(QueryBuilder::new().filter(format!(
+                    "{} = :version",
+                    accessor!(SchemaMigrationRecord::version)
+                ))),
+                debil::Params::<MySQLValue>(vec![(
+                    "version".to_string(),
+                    MySQLValue::serialize(version),
+                )]),
+            )
+            .await?;
+
+        Ok(!applied.is_empty())
+    }
+
+    // 同時に起動したインスタンスが同じ移行を終えていても失敗しない
+    pub async fn mark_applied(conn: &mut DebilConn, version: u32) -> Result<(), ServiceError> {
+        conn.sql_exec(
+            format!(
+                "INSERT INTO {} ({}, {}) VALUES (:version, :applied_at) ON DUPLICATE KEY UPDATE {} = {}",
+                table_name::<SchemaMigrationRecord>(),
+                accessor_name!(SchemaMigrationRecord::version),
+                accessor_name!(SchemaMigrationRecord::applied_at),
+                accessor_name!(SchemaMigrationRecord::version),
+                accessor_name!(SchemaMigrationRecord::version),
+            ),
+            debil::Params::<MySQLValue>(vec![
+                ("version".to_string(), MySQLValue::serialize(version)),
+                (
+                    "applied_at".to_string(),
+                    MySQLValue::serialize(UnixTime::now().0),
+                ),
+            ]),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+use crate::domain::interface::{
+    IGachaStateRepository, IGiftRepository, IJankenEventRepository, IPendingGachaEventRepository,
+    IPointTransactionRepository, ITransaction, IUnitOfWork, IUserRepository,
+};
+use crate::infra::{
+    ConnPool, GachaStateRepository, GiftRepository, Instrumented, JankenEventRepository,
+    PendingGachaEventRepository, PointTransactionRepository, UserRepository,
+};
+use crate::wrapper::error::ServiceError;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub struct UnitOfWork {
+    pool: Arc<ConnPool>,
+}
+
+impl UnitOfWork {
+    pub fn new(pool: Arc<ConnPool>) -> Self {
+        UnitOfWork { pool }
+    }
+}
+
+#[async_trait]
+impl IUnitOfWork for UnitOfWork {
+    async fn begin(&self) -> Result<Box<dyn ITransaction + Sync + Send>, ServiceError> {
+        let conn = Arc::new(self.pool.begin().await?);
+
+        Ok(Box::new(Transaction {
+            conn: conn.clone(),
+            finished: false,
+            user_repository: Instrumented::new(
+                "user_repository",
+                Arc::new(UserRepository::new(conn.clone())),
+            ),
+            gift_repository: Instrumented::new(
+                "gift_repository",
+                Arc::new(GiftRepository::new(conn.clone())),
+            ),
+            janken_repository: Instrumented::new(
+                "janken_event_repository",
+                Arc::new(JankenEventRepository::new(conn.clone())),
+            ),
+            gacha_state_repository: Instrumented::new(
+                "gacha_state_repository",
+                Arc::new(GachaStateRepository::new(conn.clone())),
+            ),
+            pending_gacha_event_repository: Instrumented::new(
+                "pending_gacha_event_repository",
+                Arc::new(PendingGachaEventRepository::new(conn.clone())),
+            ),
+            point_transaction_repository: Instrumented::new(
+                "point_transaction_repository",
+                Arc::new(PointTransactionRepository::new(conn)),
+            ),
+        }))
+    }
+}
+
+// 全てのリポジトリがトランザクション中の1つのコネクションを共有する
+pub struct Transaction {
+    conn: Arc<ConnPool>,
+    // commitかrollbackが成功したらtrue
+    finished: bool,
+    user_repository: Instrumented<dyn IUserRepository + Sync + Send>,
+    gift_repository: Instrumented<dyn IGiftRepository + Sync + Send>,
+    janken_repository: Instrumented<dyn IJankenEventRepository + Sync + Send>,
+    gacha_state_repository: Instrumented<dyn IGachaStateRepository + Sync + Send>,
+    pending_gacha_event_repository: Instrumented<dyn IPendingGachaEventRepository + Sync + Send>,
+    point_transaction_repository: Instrumented<dyn IPointTransactionRepository + Sync + Send>,
+}
+
+#[async_trait]
+impl ITransaction for Transaction {
+    fn user_repository(&self) -> &(dyn IUserRepository + Sync + Send) {
+        &self.user_repository
+    }
+
+    fn gift_repository(&self) -> &(dyn IGiftRepository + Sync + Send) {
+        &self.gift_repository
+    }
+
+    fn janken_repository(&self) -> &(dyn IJankenEventRepository + Sync + Send) {
+        &self.janken_repository
+    }
+
+    fn gacha_state_repository(&self) -> &(dyn IGachaStateRepository + Sync + Send) {
+        &self.gacha_state_repository
+    }
+
+    fn pending_gacha_event_repository(&self) -> &(dyn IPendingGachaEventRepository + Sync + Send) {
+        &self.pending_gacha_event_repository
+    }
+
+    fn point_transaction_repository(&self) -> &(dyn IPointTransactionRepository + Sync + Send) {
+        &self.point_transaction_repository
+    }
+
+    async fn commit(mut self: Box<Self>) -> Result<(), ServiceError> {
+        self.conn.commit().await?;
+        self.finished = true;
+
+        Ok(())
+    }
+
+    async fn rollback(mut self: Box<Self>) -> Result<(), ServiceError> {
+        self.conn.rollback().await?;
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+// 途中でエラーやキャンセルで抜けた場合, トランザクションが開いたままコネクションがプールに戻らないようにする
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        let conn = self.conn.clone();
+        tokio::spawn(async move {
+            if let Err(err) = conn.rollback().await {
+                error!("Failed to rollback a dropped transaction: {:?}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+pub mod unit_of_work_mock {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::infra::gacha_state_repository_mock::GachaStateRepositoryMock;
+    use crate::infra::pending_gacha_event_repository_mock::PendingGachaEventRepositoryMock;
+    use crate::infra::user_repository_mock::UserRepositoryStub;
+
+    // モックのリポジトリは即座に書き込むので、コミットとロールバックは回数だけ記録する
+    // 終わらせずに捨てたトランザクションはロールバックとして数える
+    pub struct UnitOfWorkMock {
+        pub user_repository: Arc<dyn IUserRepository + Sync + Send>,
+        pub gift_repository: Arc<dyn IGiftRepository + Sync + Send>,
+        pub janken_repository: Arc<dyn IJankenEventRepository + Sync + Send>,
+        pub gacha_state_repository: Arc<dyn IGachaStateRepository + Sync + Send>,
+        pub pending_gacha_event_repository: Arc<dyn IPendingGachaEventRepository + Sync + Send>,
+        pub point_transaction_repository: Arc<dyn IPointTransactionRepository + Sync + Send>,
+        pub committed: Arc<Mutex<u64>>,
+        pub rolled_back: Arc<Mutex<u64>>,
+    }
+
+    impl UnitOfWorkMock {
+        pub fn new(
+            gift_repository: Arc<dyn IGiftRepository + Sync + Send>,
+            janken_repository: Arc<dyn IJankenEventRepository + Sync + Send>,
+            point_transaction_repository: Arc<dyn IPointTransactionRepository + Sync + Send>,
+        ) -> Self {
+            UnitOfWorkMock {
+                user_repository: Arc::new(UserRepositoryStub::new(Default::default())),
+                gift_repository,
+                janken_repository,
+                gacha_state_repository: Arc::new(GachaStateRepositoryMock::new(Vec::new())),
+                pending_gacha_event_repository: Arc::new(PendingGachaEventRepositoryMock::new()),
+                point_transaction_repository,
+                committed: Arc::new(Mutex::new(0)),
+                rolled_back: Arc::new(Mutex::new(0)),
+            }
+        }
+
+        pub fn with_user_repository(
+            mut self,
+            user_repository: Arc<dyn IUserRepository + Sync + Send>,
+        ) -> Self {
+            self.user_repository = user_repository;
+            self
+        }
+
+        pub fn with_gacha_state_repository(
+            mut self,
+            gacha_state_repository: Arc<dyn IGachaStateRepository + Sync + Send>,
+        ) -> Self {
+            self.gacha_state_repository = gacha_state_repository;
+            self
+        }
+
+        pub fn with_pending_gacha_event_repository(
+            mut self,
+            pending_gacha_event_repository: Arc<dyn IPendingGachaEventRepository + Sync + Send>,
+        ) -> Self {
+            self.pending_gacha_event_repository = pending_gacha_event_repository;
+            self
+        }
+    }
+
+    #[async_trait]
+    impl IUnitOfWork for UnitOfWorkMock {
+        async fn begin(&self) -> Result<Box<dyn ITransaction + Sync + Send>, ServiceError> {
+            Ok(Box::new(TransactionMock {
+                finished: false,
+                user_repository: self.user_repository.clone(),
+                gift_repository: self.gift_repository.clone(),
+                janken_repository: self.janken_repository.clone(),
+                gacha_state_repository: self.gacha_state_repository.clone(),
+                pending_gacha_event_repository: self.pending_gacha_event_repository.clone(),
+                point_transaction_repository: self.point_transaction_repository.clone(),
+                committed: self.committed.clone(),
+                rolled_back: self.rolled_back.clone(),
+            }))
+        }
+    }
+
+    pub struct TransactionMock {
+        finished: bool,
+        user_repository: Arc<dyn IUserRepository + Sync + Send>,
+        gift_repository: Arc<dyn IGiftRepository + Sync + Send>,
+        janken_repository: Arc<dyn IJankenEventRepository + Sync + Send>,
+        gacha_state_repository: Arc<dyn IGachaStateRepository + Sync + Send>,
+        pending_gacha_event_repository: Arc<dyn IPendingGachaEventRepository + Sync + Send>,
+        point_transaction_repository: Arc<dyn IPointTransactionRepository + Sync + Send>,
+        committed: Arc<Mutex<u64>>,
+        rolled_back: Arc<Mutex<u64>>,
+    }
+
+    #[async_trait]
+    impl ITransaction for TransactionMock {
+        fn user_repository(&self) -> &(dyn IUserRepository + Sync + Send) {
+            self.user_repository.as_ref()
+        }
+
+        fn gift_repository(&self) -> &(dyn IGiftRepository + Sync + Send) {
+            self.gift_repository.as_ref()
+        }
+
+        fn janken_repository(&self) -> &(dyn IJankenEventRepository + Sync + Send) {
+            self.janken_repository.as_ref()
+        }
+
+        fn gacha_state_repository(&self) -> &(dyn IGachaStateRepository + Sync + Send) {
+            self.gacha_state_repository.as_ref()
+        }
+
+        fn pending_gacha_event_repository(
+            &self,
+        ) -> &(dyn IPendingGachaEventRepository + Sync + Send) {
+            self.pending_gacha_event_repository.as_ref()
+        }
+
+        fn point_transaction_repository(&self) -> &(dyn IPointTransactionRepository + Sync + Send) {
+            self.point_transaction_repository.as_ref()
+        }
+
+        async fn commit(mut self: Box<Self>) -> Result<(), ServiceError> {
+            *self.committed.lock().unwrap() += 1;
+            self.finished = true;
+
+            Ok(())
+        }
+
+        async fn rollback(mut self: Box<Self>) -> Result<(), ServiceError> {
+            *self.rolled_back.lock().unwrap() += 1;
+            self.finished = true;
+
+            Ok(())
+        }
+    }
+
+    impl Drop for TransactionMock {
+        fn drop(&mut self) {
+            if !self.finished {
+                *self.rolled_back.lock().unwrap() += 1;
+            }
+        }
+    }
+}
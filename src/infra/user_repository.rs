@@ -1,6 +1,6 @@
 use crate::domain::interface::IUserRepository;
-use crate::domain::model::{User, UserId};
-use crate::infra::ConnPool;
+use crate::domain::model::{DailyStreak, User, UserId};
+use crate::infra::{select_query, ConnPool};
 use crate::wrapper::error::ServiceError;
 use crate::wrapper::unixtime::UnixTime;
 use crate::wrapper::url::Url;
@@ -26,6 +26,10 @@ pub struct UserRecord {
     #[sql(size = 256)]
     picture_url: Option<String>,
     last_tried_daily_gacha: Option<i64>,
+    daily_streak_current: Option<u32>,
+    daily_streak_longest: Option<u32>,
+    #[sql(size = 10)]
+    daily_streak_last_date: Option<String>,
 }
 
 impl UserRecord {
@@ -39,6 +43,11 @@ impl UserRecord {
             subject: self.subject,
             picture_url: self.picture_url.map(Url),
             last_tried_daily_gacha: UnixTime(self.last_tried_daily_gacha.unwrap_or(0)),
+            daily_streak: DailyStreak {
+                current: self.daily_streak_current.unwrap_or(0),
+                longest: self.daily_streak_longest.unwrap_or(0),
+                last_date: self.daily_streak_last_date,
+            },
         }
     }
 
@@ -52,6 +61,9 @@ impl UserRecord {
             subject: user.subject,
             picture_url: user.picture_url.map(|u| u.0),
             last_tried_daily_gacha: Some(user.last_tried_daily_gacha.0),
+            daily_streak_current: Some(user.daily_streak.current),
+            daily_streak_longest: Some(user.daily_streak.longest),
+            daily_streak_last_date: user.daily_streak.last_date,
         }
     }
 }
@@ -64,6 +76,28 @@ impl UserRepository {
     pub fn new(pool: Arc<ConnPool>) -> Self {
         UserRepository { pool }
     }
+
+    // screen_nameはユーザーが入力した値なので, クエリには埋め込まずにパラメータで渡す
+    fn screen_name_query(screen_name: &str) -> (QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            QueryBuilder::new().filter(format!(
+                "{} = :screen_name",
+                accessor!(UserRecord::screen_name)
+            )),
+            vec![(
+                "screen_name",
+                MySQLValue::serialize(screen_name.to_string()),
+            )],
+        )
+    }
+
+    // subjectはJWTのsubクレームなので, screen_nameと同じくパラメータで渡す
+    fn subject_query(subject: &str) -> (QueryBuilder, Vec<(&'static str, MySQLValue)>) {
+        (
+            QueryBuilder::new().filter(format!("{} = :subject", accessor!(UserRecord::subject))),
+            vec![("subject", MySQLValue::serialize(subject.to_string()))],
+        )
+    }
 }
 
 struct UserIdMapper {
@@ -93,53 +127,57 @@ impl IUserRepository for UserRepository {
         Ok(users.into_iter().map(|m| UserId(m.id)).collect())
     }
 
-    async fn find_oldest_user(&self) -> Result<UserId, ServiceError> {
+    async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError> {
         let mut conn = self.pool.get_conn().await?;
         let user = conn
-            .first_with::<UserRecord>(
-                QueryBuilder::new()
-                    .order_by(accessor!(UserRecord::created_at), Ordering::Ascending),
+            .first_with_params::<UserRecord>(
+                QueryBuilder::new().filter(format!("{} = :id", accessor!(UserRecord::id))),
+                vec![("id", MySQLValue::serialize(user_id.0.to_string()))],
             )
             .await?;
 
-        Ok(UserId(user.id))
+        Ok(user.into_model())
     }
 
-    async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError> {
+    async fn find_by_screen_name(&self, screen_name: &String) -> Result<User, ServiceError> {
+        let (builder, params) = UserRepository::screen_name_query(screen_name);
         let mut conn = self.pool.get_conn().await?;
         let user = conn
-            .first_with::<UserRecord>(QueryBuilder::new().filter(format!(
-                "{} = '{}'",
-                accessor!(UserRecord::id),
-                user_id.0
-            )))
+            .first_with_params::<UserRecord>(builder, params)
             .await?;
 
         Ok(user.into_model())
     }
 
-    async fn find_by_screen_name(&self, screen_name: &String) -> Result<User, ServiceError> {
+    async fn find_by_subject(&self, subject: &str) -> Result<User, ServiceError> {
+        let (builder, params) = UserRepository::subject_query(subject);
         let mut conn = self.pool.get_conn().await?;
         let user = conn
-            .first_with::<UserRecord>(QueryBuilder::new().filter(format!(
-                "{} = '{}'",
-                accessor!(UserRecord::screen_name),
-                screen_name
-            )))
+            .first_with_params::<UserRecord>(builder, params)
             .await?;
 
         Ok(user.into_model())
     }
 
-    async fn find_by_subject(&self, subject: &str) -> Result<User, ServiceError> {
+    async fn find_by_subject_for_update(&self, subject: &str) -> Result<User, ServiceError> {
+        let (builder, params) = UserRepository::subject_query(subject);
         let mut conn = self.pool.get_conn().await?;
         let user = conn
-            .first_with::<UserRecord>(QueryBuilder::new().filter(format!(
-                "{} = '{}'",
-                accessor!(UserRecord::subject),
-                subject
-            )))
-            .await?;
+            .sql_query::<UserRecord>(
+                format!(
+                    "{} FOR UPDATE",
+                    select_query::<UserRecord>(builder.limit(1))
+                ),
+                debil::Params::<MySQLValue>(
+                    params
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v))
+                        .collect(),
+                ),
+            )
+            .await?
+            .pop()
+            .ok_or_else(debil_mysql::Error::not_found)?;
 
         Ok(user.into_model())
     }
@@ -152,44 +190,36 @@ impl IUserRepository for UserRepository {
     }
 
     async fn save(&self, user: User) -> Result<(), ServiceError> {
-        let mut conn = self.pool.get_conn().await?;
-        conn.save(UserRecord::from_model(user)).await?;
-
-        Ok(())
-    }
-
-    async fn conditional_save_point(
-        &self,
-        user: User,
-        daily_gacha_timestamp: UnixTime,
-    ) -> Result<(), ServiceError> {
         let mut conn = self.pool.get_conn().await?;
         let record = UserRecord::from_model(user);
-        let rows = conn
-            .sql_exec(
-                format!(
-                    // last_tried_daily_gachaがNULLも許容する必要がある
-                    "UPDATE {} SET {} = {}, {} = {} WHERE {} = '{}' AND ({} = {} OR {} IS NULL)",
-                    table_name::<UserRecord>(),
-                    accessor!(UserRecord::point),
-                    record.point,
-                    accessor!(UserRecord::last_tried_daily_gacha),
-                    record.last_tried_daily_gacha.unwrap_or(0),
-                    accessor!(UserRecord::id),
-                    record.id,
-                    accessor!(UserRecord::last_tried_daily_gacha),
-                    daily_gacha_timestamp.0,
-                    accessor!(UserRecord::last_tried_daily_gacha),
-                ),
-                debil::Params::new(),
-            )
-            .await?;
 
-        if rows == 0 {
-            return Err(ServiceError::bad_request(failure::err_msg(
-                "ConditionNotMet",
-            )));
-        }
+        // みょんポイントとデイリーガチャのタイムスタンプはpoint_transaction経由でしか更新しない
+        conn.sql_exec(
+            format!(
+                "UPDATE {} SET {} = :screen_name, {} = :display_name, {} = :picture_url WHERE {} = :id",
+                table_name::<UserRecord>(),
+                accessor!(UserRecord::screen_name),
+                accessor!(UserRecord::display_name),
+                accessor!(UserRecord::picture_url),
+                accessor!(UserRecord::id),
+            ),
+            debil::Params::<MySQLValue>(vec![
+                (
+                    "screen_name".to_string(),
+                    MySQLValue::serialize(record.screen_name),
+                ),
+                (
+                    "display_name".to_string(),
+                    MySQLValue::serialize(record.display_name),
+                ),
+                (
+                    "picture_url".to_string(),
+                    MySQLValue::serialize(record.picture_url),
+                ),
+                ("id".to_string(), MySQLValue::serialize(record.id)),
+            ]),
+        )
+        .await?;
 
         Ok(())
     }
@@ -222,10 +252,6 @@ pub mod user_repository_mock {
             unimplemented!()
         }
 
-        async fn find_oldest_user(&self) -> Result<UserId, ServiceError> {
-            unimplemented!()
-        }
-
         async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError> {
             Ok(self.item.clone())
         }
@@ -238,6 +264,10 @@ pub mod user_repository_mock {
             Ok(self.item.clone())
         }
 
+        async fn find_by_subject_for_update(&self, _subject: &str) -> Result<User, ServiceError> {
+            Ok(self.item.clone())
+        }
+
         async fn create(&self, user: User) -> Result<(), ServiceError> {
             self.created.lock().unwrap().push(user);
 
@@ -249,14 +279,6 @@ pub mod user_repository_mock {
 
             Ok(())
         }
-
-        async fn conditional_save_point(
-            &self,
-            user: User,
-            daily_gacha_timestamp: UnixTime,
-        ) -> Result<(), ServiceError> {
-            unimplemented!()
-        }
     }
 
     pub struct UserRepositoryListIdStub {
@@ -275,10 +297,6 @@ pub mod user_repository_mock {
             Ok(self.ids.clone())
         }
 
-        async fn find_oldest_user(&self) -> Result<UserId, ServiceError> {
-            unimplemented!()
-        }
-
         async fn find_by_id(&self, user_id: &UserId) -> Result<User, ServiceError> {
             unimplemented!()
         }
@@ -291,6 +309,10 @@ pub mod user_repository_mock {
             unimplemented!()
         }
 
+        async fn find_by_subject_for_update(&self, _subject: &str) -> Result<User, ServiceError> {
+            unimplemented!()
+        }
+
         async fn create(&self, user: User) -> Result<(), ServiceError> {
             unimplemented!()
         }
@@ -298,13 +320,38 @@ pub mod user_repository_mock {
         async fn save(&self, user: User) -> Result<(), ServiceError> {
             unimplemented!()
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::{assert_bound, select_query, INJECTION_PAYLOADS};
+
+    #[test]
+    fn screen_name_should_be_bound_as_parameter() {
+        // クォートやバックスラッシュを含むscreen_nameでクエリの構造が変わらないことを確かめる
+        for payload in INJECTION_PAYLOADS {
+            let (builder, params) = UserRepository::screen_name_query(payload);
+            let query = select_query::<UserRecord>(builder.limit(1));
+            assert!(query.ends_with("FROM user WHERE user.screen_name = :screen_name LIMIT 1"));
+            assert!(!query.contains(payload));
+
+            // 値はエスケープや加工をされずにそのままパラメータになる
+            assert_eq!(params.len(), 1);
+            let (name, value) = &params[0];
+            assert_eq!(*name, "screen_name");
+            assert_eq!(value.0, mysql_async::Value::from(payload.to_string()));
+        }
+    }
 
-        async fn conditional_save_point(
-            &self,
-            user: User,
-            daily_gacha_timestamp: UnixTime,
-        ) -> Result<(), ServiceError> {
-            unimplemented!()
+    #[test]
+    fn subject_should_be_bound_as_parameter() {
+        for payload in INJECTION_PAYLOADS {
+            let (builder, params) = UserRepository::subject_query(payload);
+            let query = select_query::<UserRecord>(builder.limit(1));
+            assert!(query.ends_with("FROM user WHERE user.subject = :subject LIMIT 1"));
+            assert_bound(&query, &params, "subject", payload);
         }
     }
 }
@@ -1,33 +1,198 @@
+use crate::domain::interface::{
+    IGachaEventRepository, IGachaStateRepository, IGiftRepository, IHealthCheck,
+    IJankenEventRepository, IPendingGachaEventRepository, IPointEventRepository,
+    IPointTransactionRepository, IRankingRepository, IUnitOfWork, IUserIconUploader,
+    IUserRepository,
+};
+use crate::domain::model::GachaCatalog;
 use crate::domain::service::{
-    GachaService, GiftDistributionService, GiftService, JankenProcessService, JankenService,
-    PointProcessService, PointRankingService, UserIconUploadService, UserMeService, UserService,
+    GachaService, GiftDistributionService, GiftService, HealthService, JankenProcessService,
+    JankenService, PointProcessService, PointRankingService, PointTransactionService,
+    UserIconUploadService, UserMeService, UserService,
 };
 use crate::infra::{
-    ConnPool, DynamoClient, GachaEventRepository, GiftRepository, JWTHandler,
-    JankenEventRepository, PointEventRepository, RankingRepository, S3Client, UserIconUploader,
-    UserRepository,
+    ConnPool, DevTokenSigner, DynamoClient, DynamoTableHealthCheck, GachaEventRepository,
+    GachaStateRepository, GiftRepository, InMemoryGachaEventRepository,
+    InMemoryGachaStateRepository, InMemoryGiftRepository, InMemoryJankenEventRepository,
+    InMemoryPendingGachaEventRepository, InMemoryPointEventRepository,
+    InMemoryPointTransactionRepository, InMemoryRankingRepository, InMemoryStore,
+    InMemoryUnitOfWork, InMemoryUserIconUploader, InMemoryUserRepository, Instrumented, JWTHandler,
+    JankenEventRepository, MySQLHealthCheck, MySQLRateLimitStore, PendingGachaEventRepository,
+    PointEventRepository, PointTransactionRepository, RankingRepository, S3BucketHealthCheck,
+    S3Client, UnitOfWork, UserIconUploader, UserRepository, SCHEMA_VERSION,
 };
+use crate::server::{IRateLimitStore, InMemoryRateLimitStore};
+use crate::wrapper::daily_reset::DailyReset;
 use std::sync::Arc;
+use std::time::Duration;
+
+// /readyzで1つの依存の確認を待つ時間
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub enum Backend {
+    // MySQL, DynamoDB, S3を使う
+    Remote {
+        aws_region: rusoto_core::Region,
+        db_url: String,
+        gacha_event_repository_table_name: String,
+        user_icon_upload_bucket: String,
+        // trueのときはレート制限のバケットをMySQLに置いてインスタンス間で共有する
+        shared_rate_limit: bool,
+    },
+    // 全てのデータをメモリ上に持つ, ローカルでの開発やE2Eテスト用
+    InMemory,
+}
 
 pub struct Config {
-    pub aws_region: rusoto_core::Region,
-    pub db_url: String,
-    pub public_key: Arc<biscuit::jwk::JWKSet<biscuit::Empty>>,
-    pub gacha_event_repository_table_name: String,
-    pub user_icon_upload_bucket: String,
+    pub backend: Backend,
+    pub jwt_handler: Arc<JWTHandler>,
+    pub dev_token_signer: Option<Arc<DevTokenSigner>>,
+    // 指定がないときはデイリーガチャだけのカタログを使う
+    pub gacha_catalog: Option<Arc<GachaCatalog>>,
+    pub daily_reset: DailyReset,
 }
 
 pub struct Infras {
-    pub dynamo_client: Arc<DynamoClient>,
-    pub s3_client: Arc<S3Client>,
     pub jwt_handler: Arc<JWTHandler>,
-    pub user_repository: Arc<UserRepository>,
-    pub gacha_event_repository: Arc<GachaEventRepository>,
-    pub gift_repository: Arc<GiftRepository>,
-    pub user_icon_uploader: Arc<UserIconUploader>,
-    pub janken_repository: Arc<JankenEventRepository>,
-    pub point_repository: Arc<PointEventRepository>,
-    pub ranking_repository: Arc<RankingRepository>,
+    // AUTH_MODE=devのときだけ存在する
+    pub dev_token_signer: Option<Arc<DevTokenSigner>>,
+    pub user_repository: Arc<dyn IUserRepository + Sync + Send>,
+    pub gacha_event_repository: Arc<dyn IGachaEventRepository + Sync + Send>,
+    pub gacha_state_repository: Arc<dyn IGachaStateRepository + Sync + Send>,
+    pub pending_gacha_event_repository: Arc<dyn IPendingGachaEventRepository + Sync + Send>,
+    pub gift_repository: Arc<dyn IGiftRepository + Sync + Send>,
+    pub user_icon_uploader: Arc<dyn IUserIconUploader + Sync + Send>,
+    pub janken_repository: Arc<dyn IJankenEventRepository + Sync + Send>,
+    pub point_repository: Arc<dyn IPointEventRepository + Sync + Send>,
+    pub ranking_repository: Arc<dyn IRankingRepository + Sync + Send>,
+    pub point_transaction_repository: Arc<dyn IPointTransactionRepository + Sync + Send>,
+    pub unit_of_work: Arc<dyn IUnitOfWork + Sync + Send>,
+    pub health_checks: Vec<Arc<dyn IHealthCheck + Sync + Send>>,
+    pub rate_limit_store: Arc<dyn IRateLimitStore + Sync + Send>,
+    pub gacha_catalog: Arc<GachaCatalog>,
+    // 日付が変わるタイムゾーンと時刻
+    pub daily_reset: DailyReset,
+}
+
+impl Infras {
+    fn remote(
+        jwt_handler: Arc<JWTHandler>,
+        aws_region: rusoto_core::Region,
+        db_url: String,
+        gacha_event_repository_table_name: String,
+        user_icon_upload_bucket: String,
+        shared_rate_limit: bool,
+    ) -> Infras {
+        let conn_pool = Arc::new(ConnPool::new(&db_url).unwrap());
+        let dynamo_client = Arc::new(DynamoClient::new(aws_region.clone()));
+        let s3_client = Arc::new(S3Client::new(aws_region));
+
+        Infras {
+            jwt_handler,
+            dev_token_signer: None,
+            user_repository: Arc::new(Instrumented::<dyn IUserRepository + Sync + Send>::new(
+                "user_repository",
+                Arc::new(UserRepository::new(conn_pool.clone())),
+            )),
+            gacha_event_repository: Arc::new(
+                Instrumented::<dyn IGachaEventRepository + Sync + Send>::new(
+                    "gacha_event_repository",
+                    Arc::new(GachaEventRepository::new(
+                        dynamo_client.clone(),
+                        gacha_event_repository_table_name.clone(),
+                    )),
+                ),
+            ),
+            gacha_state_repository: Arc::new(
+                Instrumented::<dyn IGachaStateRepository + Sync + Send>::new(
+                    "gacha_state_repository",
+                    Arc::new(GachaStateRepository::new(conn_pool.clone())),
+                ),
+            ),
+            pending_gacha_event_repository: Arc::new(Instrumented::<
+                dyn IPendingGachaEventRepository + Sync + Send,
+            >::new(
+                "pending_gacha_event_repository",
+                Arc::new(PendingGachaEventRepository::new(conn_pool.clone())),
+            )),
+            gift_repository: Arc::new(Instrumented::<dyn IGiftRepository + Sync + Send>::new(
+                "gift_repository",
+                Arc::new(GiftRepository::new(conn_pool.clone())),
+            )),
+            user_icon_uploader: Arc::new(UserIconUploader::new(
+                s3_client.clone(),
+                user_icon_upload_bucket.clone(),
+            )),
+            janken_repository: Arc::new(
+                Instrumented::<dyn IJankenEventRepository + Sync + Send>::new(
+                    "janken_event_repository",
+                    Arc::new(JankenEventRepository::new(conn_pool.clone())),
+                ),
+            ),
+            point_repository: Arc::new(
+                Instrumented::<dyn IPointEventRepository + Sync + Send>::new(
+                    "point_event_repository",
+                    Arc::new(PointEventRepository::new(conn_pool.clone())),
+                ),
+            ),
+            ranking_repository: Arc::new(
+                Instrumented::<dyn IRankingRepository + Sync + Send>::new(
+                    "ranking_repository",
+                    Arc::new(RankingRepository::new(conn_pool.clone())),
+                ),
+            ),
+            point_transaction_repository: Arc::new(Instrumented::<
+                dyn IPointTransactionRepository + Sync + Send,
+            >::new(
+                "point_transaction_repository",
+                Arc::new(PointTransactionRepository::new(conn_pool.clone())),
+            )),
+            unit_of_work: Arc::new(UnitOfWork::new(conn_pool.clone())),
+            rate_limit_store: if shared_rate_limit {
+                Arc::new(MySQLRateLimitStore::new(conn_pool.clone()))
+            } else {
+                Arc::new(InMemoryRateLimitStore::new())
+            },
+            health_checks: vec![
+                Arc::new(MySQLHealthCheck::new(conn_pool)),
+                Arc::new(DynamoTableHealthCheck::new(
+                    dynamo_client,
+                    gacha_event_repository_table_name,
+                )),
+                Arc::new(S3BucketHealthCheck::new(s3_client, user_icon_upload_bucket)),
+            ],
+            gacha_catalog: Arc::new(GachaCatalog::default()),
+            daily_reset: DailyReset::default(),
+        }
+    }
+
+    pub fn in_memory(jwt_handler: Arc<JWTHandler>) -> Infras {
+        let store = Arc::new(InMemoryStore::new());
+
+        Infras {
+            jwt_handler,
+            dev_token_signer: None,
+            user_repository: Arc::new(InMemoryUserRepository::new(store.clone())),
+            gacha_event_repository: Arc::new(InMemoryGachaEventRepository::new(store.clone())),
+            gacha_state_repository: Arc::new(InMemoryGachaStateRepository::new(store.clone())),
+            pending_gacha_event_repository: Arc::new(InMemoryPendingGachaEventRepository::new(
+                store.clone(),
+            )),
+            gift_repository: Arc::new(InMemoryGiftRepository::new(store.clone())),
+            user_icon_uploader: Arc::new(InMemoryUserIconUploader::new()),
+            janken_repository: Arc::new(InMemoryJankenEventRepository::new(store.clone())),
+            point_repository: Arc::new(InMemoryPointEventRepository::new(store.clone())),
+            ranking_repository: Arc::new(InMemoryRankingRepository::new(store.clone())),
+            point_transaction_repository: Arc::new(InMemoryPointTransactionRepository::new(
+                store.clone(),
+            )),
+            unit_of_work: Arc::new(InMemoryUnitOfWork::new(store)),
+            health_checks: vec![],
+            rate_limit_store: Arc::new(InMemoryRateLimitStore::new()),
+            gacha_catalog: Arc::new(GachaCatalog::default()),
+            daily_reset: DailyReset::default(),
+        }
+    }
 }
 
 pub struct Services {
@@ -41,6 +206,8 @@ pub struct Services {
     pub janken_process_service: JankenProcessService,
     pub point_process_service: PointProcessService,
     pub point_ranking_service: PointRankingService,
+    pub point_transaction_service: PointTransactionService,
+    pub health_service: HealthService,
 }
 
 pub struct App {
@@ -49,40 +216,50 @@ pub struct App {
 }
 
 pub fn new(config: Config) -> App {
-    let conn_pool = Arc::new(ConnPool::new(&config.db_url).unwrap());
-    let dynamo_client = Arc::new(DynamoClient::new(config.aws_region.clone()));
-    let s3_client = Arc::new(S3Client::new(config.aws_region.clone()));
-    let user_repo = Arc::new(UserRepository::new(conn_pool.clone()));
-    let point_repo = Arc::new(PointEventRepository::new(conn_pool.clone()));
-
-    let infras = Infras {
-        dynamo_client: dynamo_client.clone(),
-        s3_client: s3_client.clone(),
-        jwt_handler: Arc::new(JWTHandler::new(config.public_key)),
-        user_repository: user_repo.clone(),
-        gacha_event_repository: Arc::new(GachaEventRepository::new(
-            dynamo_client.clone(),
-            config.gacha_event_repository_table_name,
-        )),
-        gift_repository: Arc::new(GiftRepository::new(conn_pool.clone())),
-        user_icon_uploader: Arc::new(UserIconUploader::new(
-            s3_client.clone(),
-            config.user_icon_upload_bucket,
-        )),
-        janken_repository: Arc::new(JankenEventRepository::new(conn_pool.clone())),
-        point_repository: point_repo.clone(),
-        ranking_repository: Arc::new(RankingRepository::new(conn_pool.clone())),
+    let jwt_handler = config.jwt_handler;
+    let mut infras = match config.backend {
+        Backend::Remote {
+            aws_region,
+            db_url,
+            gacha_event_repository_table_name,
+            user_icon_upload_bucket,
+            shared_rate_limit,
+        } => Infras::remote(
+            jwt_handler,
+            aws_region,
+            db_url,
+            gacha_event_repository_table_name,
+            user_icon_upload_bucket,
+            shared_rate_limit,
+        ),
+        Backend::InMemory => Infras::in_memory(jwt_handler),
     };
+    infras.dev_token_signer = config.dev_token_signer;
+    if let Some(catalog) = config.gacha_catalog {
+        infras.gacha_catalog = catalog;
+    }
+    infras.daily_reset = config.daily_reset;
+
+    from_infras(infras)
+}
+
+pub fn from_infras(infras: Infras) -> App {
     let services = Services {
         user_me_service: UserMeService::new(infras.user_repository.clone()),
         user_service: UserService::new(infras.user_repository.clone()),
         gacha_service: GachaService::new(
             infras.gacha_event_repository.clone(),
+            infras.gacha_state_repository.clone(),
+            infras.pending_gacha_event_repository.clone(),
             infras.user_repository.clone(),
+            infras.unit_of_work.clone(),
+            infras.gacha_catalog.clone(),
+            infras.daily_reset,
         ),
         gift_service: GiftService::new(
             infras.gift_repository.clone(),
             infras.user_repository.clone(),
+            infras.unit_of_work.clone(),
         ),
         gift_distribution_service: GiftDistributionService::new(
             infras.user_repository.clone(),
@@ -95,17 +272,29 @@ pub fn new(config: Config) -> App {
         janken_service: JankenService::new(
             infras.user_repository.clone(),
             infras.janken_repository.clone(),
+            infras.unit_of_work.clone(),
         ),
         janken_process_service: JankenProcessService::new(
             infras.janken_repository.clone(),
-            infras.gift_repository.clone(),
             infras.user_repository.clone(),
+            infras.unit_of_work.clone(),
+            infras.daily_reset,
         ),
         point_process_service: PointProcessService::new(
             infras.user_repository.clone(),
             infras.point_repository.clone(),
+            infras.daily_reset,
         ),
         point_ranking_service: PointRankingService::new(infras.ranking_repository.clone()),
+        point_transaction_service: PointTransactionService::new(
+            infras.user_repository.clone(),
+            infras.point_transaction_repository.clone(),
+        ),
+        health_service: HealthService::new(
+            infras.health_checks.clone(),
+            HEALTH_CHECK_TIMEOUT,
+            SCHEMA_VERSION,
+        ),
     };
 
     App { infras, services }
@@ -9,66 +9,294 @@ mod web;
 mod wrapper;
 pub use wrapper::*;
 
+use crate::domain::model::GachaCatalog;
 use crate::infra::{
-    GiftRecord, GiftUserRelation, JWTHandler, JankenEventRecord, PointEventRecord, UserRecord,
+    DevTokenSigner, GachaStateRecord, GiftRecord, GiftUserRelation, JWTHandler, JWTValidation,
+    JankenEventRecord, PendingGachaEventRecord, PointEventRecord, PointTransactionRecord,
+    PointTransactionRepository, RateLimitBucketRecord, SchemaMigration, SchemaMigrationRecord,
+    UserRecord,
 };
+use crate::wrapper::daily_reset::DailyReset;
+use crate::wrapper::error::ServiceError;
 use debil_mysql::DebilConn;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
-async fn migrate(mut conn: DebilConn) -> Result<(), debil_mysql::Error> {
+async fn migrate(conn: &mut DebilConn) -> Result<(), debil_mysql::Error> {
     conn.migrate::<UserRecord>().await?;
     conn.migrate::<GiftRecord>().await?;
     conn.migrate::<GiftUserRelation>().await?;
     conn.migrate::<JankenEventRecord>().await?;
     conn.migrate::<PointEventRecord>().await?;
+    conn.migrate::<PointTransactionRecord>().await?;
+    conn.migrate::<RateLimitBucketRecord>().await?;
+    conn.migrate::<GachaStateRecord>().await?;
+    conn.migrate::<PendingGachaEventRecord>().await?;
+    conn.migrate::<SchemaMigrationRecord>().await?;
 
     Ok(())
 }
 
+// 既存のデータを書き換える移行, それぞれ導入したSCHEMA_VERSIONで一度だけ行う
+async fn migrate_data(conn: &mut DebilConn) -> Result<(), ServiceError> {
+    // ledger導入前の残高
+    if !SchemaMigration::is_applied(conn, 8).await? {
+        let rows = PointTransactionRepository::record_opening_balances(conn).await?;
+        info!("Recorded opening balances for {} users", rows);
+        SchemaMigration::mark_applied(conn, 8).await?;
+    }
+
+    Ok(())
+}
+
+// カンマ区切りの環境変数, 未設定なら空
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 空だと検証されなくなる設定は, 設定し忘れたまま起動しないようにする
+fn env_list_required(key: &str) -> Vec<String> {
+    let list = env_list(key);
+    if list.is_empty() {
+        panic!("{} is required", key);
+    }
+
+    list
+}
+
+// 1分あたりの回数, 0のときは制限しない
+fn env_rate_limit_policy(
+    key: &str,
+    default: Option<server::RateLimitPolicy>,
+) -> Option<server::RateLimitPolicy> {
+    match env::var(key).ok().and_then(|v| v.parse().ok()) {
+        Some(0) => None,
+        Some(capacity) => Some(server::RateLimitPolicy::per_minute(capacity)),
+        None => default,
+    }
+}
+
 #[tokio::main]
 async fn main() {
     env::set_var("RUST_LOG", "info");
-    env_logger::init();
-    dotenv::dotenv().expect("Failed to load dotenv");
+    env_logger::Builder::from_default_env()
+        .format(log_context::format)
+        .init();
+    if let Err(err) = dotenv::dotenv() {
+        warn!("Failed to load dotenv: {:?}", err);
+    }
+
+    // AUTH_MODE=dev のときはIdPを使わず、手元の鍵で署名したトークンを受け付ける
+    let dev_token_signer = match env::var("AUTH_MODE").as_ref().map(|v| v.as_str()) {
+        Ok("dev") => {
+            // 誰でも管理者のトークンを作れるので, 本番のDBに繋ぐ設定では起動しない
+            if env::var("BACKEND").as_ref().map(|v| v.as_str()) != Ok("memory") {
+                panic!("AUTH_MODE=dev requires BACKEND=memory");
+            }
+
+            let signer = match env::var("DEV_JWT_RSA_PRIVATE_KEY") {
+                Ok(path) => DevTokenSigner::from_rsa_key_file(&path),
+                Err(_) => DevTokenSigner::from_secret(
+                    &env::var("DEV_JWT_SECRET").expect("DEV_JWT_SECRET is required"),
+                ),
+            };
+            Some(Arc::new(signer.expect("Failed to load dev signing key")))
+        }
+        _ => None,
+    };
 
-    let db_url = env::var("DB_URL").unwrap();
-    let public_key = JWTHandler::load_from_jwk(&env::var("JWK_URL").unwrap()).await;
-    let gacha_event_repository_table_name = env::var("GACHA_EVENT_REPOSITORY_TABLE_NAME").unwrap();
-    let user_icon_upload_bucket = env::var("USER_ICON_UPLOAD_BUCKET").unwrap();
+    let jwt_handler = match &dev_token_signer {
+        Some(signer) => Arc::new(JWTHandler::new(signer.public_key())),
+        None => {
+            let jwt_validation = JWTValidation {
+                issuers: env_list_required("JWT_ISSUERS"),
+                audiences: env_list_required("JWT_AUDIENCES"),
+                leeway: Duration::from_secs(
+                    env::var("JWT_LEEWAY_SECONDS")
+                        .ok()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(60),
+                ),
+                required_claims: JWTValidation::parse_required_claims(
+                    &env::var("JWT_REQUIRED_CLAIMS").unwrap_or_else(|_| "exp,sub".to_string()),
+                )
+                .expect("Invalid JWT_REQUIRED_CLAIMS"),
+            };
+
+            // 未知のkidが来たときのJWKSの取り直しは1分に1回まで
+            let jwt_handler = Arc::new(
+                JWTHandler::from_url(&env::var("JWK_URL").unwrap(), Duration::from_secs(60))
+                    .await
+                    .expect("Failed to load JWKS")
+                    .with_validation(jwt_validation),
+            );
+
+            let refresh_interval = env::var("JWK_REFRESH_INTERVAL_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3600);
+            jwt_handler.spawn_refresh_task(Duration::from_secs(refresh_interval));
+
+            jwt_handler
+        }
+    };
     let exec_task = env::var("EXECUTION_TASK");
 
+    // BACKEND=memory のときはMySQLもAWSも使わずに起動する
+    let backend = match env::var("BACKEND").as_ref().map(|v| v.as_str()) {
+        Ok("memory") => initializer::Backend::InMemory,
+        _ => initializer::Backend::Remote {
+            aws_region: rusoto_core::Region::ApNortheast1,
+            db_url: env::var("DB_URL").unwrap(),
+            gacha_event_repository_table_name: env::var("GACHA_EVENT_REPOSITORY_TABLE_NAME")
+                .unwrap(),
+            user_icon_upload_bucket: env::var("USER_ICON_UPLOAD_BUCKET").unwrap(),
+            shared_rate_limit: env::var("RATE_LIMIT_STORE")
+                .map(|v| v == "mysql")
+                .unwrap_or(false),
+        },
+    };
+    let db_url = match &backend {
+        initializer::Backend::Remote { db_url, .. } => Some(db_url.clone()),
+        initializer::Backend::InMemory => None,
+    };
+
+    // ガチャの種類とドロップテーブルはJSONファイルで差し替えられる
+    let gacha_catalog = env::var("GACHA_CATALOG_PATH").ok().map(|path| {
+        let json = std::fs::read_to_string(&path).expect("Failed to read GACHA_CATALOG_PATH");
+        Arc::new(GachaCatalog::from_json(&json).expect("Invalid gacha catalog"))
+    });
+
+    let daily_reset = DailyReset::new(
+        &env::var("DAILY_RESET_TIMEZONE").unwrap_or_else(|_| "Asia/Tokyo".to_string()),
+        env::var("DAILY_RESET_HOUR")
+            .map(|hour| hour.parse().expect("DAILY_RESET_HOUR must be a number"))
+            .unwrap_or(0),
+    )
+    .expect("Invalid DAILY_RESET_TIMEZONE or DAILY_RESET_HOUR");
+
     let app = initializer::new(initializer::Config {
-        aws_region: rusoto_core::Region::ApNortheast1,
-        db_url: db_url.clone(),
-        public_key: Arc::new(public_key),
-        gacha_event_repository_table_name,
-        user_icon_upload_bucket,
+        backend,
+        jwt_handler,
+        dev_token_signer: dev_token_signer.clone(),
+        gacha_catalog,
+        daily_reset,
     });
 
     match exec_task {
         Ok(task) => match task.as_str() {
             "janken" => {
-                if let Err(err) = app.services.janken_process_service.run().await {
+                // ワーカーが止まっていないかを監視できるようにメトリクスだけ公開する
+                if let Some(port) = env::var("METRICS_PORT").ok().and_then(|v| v.parse().ok()) {
+                    tokio::spawn(async move {
+                        server::HttpServer::new()
+                            .bind(([0, 0, 0, 0], port).into())
+                            .service(server::App::new(()).route(
+                                "/metrics",
+                                http::Method::GET,
+                                server::metrics_handler,
+                            ))
+                            .run()
+                            .await
+                            .unwrap();
+                    });
+                }
+
+                if let Err(err) = app
+                    .services
+                    .janken_process_service
+                    .run(shutdown::Shutdown::on_signal())
+                    .await
+                {
                     panic!("{:?}", err);
                 }
             }
             "ranking" => {
-                if let Err(err) = app.services.point_process_service.run().await {
+                if let Err(err) = app
+                    .services
+                    .point_process_service
+                    .run(shutdown::Shutdown::on_signal())
+                    .await
+                {
                     panic!("{:?}", err);
                 }
             }
+            // EXECUTION_TASK=mint_token cargo run -- <sub> [roles...]
+            "mint_token" => {
+                let signer = dev_token_signer.expect("AUTH_MODE=dev is required");
+                let args = env::args().skip(1).collect::<Vec<_>>();
+                let subject = args.first().expect("subject is required");
+                let token = signer
+                    .sign(subject, &args[1..], chrono::Duration::days(1))
+                    .unwrap();
+                println!("{}", token);
+            }
             _ => panic!("Unsupported task: {}", task),
         },
         Err(_) => {
-            let mut conn = debil_mysql::DebilConn::from_conn(
-                mysql_async::Conn::from_url(db_url.clone()).await.unwrap(),
-            );
-            migrate(conn).await.expect("Error in migration");
+            if let Some(db_url) = db_url {
+                let mut conn = debil_mysql::DebilConn::from_conn(
+                    mysql_async::Conn::from_url(db_url).await.unwrap(),
+                );
+                migrate(&mut conn).await.expect("Error in migration");
+                migrate_data(&mut conn)
+                    .await
+                    .expect("Error in data migration");
+            }
+
+            // AUTH_MODE=devでは未設定のときに全てのoriginを許可する, それ以外では必ず指定する
+            let cors = server::CorsConfig::new(
+                match &dev_token_signer {
+                    Some(_) => match env_list("CORS_ALLOWED_ORIGINS") {
+                        origins if origins.is_empty() => vec!["*".to_string()],
+                        origins => origins,
+                    },
+                    None => env_list_required("CORS_ALLOWED_ORIGINS"),
+                },
+                env::var("CORS_ALLOW_CREDENTIALS")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                env::var("CORS_MAX_AGE_SECONDS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .or(Some(600)),
+            )
+            .expect("CORS_ALLOW_CREDENTIALS=true requires CORS_ALLOWED_ORIGINS");
+
+            let defaults = web::RateLimitConfig::default();
+            let rate_limit = web::RateLimitConfig {
+                default_policy: env_rate_limit_policy(
+                    "RATE_LIMIT_PER_MINUTE",
+                    defaults.default_policy,
+                ),
+                ip_policy: env_rate_limit_policy("RATE_LIMIT_IP_PER_MINUTE", defaults.ip_policy),
+                // ロードバランサーの後ろでは必ずtrueにする
+                trust_forwarded_for: env::var("RATE_LIMIT_TRUST_FORWARDED_FOR")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+            };
+
+            // SIGTERMを受けてから処理中のリクエストを待つ時間
+            let drain_timeout = env::var("SHUTDOWN_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(25);
 
             server::HttpServer::new()
                 .bind(([0, 0, 0, 0], 1234).into())
-                .service(web::handlers(app))
+                .service(web::handlers(app, cors, rate_limit))
+                .graceful_shutdown(
+                    shutdown::Shutdown::on_signal(),
+                    Duration::from_secs(drain_timeout),
+                )
                 .run()
                 .await
                 .unwrap();
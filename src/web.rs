@@ -1,85 +1,291 @@
-use crate::domain::model::{Authorization, GiftId, GiftStatus};
+use crate::domain::model::{Authorization, GachaType, GiftId, GiftStatus};
+use crate::domain::service::{DistributeInput, JankenCreateInput, UpdateMeInput, UploadInput};
 use crate::initializer::App;
+use crate::log_context;
 use crate::server;
-use crate::wrapper::error::ServiceError;
-use serde::de::DeserializeOwned;
+use crate::server::{typed, BodyLimit, Extension, Json, Operation, Path, Query};
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::*;
 use std::sync::Arc;
 
+#[cfg(test)]
+pub mod test_harness;
+
 pub struct WebContext {
     app: App,
 }
 
 impl WebContext {
-    fn get_authorization(req: &server::Request, ctx: Arc<WebContext>) -> Authorization {
-        let r = || {
+    async fn authorize(req: &server::Request, ctx: Arc<WebContext>) -> Authorization {
+        let r = async {
             let auth = req
                 .headers()
                 .get("Authorization")
-                .ok_or(ServiceError::unauthorized(failure::err_msg(
-                    "no Authorization header",
-                )))?
+                .ok_or(ServiceError::unauthorized(
+                    ErrorCode::MissingAuthorizationHeader,
+                    failure::err_msg("no Authorization header"),
+                ))?
                 .to_str()
                 .map_err(|err| {
-                    ServiceError::bad_request(failure::Error::from_boxed_compat(Box::new(err)))
+                    ServiceError::bad_request(
+                        ErrorCode::InvalidAuthorizationHeader,
+                        failure::Error::from_boxed_compat(Box::new(err)),
+                    )
                 })?;
 
-            ctx.app.infras.jwt_handler.authorize(auth)
+            ctx.app.infras.jwt_handler.authorize(auth).await
         };
 
-        Authorization::new(r())
+        Authorization::new(r.await)
     }
+}
+
+// Extension<Authorization>を受け取るハンドラーは認証が必要なルートとしてドキュメントに載せる
+impl server::DescribeInput for Authorization {
+    fn describe(schema: server::RouteSchema) -> server::RouteSchema {
+        schema.authenticated()
+    }
+}
 
-    async fn read_body<T: DeserializeOwned>(body: hyper::Body) -> Result<T, ServiceError> {
-        use bytes::buf::BufExt;
+// Authorizationヘッダーを検証して結果をリクエストに持たせる
+// 認証が必要かどうかは各サービスがAuthorizationを見て判断する
+struct Authenticate;
 
-        let body = hyper::body::aggregate(body).await.map_err(|err| {
-            ServiceError::bad_request(failure::Error::from_boxed_compat(Box::new(err)))
-        })?;
-        let resp = serde_json::from_reader(body.reader())?;
-        Ok(resp)
+#[async_trait]
+impl server::Middleware<WebContext> for Authenticate {
+    async fn before(
+        &self,
+        req: &mut server::Request,
+        _params: &server::Params,
+        ctx: Arc<WebContext>,
+    ) -> Option<server::Response> {
+        let auth = WebContext::authorize(req, ctx).await;
+        if let Some(subject) = auth.subject() {
+            log_context::set_subject(subject);
+        }
+        req.extensions_mut().insert(auth);
+
+        None
     }
 }
 
-pub fn handlers(app: App) -> server::App<WebContext> {
-    server::App::new(WebContext { app })
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    // 個別に指定していないルートに共通で掛ける制限, Noneのときは制限しない
+    pub default_policy: Option<server::RateLimitPolicy>,
+    // 認証より前に接続元のIPごとに全ルート共通で掛ける制限, Noneのときは制限しない
+    pub ip_policy: Option<server::RateLimitPolicy>,
+    // ロードバランサーの後ろではtrueにしないと, 全てのクライアントが1つのIPとして数えられる
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            default_policy: Some(server::RateLimitPolicy::per_minute(120)),
+            ip_policy: Some(server::RateLimitPolicy::per_minute(600)),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+// 監視からのリクエストは制限しない
+fn without_monitoring(rate_limit: server::RateLimit) -> server::RateLimit {
+    rate_limit
+        .route("/metrics", None)
+        .route("/healthz", None)
+        .route("/readyz", None)
+        .route("/version", None)
+}
+
+// JWTの検証(未知のkidならJWKSの取り直し)より前に, 接続元のIPごとに数える
+// 不正なトークンを大量に送られても検証まで進ませない
+fn ip_rate_limit(
+    store: Arc<dyn server::IRateLimitStore + Sync + Send>,
+    config: &RateLimitConfig,
+) -> server::RateLimit {
+    without_monitoring(
+        server::RateLimit::new(store, config.ip_policy)
+            .prefix("ip")
+            .trust_forwarded_for(config.trust_forwarded_for),
+    )
+}
+
+// 認証済みならsubjectごと, そうでなければ接続元のIPごとに数える
+fn rate_limit(
+    store: Arc<dyn server::IRateLimitStore + Sync + Send>,
+    config: RateLimitConfig,
+) -> server::RateLimit {
+    let strict = config
+        .default_policy
+        .map(|_| server::RateLimitPolicy::per_minute(20));
+
+    without_monitoring(
+        server::RateLimit::new(store, config.default_policy)
+            .trust_forwarded_for(config.trust_forwarded_for)
+            .key_by(|req| {
+                req.extensions()
+                    .get::<Authorization>()
+                    .and_then(|auth| auth.subject())
+                    .map(|subject| format!("sub:{}", subject))
+            }),
+    )
+    // screen_nameの総当たりやS3へのアップロードは重いので厳しめにする
+    .route("/users/:screen_name/available", strict)
+    .route("/me/icon", strict)
+}
+
+pub fn handlers(
+    app: App,
+    cors: server::CorsConfig,
+    rate_limit_config: RateLimitConfig,
+) -> server::App<WebContext> {
+    let dev_mode = app.infras.dev_token_signer.is_some();
+    let ip_rate_limit = ip_rate_limit(app.infras.rate_limit_store.clone(), &rate_limit_config);
+    let rate_limit = rate_limit(app.infras.rate_limit_store.clone(), rate_limit_config);
+    let routes = server::App::new(WebContext { app })
+        .middleware(server::AccessLog)
+        .middleware(server::RequestMetrics)
+        .middleware(server::Cors::new(cors))
+        .middleware(ip_rate_limit)
+        .middleware(Authenticate)
+        .middleware(rate_limit)
         .route("/hello", http::Method::GET, api_hello)
-        .route("/me", http::Method::GET, api_get_me)
-        .route("/me", http::Method::PUT, api_update_me)
-        .route("/me/icon", http::Method::POST, api_upload_icon)
+        .route("/metrics", http::Method::GET, server::metrics_handler)
+        .doc(Operation::new("Prometheus metrics").tag("system"))
+        .route("/healthz", http::Method::GET, typed(api_healthz))
+        .doc(Operation::new("Liveness check").tag("system"))
+        .route("/readyz", http::Method::GET, typed(api_readyz))
+        .doc(Operation::new("Readiness check").tag("system"))
+        .route("/version", http::Method::GET, typed(api_version))
+        .doc(Operation::new("Build and schema version").tag("system"))
+        .route("/me", http::Method::GET, typed(api_get_me))
+        .doc(Operation::new("Get the signed-in user, creating it on first access").tag("me"))
+        .route("/me", http::Method::PUT, typed(api_update_me))
+        .doc(Operation::new("Update the signed-in user").tag("me"))
+        // 画像をbase64で送るので大きめに取る
+        .route_with(
+            "/me/icon",
+            http::Method::POST,
+            vec![Arc::new(BodyLimit(5 * 1024 * 1024))],
+            typed(api_upload_icon),
+        )
+        .doc(Operation::new("Upload an icon image").tag("me"))
+        .route(
+            "/me/point_transactions",
+            http::Method::GET,
+            typed(api_list_point_transactions),
+        )
+        .doc(Operation::new("List point transactions of the signed-in user").tag("me"))
         .route(
             "/users/:screen_name/available",
             http::Method::GET,
-            api_check_user_available,
+            typed(api_check_user_available),
         )
-        .route("/users/:screen_name", http::Method::GET, api_get_user)
-        .route("/gacha/daily", http::Method::POST, api_try_daily_gacha)
+        .doc(Operation::new("Check whether a screen name is available").tag("users"))
+        .route(
+            "/users/:screen_name",
+            http::Method::GET,
+            typed(api_get_user),
+        )
+        .doc(Operation::new("Get a user by screen name").tag("users"))
+        .route(
+            "/gacha/daily",
+            http::Method::POST,
+            typed(api_try_daily_gacha),
+        )
+        .doc(Operation::new("Draw the daily gacha").tag("gacha"))
         .route(
             "/gacha/daily/latest",
             http::Method::GET,
-            api_get_latest_daily_gacha,
+            typed(api_get_latest_daily_gacha),
         )
+        .doc(Operation::new("Get the latest daily gacha event").tag("gacha"))
         .route(
             "/gacha/daily/record",
             http::Method::GET,
-            api_get_daily_gacha_record,
+            typed(api_get_daily_gacha_record),
         )
-        .route("/gift/ready", http::Method::GET, api_list_gifts_ready)
-        .route("/gift/opened", http::Method::GET, api_list_gifts_opened)
-        .route("/gift/:gift_id/open", http::Method::POST, api_open_gift)
+        .doc(Operation::new("Get the daily gacha status").tag("gacha"))
+        .route("/gacha", http::Method::GET, typed(api_get_gacha_catalog))
+        .doc(Operation::new("List the available gacha types and their drop tables").tag("gacha"))
+        .route(
+            "/gacha/:gacha_type",
+            http::Method::POST,
+            typed(api_draw_gacha),
+        )
+        .doc(Operation::new("Draw a gacha defined in the catalog").tag("gacha"))
+        .route(
+            "/gacha/:gacha_type/multi",
+            http::Method::POST,
+            typed(api_draw_multi_gacha),
+        )
+        .doc(
+            Operation::new("Draw a gacha several times at once with a guaranteed rarity")
+                .tag("gacha"),
+        )
+        .route(
+            "/gacha/:gacha_type/record",
+            http::Method::GET,
+            typed(api_get_gacha_record),
+        )
+        .doc(Operation::new("Get the status of a gacha including the pity count").tag("gacha"))
+        .route(
+            "/gift/ready",
+            http::Method::GET,
+            typed(api_list_gifts_ready),
+        )
+        .doc(Operation::new("List unopened gifts").tag("gift"))
+        .route(
+            "/gift/opened",
+            http::Method::GET,
+            typed(api_list_gifts_opened),
+        )
+        .doc(Operation::new("List opened gifts").tag("gift"))
+        .route(
+            "/gift/:gift_id/open",
+            http::Method::POST,
+            typed(api_open_gift),
+        )
+        .doc(Operation::new("Open a gift").tag("gift"))
         .route(
             "/admin/gift/distribute_all",
             http::Method::POST,
-            api_admin_distribute_gift,
+            typed(api_admin_distribute_gift),
         )
-        .route("/janken", http::Method::POST, api_create_janken)
-        .route("/janken", http::Method::GET, api_list_janken_events)
-        .route("/ranking/top", http::Method::GET, api_ranking_top)
-        .route("/ranking/diff", http::Method::GET, api_ranking_diff)
+        .doc(Operation::new("Distribute a point gift to every user").tag("admin"))
+        .route("/janken", http::Method::POST, typed(api_create_janken))
+        .doc(Operation::new("Enter a janken match").tag("janken"))
+        .route("/janken", http::Method::GET, typed(api_list_janken_events))
+        .doc(Operation::new("List janken matches of the signed-in user").tag("janken"))
+        .route("/ranking/top", http::Method::GET, typed(api_ranking_top))
+        .doc(Operation::new("Users with the most points").tag("ranking"))
+        .route("/ranking/diff", http::Method::GET, typed(api_ranking_diff))
+        .doc(Operation::new("Users who gained the most points recently").tag("ranking"))
         .route(
             "/ranking/start_execution",
             http::Method::POST,
-            api_ranking_batch_start,
+            typed(api_ranking_batch_start),
         )
+        .doc(Operation::new("Start the ranking batch").tag("ranking"));
+
+    // AUTH_MODE=devのときだけトークンを発行できる
+    let routes = if dev_mode {
+        routes
+            .route("/dev/token", http::Method::POST, typed(api_dev_issue_token))
+            .doc(Operation::new("Issue a token for local development").tag("dev"))
+    } else {
+        routes
+    };
+
+    // 全てのルートを登録してから作る
+    routes.openapi_route(
+        "/openapi.json",
+        "jitome-kingdom-api",
+        env!("CARGO_PKG_VERSION"),
+    )
 }
 
 async fn api_hello(
@@ -93,288 +299,990 @@ async fn api_hello(
     })))
 }
 
-async fn api_get_me(
-    req: server::Request,
-    ps: server::Params,
+async fn api_healthz(
+    _: (),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    Ok(ctx.app.services.health_service.liveness())
+}
 
-    server::response_from(ctx.app.services.user_me_service.get_me(auth).await)
+async fn api_readyz(
+    _: (),
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app.services.health_service.readiness().await
 }
 
-async fn api_update_me(
-    req: server::Request,
-    ps: server::Params,
+async fn api_version(
+    _: (),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    Ok(ctx.app.services.health_service.version())
+}
 
-    server::response_from_async(async {
-        let body = WebContext::read_body(req.into_body()).await?;
+#[derive(Deserialize)]
+struct ScreenNamePath {
+    screen_name: String,
+}
 
-        ctx.app.services.user_me_service.update_me(auth, body).await
-    })
-    .await
+#[derive(Deserialize)]
+struct GachaTypePath {
+    gacha_type: String,
 }
 
-async fn api_upload_icon(
-    req: server::Request,
-    ps: server::Params,
+#[derive(Deserialize)]
+struct GiftIdPath {
+    gift_id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct LimitQuery {
+    limit: Option<i32>,
+}
+
+async fn api_get_me(
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app.services.user_me_service.get_me(auth).await
+}
 
-    server::response_from_async(async {
-        let body = WebContext::read_body(req.into_body()).await?;
+async fn api_update_me(
+    (Extension(auth), Json(input)): (Extension<Authorization>, Json<UpdateMeInput>),
+    ctx: Arc<WebContext>,
+) -> Result<(), ServiceError> {
+    ctx.app
+        .services
+        .user_me_service
+        .update_me(auth, input)
+        .await
+}
 
-        ctx.app
-            .services
-            .user_icon_upload_service
-            .upload(auth, body)
-            .await
-    })
-    .await
+async fn api_upload_icon(
+    (Extension(auth), Json(input)): (Extension<Authorization>, Json<UploadInput>),
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .user_icon_upload_service
+        .upload(auth, input)
+        .await
 }
 
-async fn api_get_user(
-    req: server::Request,
-    ps: server::Params,
+async fn api_list_point_transactions(
+    (Extension(auth), Query(query)): (Extension<Authorization>, Query<LimitQuery>),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let screen_name = match ps.find("screen_name") {
-        None => {
-            return server::response_from::<()>(Err(ServiceError::bad_request(failure::err_msg(
-                "not_found",
-            ))))
-        }
-        Some(v) => v,
-    };
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .point_transaction_service
+        .list_by_user(auth, query.limit.unwrap_or(20))
+        .await
+}
 
-    server::response_from(
-        ctx.app
-            .services
-            .user_service
-            .find_by_screen_name(screen_name)
-            .await,
-    )
+async fn api_get_user(
+    Path(path): Path<ScreenNamePath>,
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .user_service
+        .find_by_screen_name(path.screen_name)
+        .await
 }
 
 async fn api_check_user_available(
-    req: server::Request,
-    ps: server::Params,
+    (Extension(auth), Path(path)): (Extension<Authorization>, Path<ScreenNamePath>),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-    let screen_name = match ps.find("screen_name") {
-        None => {
-            return server::response_from::<()>(Err(ServiceError::bad_request(failure::err_msg(
-                "not_found",
-            ))))
-        }
-        Some(v) => v,
-    };
-
-    server::response_from_async(async {
-        ctx.app
-            .services
-            .user_service
-            .is_screen_name_available(auth, screen_name)
-            .await
-    })
-    .await
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .user_service
+        .is_screen_name_available(auth, path.screen_name)
+        .await
 }
 
 async fn api_try_daily_gacha(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(ctx.app.services.gacha_service.try_daily(auth).await)
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app.services.gacha_service.try_daily(auth).await
 }
 
 async fn api_get_latest_daily_gacha(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(
-        ctx.app
-            .services
-            .gacha_service
-            .get_latest_daily_event(auth)
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gacha_service
+        .get_latest_daily_event(auth)
+        .await
 }
 
 async fn api_get_daily_gacha_record(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(
-        ctx.app
-            .services
-            .gacha_service
-            .get_daily_gacha_record(auth)
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gacha_service
+        .get_daily_gacha_record(auth)
+        .await
+}
+
+async fn api_get_gacha_catalog(
+    _: (),
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    Ok(ctx.app.services.gacha_service.get_catalog().clone())
+}
+
+async fn api_draw_gacha(
+    (Extension(auth), Path(path)): (Extension<Authorization>, Path<GachaTypePath>),
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gacha_service
+        .draw(auth, GachaType::new(&path.gacha_type))
+        .await
+}
+
+async fn api_draw_multi_gacha(
+    (Extension(auth), Path(path)): (Extension<Authorization>, Path<GachaTypePath>),
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gacha_service
+        .draw_multi(auth, GachaType::new(&path.gacha_type))
+        .await
+}
+
+async fn api_get_gacha_record(
+    (Extension(auth), Path(path)): (Extension<Authorization>, Path<GachaTypePath>),
+    ctx: Arc<WebContext>,
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gacha_service
+        .get_gacha_record(auth, GachaType::new(&path.gacha_type))
+        .await
 }
 
 async fn api_list_gifts_ready(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(
-        ctx.app
-            .services
-            .gift_service
-            .list_by_status(auth, GiftStatus::Ready)
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gift_service
+        .list_by_status(auth, GiftStatus::Ready)
+        .await
 }
 
 async fn api_list_gifts_opened(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(
-        ctx.app
-            .services
-            .gift_service
-            .list_by_status(auth, GiftStatus::Opened)
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gift_service
+        .list_by_status(auth, GiftStatus::Opened)
+        .await
 }
 
 async fn api_open_gift(
-    req: server::Request,
-    ps: server::Params,
+    (Extension(auth), Path(path)): (Extension<Authorization>, Path<GiftIdPath>),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-    let gift_id = match ps.find("gift_id") {
-        None => {
-            return server::response_from::<()>(Err(ServiceError::bad_request(failure::err_msg(
-                "not_found",
-            ))))
-        }
-        Some(v) => v,
-    };
-
-    server::response_from(
-        ctx.app
-            .services
-            .gift_service
-            .open(auth, &GiftId(gift_id))
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .gift_service
+        .open(auth, &GiftId(path.gift_id))
+        .await
 }
 
 async fn api_admin_distribute_gift(
-    req: server::Request,
-    ps: server::Params,
+    (Extension(auth), Json(input)): (Extension<Authorization>, Json<DistributeInput>),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from_async(async {
-        let body = WebContext::read_body(req.into_body()).await?;
-
-        ctx.app
-            .services
-            .gift_distribution_service
-            .distribute_point(auth, body)
-            .await
-    })
-    .await
+) -> Result<(), ServiceError> {
+    ctx.app
+        .services
+        .gift_distribution_service
+        .distribute_point(auth, input)
+        .await
 }
 
 async fn api_create_janken(
-    req: server::Request,
-    ps: server::Params,
+    (Extension(auth), Json(input)): (Extension<Authorization>, Json<JankenCreateInput>),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from_async(async {
-        let body = WebContext::read_body(req.into_body()).await?;
-
-        ctx.app.services.janken_service.create(auth, body).await
-    })
-    .await
+) -> Result<(), ServiceError> {
+    ctx.app.services.janken_service.create(auth, input).await
 }
 
 async fn api_list_janken_events(
-    req: server::Request,
-    ps: server::Params,
+    (Extension(auth), Query(query)): (Extension<Authorization>, Query<LimitQuery>),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-    // urlが相対パスをパースできないので適当にoriginを設定
-    let query = url::Url::parse("http://localhost")
-        .and_then(|u| u.join(&req.uri().to_string()))
-        .ok()
-        .and_then(|u| {
-            u.query_pairs()
-                .into_iter()
-                .collect::<std::collections::HashMap<_, _>>()
-                .get("limit")
-                .and_then(|r| r.parse::<i32>().ok())
-        })
-        .unwrap_or(20);
-
-    server::response_from_async(ctx.app.services.janken_service.find_by_user_id(auth, query)).await
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .janken_service
+        .find_by_user_id(auth, query.limit.unwrap_or(20))
+        .await
 }
 
 async fn api_ranking_top(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(
-        ctx.app
-            .services
-            .point_ranking_service
-            .list_by_points(auth)
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .point_ranking_service
+        .list_by_points(auth)
+        .await
 }
 
 async fn api_ranking_diff(
-    req: server::Request,
-    ps: server::Params,
+    Extension(auth): Extension<Authorization>,
     ctx: Arc<WebContext>,
-) -> server::Response {
-    let auth = WebContext::get_authorization(&req, ctx.clone());
-
-    server::response_from(
-        ctx.app
-            .services
-            .point_ranking_service
-            .list_by_diff(auth)
-            .await,
-    )
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app
+        .services
+        .point_ranking_service
+        .list_by_diff(auth)
+        .await
 }
 
 async fn api_ranking_batch_start(
-    req: server::Request,
-    ps: server::Params,
+    _: (),
     ctx: Arc<WebContext>,
-) -> server::Response {
-    server::response_from(ctx.app.services.point_process_service.start().await)
+) -> Result<impl Serialize + JsonSchema, ServiceError> {
+    ctx.app.services.point_process_service.start().await
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DevTokenInput {
+    sub: String,
+    #[serde(default)]
+    roles: Vec<String>,
+    // 秒, 省略時は1日
+    expires_in: Option<i64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct DevTokenResponse {
+    token: String,
+}
+
+async fn api_dev_issue_token(
+    Json(input): Json<DevTokenInput>,
+    ctx: Arc<WebContext>,
+) -> Result<DevTokenResponse, ServiceError> {
+    let signer = ctx.app.infras.dev_token_signer.as_ref().ok_or_else(|| {
+        ServiceError::not_found(ErrorCode::NotFound, failure::err_msg("not found"))
+    })?;
+    let token = signer.sign(
+        &input.sub,
+        &input.roles,
+        chrono::Duration::seconds(input.expires_in.unwrap_or(60 * 60 * 24)),
+    )?;
+
+    Ok(DevTokenResponse { token })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_harness::{TestRequest, TestServer};
+    use super::RateLimitConfig;
+    use crate::domain::interface::{IHealthCheck, IRankingRepository};
+    use crate::domain::model::{GachaCatalog, PointDiffRankingRecord};
+    use crate::infra::{DevTokenSigner, JWTHandler};
+    use crate::wrapper::error::{ErrorCode, ServiceError};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn unknown_route_should_be_not_found() {
+        let server = TestServer::new();
+
+        let resp = server.send(TestRequest::get("/unknown")).await;
+        assert_eq!(resp.status, http::StatusCode::NOT_FOUND);
+
+        let resp = server.send(TestRequest::put("/gacha/daily")).await;
+        assert_eq!(resp.status, http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn options_should_return_cors_headers() {
+        let server = TestServer::new();
+
+        let resp = server
+            .send(TestRequest::new(http::Method::OPTIONS, "/janken"))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(
+            resp.headers[hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "*"
+        );
+        assert!(resp
+            .headers
+            .contains_key(hyper::header::ACCESS_CONTROL_ALLOW_METHODS));
+    }
+
+    #[tokio::test]
+    async fn get_me_should_require_valid_authorization() {
+        let server = TestServer::new();
+
+        let resp = server.send(TestRequest::get("/me")).await;
+        assert_eq!(resp.status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.json()["error"]["code"], "missing_authorization_header");
+
+        let token = server.token("auth0|me");
+        let resp = server
+            .send(TestRequest::get("/me").header("Authorization", &token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.json()["error"]["code"], "invalid_authorization_header");
+
+        let resp = server
+            .send(TestRequest::get("/me").bearer("invalid.jwt.token"))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.json()["error"]["code"], "invalid_token");
+    }
+
+    #[tokio::test]
+    async fn get_me_should_create_user() {
+        let server = TestServer::new();
+
+        let resp = server
+            .send(TestRequest::get("/me").bearer(&server.admin_token("auth0|me")))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let body = resp.json();
+        assert_eq!(body["subject"], "auth0|me");
+        assert_eq!(body["point"], 0);
+        assert_eq!(body["roles"], serde_json::json!(["admin"]));
+    }
+
+    #[tokio::test]
+    async fn update_me_and_find_by_screen_name() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+        let resp = server
+            .send(
+                TestRequest::put("/me")
+                    .bearer(&token)
+                    .json(serde_json::json!({
+                        "screen_name": "myuon",
+                        "display_name": "みょん",
+                        "picture_url": "https://example.com/icon.png",
+                    })),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server.send(TestRequest::get("/users/myuon")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(resp.json()["display_name"], "みょん");
+
+        let resp = server.send(TestRequest::get("/users/unknown")).await;
+        assert_eq!(resp.status, http::StatusCode::NOT_FOUND);
+
+        let resp = server
+            .send(TestRequest::get("/users/myuon/available").bearer(&token))
+            .await;
+        assert_eq!(resp.json(), serde_json::json!({ "availability": false }));
+    }
+
+    #[tokio::test]
+    async fn daily_gacha_should_be_tried_once_a_day() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        let resp = server
+            .send(TestRequest::get("/gacha/daily/latest").bearer(&token))
+            .await;
+        assert_eq!(resp.json(), serde_json::json!(null));
+
+        let resp = server
+            .send(TestRequest::post("/gacha/daily").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server
+            .send(TestRequest::post("/gacha/daily").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            resp.json()["error"]["code"],
+            "daily_gacha_rate_limit_exceeded"
+        );
+
+        let resp = server
+            .send(TestRequest::get("/gacha/daily/record").bearer(&token))
+            .await;
+        assert_eq!(resp.json()["is_available"], false);
+        assert_eq!(resp.json()["latest"]["gacha_type"], "daily");
+
+        let resp = server
+            .send(TestRequest::get("/me/point_transactions").bearer(&token))
+            .await;
+        let data = resp.json()["data"].as_array().unwrap().clone();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0]["reason"], "daily_gacha");
+    }
+
+    #[tokio::test]
+    async fn janken_should_pay_bet_point() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        // ポイントが足りない
+        let resp = server
+            .send(
+                TestRequest::post("/janken")
+                    .bearer(&token)
+                    .json(serde_json::json!({ "hand": "rock" })),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(resp.json()["error"]["code"], "not_enough_point");
+        assert_eq!(
+            resp.json()["error"]["details"],
+            serde_json::json!({ "required": 5, "current": 0 })
+        );
+
+        server
+            .send(TestRequest::post("/gacha/daily").bearer(&token))
+            .await;
+        let point = server
+            .send(TestRequest::get("/me").bearer(&token))
+            .await
+            .json()["point"]
+            .as_u64()
+            .unwrap();
+
+        let resp = server
+            .send(
+                TestRequest::post("/janken")
+                    .bearer(&token)
+                    .json(serde_json::json!({ "hand": "rock" })),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server
+            .send(TestRequest::get("/janken?limit=5").bearer(&token))
+            .await;
+        let events = resp.json()["events"].as_array().unwrap().clone();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["hand"], "rock");
+        assert_eq!(events[0]["status"], "ready");
+
+        let resp = server.send(TestRequest::get("/me").bearer(&token)).await;
+        assert_eq!(resp.json()["point"], point - 5);
+    }
+
+    #[tokio::test]
+    async fn distributed_gift_should_be_opened_once() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+        let admin_token = server.admin_token("auth0|admin");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        let input = serde_json::json!({ "point": 10, "description": "gift" });
+        let resp = server
+            .send(
+                TestRequest::post("/admin/gift/distribute_all")
+                    .bearer(&token)
+                    .json(input.clone()),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::FORBIDDEN);
+
+        let resp = server
+            .send(
+                TestRequest::post("/admin/gift/distribute_all")
+                    .bearer(&admin_token)
+                    .json(input),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server
+            .send(TestRequest::get("/gift/ready").bearer(&token))
+            .await;
+        let gifts = resp.json()["data"].as_array().unwrap().clone();
+        assert_eq!(gifts.len(), 1);
+        let path = format!("/gift/{}/open", gifts[0]["id"].as_str().unwrap());
+
+        let resp = server.send(TestRequest::post(&path).bearer(&token)).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server.send(TestRequest::post(&path).bearer(&token)).await;
+        assert_eq!(resp.status, http::StatusCode::CONFLICT);
+        assert_eq!(resp.json()["error"]["code"], "gift_cannot_be_opened");
+
+        let resp = server
+            .send(TestRequest::get("/gift/opened").bearer(&token))
+            .await;
+        assert_eq!(resp.json()["data"].as_array().unwrap().len(), 1);
+
+        let resp = server.send(TestRequest::get("/me").bearer(&token)).await;
+        assert_eq!(resp.json()["point"], 10);
+    }
+
+    #[tokio::test]
+    async fn ranking_should_list_after_execution() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        let resp = server
+            .send(TestRequest::get("/ranking/top").bearer(&token))
+            .await;
+        assert_eq!(resp.json(), serde_json::json!([]));
+
+        let resp = server
+            .send(TestRequest::post("/ranking/start_execution"))
+            .await;
+        assert_eq!(resp.json(), serde_json::json!({ "executed": true }));
+
+        let resp = server
+            .send(TestRequest::get("/ranking/top").bearer(&token))
+            .await;
+        assert_eq!(resp.json().as_array().unwrap().len(), 1);
+
+        let resp = server
+            .send(TestRequest::get("/ranking/diff").bearer(&token))
+            .await;
+        assert_eq!(resp.json()[0]["diff"], 0);
+    }
+
+    struct FailingRankingRepository;
+
+    #[async_trait]
+    impl IRankingRepository for FailingRankingRepository {
+        async fn list_top_points(
+            &self,
+            _limit: u64,
+        ) -> Result<Vec<PointDiffRankingRecord>, ServiceError> {
+            Err(ServiceError::internal_server_error(
+                ErrorCode::DatabaseError,
+                failure::err_msg("connection refused"),
+            ))
+        }
+
+        async fn list_top_point_diffs(
+            &self,
+            _limit: u64,
+        ) -> Result<Vec<PointDiffRankingRecord>, ServiceError> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn injected_repository_should_be_used() {
+        let server = TestServer::with_infras(|infras| {
+            infras.ranking_repository = Arc::new(FailingRankingRepository);
+        });
+
+        let resp = server
+            .send(TestRequest::get("/ranking/top").bearer(&server.token("auth0|me")))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            resp.json(),
+            serde_json::json!({
+                "error": {
+                    "code": "database_error",
+                    "message": "connection refused",
+                    "details": null,
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_should_expose_requests_and_domain_counters() {
+        let server = TestServer::new();
+        let token = server.token("auth0|metrics");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+        let resp = server
+            .send(TestRequest::post("/gacha/daily").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server.send(TestRequest::get("/metrics")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert!(resp.headers[hyper::header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+
+        let text = resp.body;
+        assert!(text
+            .contains(r#"http_requests_total{method="POST",route="/gacha/daily",status="200"}"#));
+        assert!(text.contains("daily_gacha_drawn_total"));
+        assert!(text.contains(r#"points_minted_total{reason="daily_gacha"}"#));
+    }
+
+    struct FailingHealthCheck;
+
+    #[async_trait]
+    impl IHealthCheck for FailingHealthCheck {
+        fn name(&self) -> &str {
+            "mysql"
+        }
+
+        async fn check(&self) -> Result<(), ServiceError> {
+            Err(ServiceError::service_unavailable(
+                ErrorCode::DatabaseUnavailable,
+                failure::err_msg("connection refused"),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn health_endpoints_should_not_require_authorization() {
+        let server = TestServer::new();
+
+        let resp = server.send(TestRequest::get("/healthz")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(resp.json(), serde_json::json!({ "status": "ok" }));
+
+        let resp = server.send(TestRequest::get("/readyz")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(
+            resp.json(),
+            serde_json::json!({ "status": "ok", "checks": [] })
+        );
+
+        let resp = server.send(TestRequest::get("/version")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(resp.json()["schema_version"], crate::infra::SCHEMA_VERSION);
+        assert!(resp.json()["git_sha"].is_string());
+    }
+
+    #[tokio::test]
+    async fn readyz_should_be_unavailable_when_dependency_fails() {
+        let server = TestServer::with_infras(|infras| {
+            infras.health_checks = vec![Arc::new(FailingHealthCheck)];
+        });
+
+        let resp = server.send(TestRequest::get("/readyz")).await;
+        assert_eq!(resp.status, http::StatusCode::SERVICE_UNAVAILABLE);
+        let body = resp.json();
+        assert_eq!(body["error"]["code"], "dependency_unavailable");
+        assert_eq!(body["error"]["details"]["checks"][0]["name"], "mysql");
+        assert_eq!(body["error"]["details"]["checks"][0]["ok"], false);
+
+        // 生きてはいる
+        let resp = server.send(TestRequest::get("/healthz")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn screen_name_check_should_be_rate_limited_per_subject() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        for _ in 0..20 {
+            let resp = server
+                .send(TestRequest::get("/users/myuon/available").bearer(&token))
+                .await;
+            assert_eq!(resp.status, http::StatusCode::OK);
+        }
+
+        let resp = server
+            .send(TestRequest::get("/users/myuon/available").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.json()["error"]["code"], "rate_limit_exceeded");
+        assert_eq!(resp.headers[hyper::header::RETRY_AFTER], "3");
+
+        // 他のルートや他のユーザーには影響しない
+        let resp = server.send(TestRequest::get("/me").bearer(&token)).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let other = server.token("auth0|other");
+        server.send(TestRequest::get("/me").bearer(&other)).await;
+        let resp = server
+            .send(TestRequest::get("/users/myuon/available").bearer(&other))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn anonymous_flood_should_be_limited_per_ip_before_authentication() {
+        let server = TestServer::with_rate_limit(RateLimitConfig {
+            ip_policy: Some(crate::server::RateLimitPolicy::per_minute(3)),
+            trust_forwarded_for: true,
+            ..Default::default()
+        });
+
+        // 検証できないトークンでも認証より前に数えられる
+        for _ in 0..3 {
+            let resp = server
+                .send(
+                    TestRequest::get("/me")
+                        .bearer("invalid")
+                        .header("X-Forwarded-For", "203.0.113.1"),
+                )
+                .await;
+            assert_eq!(resp.status, http::StatusCode::UNAUTHORIZED);
+        }
+        let resp = server
+            .send(
+                TestRequest::get("/me")
+                    .bearer("invalid")
+                    .header("X-Forwarded-For", "203.0.113.1"),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.json()["error"]["code"], "rate_limit_exceeded");
+
+        // 他のIPや監視のルートには影響しない
+        let resp = server
+            .send(
+                TestRequest::get("/me")
+                    .bearer("invalid")
+                    .header("X-Forwarded-For", "203.0.113.2"),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::UNAUTHORIZED);
+        let resp = server
+            .send(TestRequest::get("/healthz").header("X-Forwarded-For", "203.0.113.1"))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn openapi_should_describe_routes() {
+        let server = TestServer::new();
+
+        let resp = server.send(TestRequest::get("/openapi.json")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        let spec = resp.json();
+        assert_eq!(spec["openapi"], "3.0.3");
+
+        let me = &spec["paths"]["/me"];
+        assert_eq!(
+            me["get"]["security"][0]["bearerAuth"],
+            serde_json::json!([])
+        );
+        assert_eq!(
+            me["put"]["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/UpdateMeInput"
+        );
+
+        let user = &spec["paths"]["/users/{screen_name}"]["get"];
+        assert_eq!(user["parameters"][0]["name"], "screen_name");
+        assert_eq!(user["parameters"][0]["in"], "path");
+
+        let gacha = &spec["paths"]["/gacha/daily"]["post"];
+        assert_eq!(
+            gacha["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/DailyGachaResponse"
+        );
+
+        let transactions = &spec["paths"]["/me/point_transactions"]["get"];
+        assert_eq!(transactions["parameters"][0]["name"], "limit");
+        assert_eq!(transactions["parameters"][0]["in"], "query");
+
+        let schemas = &spec["components"]["schemas"];
+        for name in &[
+            "UserProfile",
+            "UpdateMeInput",
+            "JankenCreateInput",
+            "DistributeInput",
+            "DailyGachaRecord",
+            "ListGiftResponse",
+            "ErrorResponse",
+        ] {
+            assert!(schemas[name].is_object(), "{} is missing", name);
+        }
+        assert_eq!(
+            schemas["JankenHand"]["enum"],
+            serde_json::json!(["rock", "paper", "scissors"])
+        );
+
+        // devモードでないときは載らない
+        assert!(spec["paths"].get("/dev/token").is_none());
+    }
+
+    #[tokio::test]
+    async fn dev_token_should_be_issued_only_in_dev_mode() {
+        let server = TestServer::new();
+        let resp = server
+            .send(TestRequest::post("/dev/token").json(serde_json::json!({ "sub": "auth0|dev" })))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::NOT_FOUND);
+
+        let server = TestServer::with_infras(|infras| {
+            let signer = Arc::new(DevTokenSigner::from_secret("dev-secret").unwrap());
+            infras.jwt_handler = Arc::new(JWTHandler::new(signer.public_key()));
+            infras.dev_token_signer = Some(signer);
+        });
+        let resp = server
+            .send(TestRequest::post("/dev/token").json(serde_json::json!({
+                "sub": "auth0|dev",
+                "roles": ["admin"],
+            })))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        let token = resp.json()["token"].as_str().unwrap().to_string();
+
+        let resp = server.send(TestRequest::get("/me").bearer(&token)).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server
+            .send(
+                TestRequest::post("/admin/gift/distribute_all")
+                    .bearer(&token)
+                    .json(serde_json::json!({ "point": 10, "description": "gift" })),
+            )
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn invalid_query_should_be_bad_request() {
+        let server = TestServer::new();
+        let token = server.token("auth0|me");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        let resp = server
+            .send(TestRequest::get("/janken?limit=10").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+
+        let resp = server
+            .send(TestRequest::get("/janken?limit=many").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(resp.json()["error"]["code"], "invalid_query_parameter");
+    }
+
+    #[tokio::test]
+    async fn named_gacha_should_follow_catalog() {
+        let server = TestServer::with_infras(|infras| {
+            infras.gacha_catalog = Arc::new(
+                GachaCatalog::from_json(
+                    r#"{
+                        "gachas": [
+                            {
+                                "gacha_type": "daily",
+                                "cost": 0,
+                                "cooldown": "daily",
+                                "drops": [
+                                    { "weight": 1, "rarity": "common", "reward": { "point": { "min": 5, "max": 15 } } }
+                                ]
+                            },
+                            {
+                                "gacha_type": "hourly",
+                                "cost": 0,
+                                "cooldown": { "seconds": 3600 },
+                                "drops": [
+                                    { "weight": 1, "rarity": "rare", "reward": { "item": { "name": "badge" } } }
+                                ]
+                            },
+                            {
+                                "gacha_type": "premium",
+                                "cost": 30,
+                                "cooldown": "none",
+                                "drops": [
+                                    { "weight": 1, "rarity": "super_rare", "reward": { "point": { "min": 10, "max": 100 } } }
+                                ]
+                            }
+                        ]
+                    }"#,
+                )
+                .unwrap(),
+            );
+        });
+        let token = server.token("auth0|me");
+        server.send(TestRequest::get("/me").bearer(&token)).await;
+
+        let resp = server.send(TestRequest::get("/gacha")).await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(resp.json()["gachas"][1]["gacha_type"], "hourly");
+
+        let resp = server
+            .send(TestRequest::post("/gacha/hourly").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert_eq!(
+            resp.json(),
+            serde_json::json!({
+                "gacha_type": "hourly",
+                "obtained": 0,
+                "result": { "rarity": "rare", "point": 0, "item": "badge" },
+                "pity_count": 0,
+                "streak": null,
+                "streak_bonus": null,
+            })
+        );
+
+        let resp = server
+            .send(TestRequest::get("/gacha/hourly/record").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        let body = resp.json();
+        assert_eq!(body["gacha_type"], "hourly");
+        assert_eq!(body["is_available"], false);
+        assert_eq!(body["pity_count"], 0);
+
+        // 1時間は引けない
+        let resp = server
+            .send(TestRequest::post("/gacha/hourly").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.json()["error"]["code"], "gacha_cooldown_not_elapsed");
+
+        let resp = server
+            .send(TestRequest::post("/gacha/premium").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(resp.json()["error"]["code"], "not_enough_point");
+
+        let resp = server
+            .send(TestRequest::post("/gacha/premium/multi").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::BAD_REQUEST);
+        assert_eq!(resp.json()["error"]["code"], "multi_draw_not_supported");
+
+        let resp = server
+            .send(TestRequest::post("/gacha/unknown_type").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::NOT_FOUND);
+        assert_eq!(resp.json()["error"]["code"], "unknown_gacha_type");
+
+        // デイリーガチャは従来のエンドポイントでも引ける
+        let resp = server
+            .send(TestRequest::post("/gacha/daily").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        let resp = server
+            .send(TestRequest::post("/gacha/daily").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::TOO_MANY_REQUESTS);
+
+        // 静的なルートが優先される
+        let resp = server
+            .send(TestRequest::get("/gacha/daily/record").bearer(&token))
+            .await;
+        assert_eq!(resp.status, http::StatusCode::OK);
+        assert!(resp.json().get("next_gacha_time").is_some());
+        assert_eq!(resp.json()["streak"]["current"], 1);
+    }
 }
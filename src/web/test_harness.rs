@@ -0,0 +1,190 @@
+// web::handlersに対してソケットを介さずにリクエストを送るためのテスト用ハーネス
+use crate::infra::JWTHandler;
+use crate::initializer::{self, Infras};
+use crate::server;
+use crate::web::{handlers, RateLimitConfig, WebContext};
+use std::sync::Arc;
+
+const TEST_KEY_ID: &str = "test";
+
+// testdata/jwt/private_key.derに対応する公開鍵
+pub fn test_jwks() -> biscuit::jwk::JWKSet<biscuit::Empty> {
+    serde_json::from_str(include_str!("../../testdata/jwt/jwks.json")).unwrap()
+}
+
+// テスト用の鍵でRS256のトークンを発行する
+pub struct TestSigner {
+    secret: biscuit::jws::Secret,
+}
+
+impl TestSigner {
+    pub fn new() -> Self {
+        TestSigner {
+            secret: biscuit::jws::Secret::rsa_keypair_from_file(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/testdata/jwt/private_key.der"
+            ))
+            .unwrap(),
+        }
+    }
+
+    pub fn sign(&self, subject: &str, roles: Vec<&str>) -> String {
+        self.sign_claims(
+            biscuit::RegisteredClaims {
+                subject: Some(std::str::FromStr::from_str(subject).unwrap()),
+                expiry: Some(From::from(chrono::Utc::now() + chrono::Duration::hours(1))),
+                ..Default::default()
+            },
+            roles,
+        )
+    }
+
+    // iss, audなどを任意に指定したいときに使う
+    pub fn sign_claims(&self, registered: biscuit::RegisteredClaims, roles: Vec<&str>) -> String {
+        let claims = biscuit::ClaimsSet::<serde_json::Value> {
+            registered,
+            private: serde_json::json!({
+                "https://jitome.ramda.io/roles": roles,
+            }),
+        };
+
+        biscuit::JWT::new_decoded(
+            From::from(biscuit::jws::RegisteredHeader {
+                algorithm: biscuit::jwa::SignatureAlgorithm::RS256,
+                key_id: Some(TEST_KEY_ID.to_string()),
+                ..Default::default()
+            }),
+            claims,
+        )
+        .into_encoded(&self.secret)
+        .unwrap()
+        .unwrap_encoded()
+        .to_string()
+    }
+}
+
+pub struct TestRequest {
+    method: http::Method,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+}
+
+impl TestRequest {
+    pub fn new(method: http::Method, path: &str) -> Self {
+        TestRequest {
+            method,
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        TestRequest::new(http::Method::GET, path)
+    }
+
+    pub fn post(path: &str) -> Self {
+        TestRequest::new(http::Method::POST, path)
+    }
+
+    pub fn put(path: &str) -> Self {
+        TestRequest::new(http::Method::PUT, path)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+
+        self
+    }
+
+    pub fn bearer(self, token: &str) -> Self {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+
+    pub fn json(mut self, body: serde_json::Value) -> Self {
+        self.body = Some(body.to_string());
+
+        self.header("Content-Type", "application/json")
+    }
+
+    fn into_request(self) -> server::Request {
+        let mut builder = hyper::Request::builder().method(self.method).uri(self.path);
+        for (name, value) in self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        builder
+            .body(self.body.map(hyper::Body::from).unwrap_or_default())
+            .unwrap()
+    }
+}
+
+pub struct TestResponse {
+    pub status: http::StatusCode,
+    pub headers: http::HeaderMap,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body)
+            .unwrap_or_else(|err| panic!("invalid json: {:?}, {}", err, self.body))
+    }
+}
+
+pub struct TestServer {
+    app: server::App<WebContext>,
+    pub signer: TestSigner,
+}
+
+impl TestServer {
+    // 全てのリポジトリをメモリ上に持つ
+    pub fn new() -> Self {
+        TestServer::with_infras(|_| ())
+    }
+
+    // 一部のリポジトリを差し替えたいときに使う
+    pub fn with_infras(f: impl FnOnce(&mut Infras)) -> Self {
+        TestServer::build(f, Default::default())
+    }
+
+    pub fn with_rate_limit(rate_limit: RateLimitConfig) -> Self {
+        TestServer::build(|_| (), rate_limit)
+    }
+
+    fn build(f: impl FnOnce(&mut Infras), rate_limit: RateLimitConfig) -> Self {
+        let mut infras = Infras::in_memory(Arc::new(JWTHandler::new(Arc::new(test_jwks()))));
+        f(&mut infras);
+
+        TestServer {
+            app: handlers(
+                initializer::from_infras(infras),
+                Default::default(),
+                rate_limit,
+            ),
+            signer: TestSigner::new(),
+        }
+    }
+
+    pub fn token(&self, subject: &str) -> String {
+        self.signer.sign(subject, Vec::new())
+    }
+
+    pub fn admin_token(&self, subject: &str) -> String {
+        self.signer.sign(subject, vec!["admin"])
+    }
+
+    pub async fn send(&self, req: TestRequest) -> TestResponse {
+        let resp = self.app.handle(req.into_request()).await;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+        TestResponse {
+            status,
+            headers,
+            body: String::from_utf8(body.to_vec()).unwrap(),
+        }
+    }
+}
@@ -1,6 +1,10 @@
 pub mod base64;
+pub mod daily_reset;
 pub mod error;
+pub mod log_context;
+pub mod metrics;
 pub mod rand_gen;
 pub mod server;
+pub mod shutdown;
 pub mod unixtime;
 pub mod url;
@@ -1,7 +1,8 @@
-use crate::wrapper::error::ServiceError;
+use crate::wrapper::error::{ErrorCode, ServiceError};
+use schemars::JsonSchema;
 use serde::*;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Base64(pub String);
 
 impl Base64 {
@@ -11,7 +12,10 @@ impl Base64 {
 
     pub fn decode(self) -> Result<Vec<u8>, ServiceError> {
         base64::decode(self.0).map_err(|err| {
-            ServiceError::bad_request(failure::Error::from_boxed_compat(Box::new(err)))
+            ServiceError::bad_request(
+                ErrorCode::InvalidBase64,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
         })
     }
 }
@@ -0,0 +1,148 @@
+use crate::wrapper::unixtime::UnixTime;
+use chrono::offset::{LocalResult, TimeZone};
+use chrono::{Duration, NaiveDate};
+use chrono_tz::Tz;
+
+// 1日の区切り, デイリーガチャ・じゃんけんのタイムアウト・ランキングの集計で共通して使う
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DailyReset {
+    timezone: Tz,
+    // 日付が変わる時刻(0-23時)
+    hour: u32,
+}
+
+impl Default for DailyReset {
+    // JSTの0時
+    fn default() -> Self {
+        DailyReset {
+            timezone: chrono_tz::Asia::Tokyo,
+            hour: 0,
+        }
+    }
+}
+
+impl DailyReset {
+    pub fn new(timezone: &str, hour: u32) -> Result<Self, String> {
+        let timezone = timezone.parse::<Tz>()?;
+        if hour >= 24 {
+            return Err(format!("hour must be less than 24: {}", hour));
+        }
+
+        Ok(DailyReset { timezone, hour })
+    }
+
+    // timeが属する日付, 区切りの時刻より前は前日として扱う
+    pub fn date_of(&self, time: &UnixTime) -> NaiveDate {
+        let local = self
+            .timezone
+            .timestamp_opt(time.0, 0)
+            .unwrap()
+            .naive_local();
+
+        (local - Duration::hours(self.hour as i64)).date()
+    }
+
+    // dateが始まる時刻
+    pub fn start_of(&self, date: NaiveDate) -> UnixTime {
+        let mut local = date.and_hms_opt(self.hour, 0, 0).unwrap();
+        loop {
+            match self.timezone.from_local_datetime(&local) {
+                LocalResult::Single(t) => return UnixTime(t.timestamp()),
+                // 夏時間の終わりで2回ある時刻は早い方
+                LocalResult::Ambiguous(t, _) => return UnixTime(t.timestamp()),
+                // 夏時間の始まりで存在しない時刻は, 存在する時刻まで進める
+                LocalResult::None => local += Duration::minutes(15),
+            }
+        }
+    }
+
+    // timeの後で最初に日付が変わる時刻
+    pub fn next_reset(&self, time: &UnixTime) -> UnixTime {
+        self.start_of(self.date_of(time).succ_opt().unwrap())
+    }
+
+    pub fn is_same_day(&self, x: &UnixTime, y: &UnixTime) -> bool {
+        self.date_of(x) == self.date_of(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn unix(tz: Tz, y: i32, m: u32, d: u32, h: u32, min: u32) -> UnixTime {
+        UnixTime(
+            tz.from_local_datetime(&date(y, m, d).and_hms_opt(h, min, 0).unwrap())
+                .earliest()
+                .unwrap()
+                .timestamp(),
+        )
+    }
+
+    #[test]
+    fn date_should_change_at_reset_hour() {
+        let tokyo = chrono_tz::Asia::Tokyo;
+        let reset = DailyReset::new("Asia/Tokyo", 5).unwrap();
+
+        // 5時より前は前日
+        assert_eq!(
+            reset.date_of(&unix(tokyo, 2020, 9, 14, 4, 59)),
+            date(2020, 9, 13)
+        );
+        assert_eq!(
+            reset.date_of(&unix(tokyo, 2020, 9, 14, 5, 0)),
+            date(2020, 9, 14)
+        );
+        assert_eq!(
+            reset.next_reset(&unix(tokyo, 2020, 9, 14, 4, 59)),
+            unix(tokyo, 2020, 9, 14, 5, 0)
+        );
+        assert_eq!(
+            reset.next_reset(&unix(tokyo, 2020, 9, 14, 5, 0)),
+            unix(tokyo, 2020, 9, 15, 5, 0)
+        );
+
+        // 既定はJSTの0時
+        let reset = DailyReset::default();
+        assert_eq!(
+            reset.next_reset(&unix(tokyo, 2020, 12, 31, 23, 0)),
+            unix(tokyo, 2021, 1, 1, 0, 0)
+        );
+
+        assert!(DailyReset::new("Asia/Tokio", 0).is_err());
+        assert!(DailyReset::new("Asia/Tokyo", 24).is_err());
+    }
+
+    #[test]
+    fn reset_should_follow_daylight_saving_time() {
+        let new_york = chrono_tz::America::New_York;
+        let reset = DailyReset::new("America/New_York", 2).unwrap();
+
+        // 2021-03-14は2時が存在しないので3時(EDT)に日付が変わる
+        let before = unix(new_york, 2021, 3, 14, 1, 59);
+        let spring = reset.next_reset(&before);
+        assert_eq!(spring, unix(new_york, 2021, 3, 14, 3, 0));
+        assert_eq!(spring.0 - before.0, 60);
+        assert_eq!(reset.date_of(&spring), date(2021, 3, 14));
+        // その日は23時間しかない
+        assert_eq!(reset.next_reset(&spring).0 - spring.0, 23 * 60 * 60);
+
+        // 2021-11-07は1時台が2回あるので, 1回目の1時(EDT)を区切りにすると25時間になる
+        let reset = DailyReset::new("America/New_York", 1).unwrap();
+        let fall = reset.start_of(date(2021, 11, 7));
+        assert_eq!(fall, unix(new_york, 2021, 11, 7, 1, 0));
+        assert_eq!(reset.start_of(date(2021, 11, 8)).0 - fall.0, 25 * 60 * 60);
+        // 2回目の1時台も同じ日
+        assert!(reset.is_same_day(&fall, &UnixTime(fall.0 + 90 * 60)));
+        assert!(!reset.is_same_day(&fall, &UnixTime(fall.0 - 1)));
+
+        // 夏時間がないタイムゾーンはいつも24時間
+        let reset = DailyReset::new("Asia/Tokyo", 5).unwrap();
+        let start = reset.start_of(date(2021, 3, 14));
+        assert_eq!(reset.next_reset(&start).0 - start.0, 24 * 60 * 60);
+    }
+}
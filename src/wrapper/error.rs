@@ -1,51 +1,173 @@
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::error::Error;
 
+// クライアントが文字列比較せずに済むようにエラーの種類を表すコード
+// 値はAPIの互換性のために変えないこと
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // リクエストの形式
+    InvalidRequestBody,
+    InvalidBase64,
+    MissingPathParameter,
+    InvalidPathParameter,
+    InvalidQueryParameter,
+    RequestBodyTooLarge,
+    RateLimitExceeded,
+    // 認証
+    MissingAuthorizationHeader,
+    InvalidAuthorizationHeader,
+    InvalidToken,
+    UnknownSigningKey,
+    TokenExpired,
+    TokenNotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+    MissingRequiredClaim,
+    MissingSubject,
+    AdminRequired,
+    OriginNotAllowed,
+    // ユーザー
+    InvalidScreenName,
+    // みょんポイント
+    NotEnoughPoint,
+    ConditionNotMet,
+    // ガチャ
+    DailyGachaRateLimitExceeded,
+    UnknownGachaType,
+    GachaCooldownNotElapsed,
+    MultiDrawNotSupported,
+    // じゃんけん
+    JankenRateLimitExceeded,
+    UnsupportedJankenHand,
+    UnsupportedJankenStatus,
+    // ギフト
+    GiftCannotBeOpened,
+    // 汎用
+    NotFound,
+    DuplicateEntry,
+    DatabaseError,
+    DatabaseUnavailable,
+    ExternalServiceError,
+    ExternalServiceUnavailable,
+    DependencyUnavailable,
+    InvalidConfiguration,
+    InternalError,
+}
+
+// レスポンスと同じsnake_caseの文字列, メトリクスのラベルなどに使う
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(code)) => write!(f, "{}", code),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+// エラー時のレスポンスボディ, OpenAPIのスキーマもここから作る
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
 #[derive(Debug)]
 pub struct ServiceError {
     pub error: failure::Error,
     pub status_code: http::StatusCode,
+    pub code: ErrorCode,
+    pub details: Option<serde_json::Value>,
 }
 
 impl ServiceError {
+    fn new(status_code: http::StatusCode, code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError {
+            error: err,
+            status_code,
+            code,
+            details: None,
+        }
+    }
+
+    // エラーの原因をクライアントに伝えるための追加情報
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+
+        self
+    }
+
+    // {"error": {"code": ..., "message": ..., "details": ...}}
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(ErrorResponse {
+            error: ErrorBody {
+                code: self.code.clone(),
+                message: self.error.to_string(),
+                details: self.details.clone(),
+            },
+        })
+        .unwrap()
+    }
+
     pub fn into_http_response(self) -> http::Response<hyper::Body> {
         http::Response::builder()
             .status(self.status_code)
-            .body(hyper::Body::from(self.error.to_string()))
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(hyper::Body::from(self.to_json().to_string()))
             .unwrap()
     }
 
-    pub fn bad_request(err: failure::Error) -> Self {
-        ServiceError {
-            error: err,
-            status_code: http::StatusCode::BAD_REQUEST,
-        }
+    pub fn bad_request(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::BAD_REQUEST, code, err)
     }
 
-    pub fn unauthorized(err: failure::Error) -> Self {
-        ServiceError {
-            error: err,
-            status_code: http::StatusCode::UNAUTHORIZED,
-        }
+    pub fn unauthorized(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::UNAUTHORIZED, code, err)
     }
 
-    pub fn not_found(err: failure::Error) -> Self {
-        ServiceError {
-            error: err,
-            status_code: http::StatusCode::NOT_FOUND,
-        }
+    // 認証はできているが権限がない
+    pub fn forbidden(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::FORBIDDEN, code, err)
     }
 
-    pub fn internal_server_error(err: failure::Error) -> Self {
-        ServiceError {
-            error: err,
-            status_code: http::StatusCode::INTERNAL_SERVER_ERROR,
-        }
+    pub fn not_found(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::NOT_FOUND, code, err)
+    }
+
+    // 他のリクエストと競合した, 状態を取得し直せば解決する
+    pub fn conflict(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::CONFLICT, code, err)
+    }
+
+    pub fn payload_too_large(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::PAYLOAD_TOO_LARGE, code, err)
+    }
+
+    // 時間をおけば再び受け付ける
+    pub fn too_many_requests(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::TOO_MANY_REQUESTS, code, err)
+    }
+
+    pub fn internal_server_error(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::INTERNAL_SERVER_ERROR, code, err)
+    }
+
+    // 依存しているサービスに繋がらない, そのままリトライしてよい
+    pub fn service_unavailable(code: ErrorCode, err: failure::Error) -> Self {
+        ServiceError::new(http::StatusCode::SERVICE_UNAVAILABLE, code, err)
     }
 }
 
 impl From<failure::Error> for ServiceError {
     fn from(err: failure::Error) -> Self {
-        ServiceError::unauthorized(err)
+        ServiceError::internal_server_error(ErrorCode::InternalError, err)
     }
 }
 
@@ -54,26 +176,83 @@ impl From<debil_mysql::Error> for ServiceError {
         use debil_mysql::Error::*;
 
         match err {
-            NotFoundError => ServiceError::not_found(failure::err_msg("record not found")),
-            MySQLError(err) => ServiceError::internal_server_error(From::from(err)),
+            NotFoundError => {
+                ServiceError::not_found(ErrorCode::NotFound, failure::err_msg("record not found"))
+            }
+            MySQLError(mysql_async::error::Error::Io(err)) => {
+                ServiceError::service_unavailable(ErrorCode::DatabaseUnavailable, From::from(err))
+            }
+            // ER_DUP_ENTRY
+            MySQLError(mysql_async::error::Error::Server(err)) if err.code == 1062 => {
+                ServiceError::conflict(ErrorCode::DuplicateEntry, From::from(err))
+            }
+            MySQLError(err) => {
+                ServiceError::internal_server_error(ErrorCode::DatabaseError, From::from(err))
+            }
         }
     }
 }
 
 impl From<tokio::task::JoinError> for ServiceError {
     fn from(err: tokio::task::JoinError) -> Self {
-        ServiceError::internal_server_error(From::from(err))
+        ServiceError::internal_server_error(ErrorCode::InternalError, From::from(err))
     }
 }
 
 impl<E: Sync + Send + Error + 'static> From<rusoto_core::RusotoError<E>> for ServiceError {
     fn from(err: rusoto_core::RusotoError<E>) -> Self {
-        ServiceError::internal_server_error(From::from(err))
+        use rusoto_core::RusotoError::*;
+
+        match err {
+            HttpDispatch(_) => ServiceError::service_unavailable(
+                ErrorCode::ExternalServiceUnavailable,
+                From::from(err),
+            ),
+            Unknown(ref resp) if resp.status.is_server_error() => {
+                ServiceError::service_unavailable(
+                    ErrorCode::ExternalServiceUnavailable,
+                    From::from(err),
+                )
+            }
+            _ => ServiceError::internal_server_error(
+                ErrorCode::ExternalServiceError,
+                From::from(err),
+            ),
+        }
     }
 }
 
 impl From<serde_json::Error> for ServiceError {
     fn from(err: serde_json::Error) -> Self {
-        ServiceError::bad_request(From::from(err))
+        ServiceError::bad_request(ErrorCode::InvalidRequestBody, From::from(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mysql_errors_should_be_classified() {
+        let err: ServiceError = debil_mysql::Error::MySQLError(mysql_async::error::Error::Io(
+            std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused"),
+        ))
+        .into();
+        assert_eq!(err.status_code, http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code, ErrorCode::DatabaseUnavailable);
+
+        let err: ServiceError = debil_mysql::Error::MySQLError(mysql_async::error::Error::Server(
+            mysql_async::error::ServerError {
+                code: 1062,
+                message: "Duplicate entry".to_string(),
+                state: "23000".to_string(),
+            },
+        ))
+        .into();
+        assert_eq!(err.status_code, http::StatusCode::CONFLICT);
+        assert_eq!(err.code, ErrorCode::DuplicateEntry);
+
+        let err: ServiceError = debil_mysql::Error::NotFoundError.into();
+        assert_eq!(err.status_code, http::StatusCode::NOT_FOUND);
     }
 }
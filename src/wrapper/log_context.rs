@@ -0,0 +1,114 @@
+// リクエストごとのログの文脈, ハンドラーの中から出したログにもリクエストIDを付ける
+use futures::prelude::*;
+use std::cell::RefCell;
+use std::io::Write;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// アクセスログのtarget, メッセージをそのまま1行のJSONとして出す
+pub const ACCESS_LOG_TARGET: &str = "access_log";
+
+struct LogContext {
+    request_id: String,
+    subject: Option<String>,
+}
+
+tokio::task_local! {
+    static CONTEXT: RefCell<LogContext>;
+}
+
+pub async fn scope<F: Future>(request_id: String, f: F) -> F::Output {
+    CONTEXT
+        .scope(
+            RefCell::new(LogContext {
+                request_id,
+                subject: None,
+            }),
+            f,
+        )
+        .await
+}
+
+// scopeの外ではNone
+pub fn request_id() -> Option<String> {
+    CONTEXT.try_with(|ctx| ctx.borrow().request_id.clone()).ok()
+}
+
+pub fn subject() -> Option<String> {
+    CONTEXT
+        .try_with(|ctx| ctx.borrow().subject.clone())
+        .ok()
+        .flatten()
+}
+
+pub fn set_subject(subject: &str) {
+    let _ = CONTEXT.try_with(|ctx| ctx.borrow_mut().subject = Some(subject.to_string()));
+}
+
+// クライアントから渡されたIDはログを壊さないものだけ使う
+pub fn accept_request_id(value: Option<&str>) -> String {
+    match value {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) =>
+        {
+            id.to_string()
+        }
+        _ => uuid::Uuid::new_v4().to_string(),
+    }
+}
+
+// env_loggerのフォーマッター, 1行に1つのJSONを出す
+pub fn format(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    if record.target() == ACCESS_LOG_TARGET {
+        return writeln!(buf, "{}", record.args());
+    }
+
+    writeln!(
+        buf,
+        "{}",
+        serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+            "request_id": request_id(),
+        })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn context_should_be_visible_only_inside_scope() {
+        assert_eq!(request_id(), None);
+
+        scope("req-1".to_string(), async {
+            assert_eq!(request_id(), Some("req-1".to_string()));
+            assert_eq!(subject(), None);
+
+            set_subject("auth0|me");
+            tokio::time::delay_for(std::time::Duration::from_millis(1)).await;
+            assert_eq!(subject(), Some("auth0|me".to_string()));
+        })
+        .await;
+
+        assert_eq!(request_id(), None);
+    }
+
+    #[test]
+    fn invalid_request_id_should_be_replaced() {
+        assert_eq!(accept_request_id(Some("abc-123_x.y:z")), "abc-123_x.y:z");
+
+        for id in &[None, Some(""), Some("a b"), Some("\"}{")] {
+            let generated = accept_request_id(*id);
+            assert!(uuid::Uuid::parse_str(&generated).is_ok(), "{:?}", id);
+        }
+        assert_ne!(accept_request_id(Some(&"a".repeat(129))), "a".repeat(129));
+    }
+}
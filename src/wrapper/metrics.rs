@@ -0,0 +1,185 @@
+// Prometheusのメトリクス, /metricsで公開する
+use crate::error::ServiceError;
+use futures::prelude::*;
+use prometheus::{Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder};
+use std::time::{Duration, Instant};
+
+lazy_static::lazy_static! {
+    static ref HTTP_REQUESTS_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION_SECONDS: HistogramVec = prometheus::register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency",
+        &["method", "route"]
+    )
+    .unwrap();
+    static ref REPOSITORY_CALL_DURATION_SECONDS: HistogramVec = prometheus::register_histogram_vec!(
+        "repository_call_duration_seconds",
+        "Repository call latency",
+        &["repository", "method"]
+    )
+    .unwrap();
+    static ref REPOSITORY_CALL_ERRORS_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "repository_call_errors_total",
+        "Number of failed repository calls",
+        &["repository", "method", "code"]
+    )
+    .unwrap();
+    static ref DAILY_GACHA_DRAWN_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "daily_gacha_drawn_total",
+        "Number of daily gachas drawn"
+    )
+    .unwrap();
+    static ref POINTS_MINTED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "points_minted_total",
+        "Myon points given to users",
+        &["reason"]
+    )
+    .unwrap();
+    static ref POINTS_BURNED_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "points_burned_total",
+        "Myon points taken from users",
+        &["reason"]
+    )
+    .unwrap();
+    static ref JANKEN_MATCHES_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "janken_matches_total",
+        "Number of janken matches resolved or timed out by the worker",
+        &["result"]
+    )
+    .unwrap();
+    static ref JANKEN_PROCESS_LAST_RUN_TIMESTAMP_SECONDS: IntGauge = prometheus::register_int_gauge!(
+        "janken_process_last_run_timestamp_seconds",
+        "Unix time when the janken worker last finished a run"
+    )
+    .unwrap();
+    static ref GIFTS_OPENED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "gifts_opened_total",
+        "Number of gifts opened"
+    )
+    .unwrap();
+}
+
+// どのルートにもマッチしなかったリクエストのroute
+// パスをそのままラベルにするとラベルの種類が際限なく増える
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+pub fn observe_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+// リポジトリの呼び出しにかかった時間と失敗した回数を記録する
+pub async fn observe_repository_call<T>(
+    repository: &str,
+    method: &str,
+    call: impl Future<Output = Result<T, ServiceError>>,
+) -> Result<T, ServiceError> {
+    let started_at = Instant::now();
+    let result = call.await;
+
+    REPOSITORY_CALL_DURATION_SECONDS
+        .with_label_values(&[repository, method])
+        .observe(started_at.elapsed().as_secs_f64());
+    if let Err(err) = &result {
+        REPOSITORY_CALL_ERRORS_TOTAL
+            .with_label_values(&[repository, method, &err.code.to_string()])
+            .inc();
+    }
+
+    result
+}
+
+pub fn inc_daily_gacha_drawn() {
+    DAILY_GACHA_DRAWN_TOTAL.inc();
+}
+
+// 正なら付与, 負なら消費として数える
+pub fn observe_point_delta(reason: &str, delta: i64) {
+    if delta >= 0 {
+        POINTS_MINTED_TOTAL
+            .with_label_values(&[reason])
+            .inc_by(delta);
+    } else {
+        POINTS_BURNED_TOTAL
+            .with_label_values(&[reason])
+            .inc_by(-delta);
+    }
+}
+
+// resultは"resolved"か"timed_out"
+pub fn inc_janken_matches(result: &str) {
+    JANKEN_MATCHES_TOTAL.with_label_values(&[result]).inc();
+}
+
+pub fn set_janken_process_last_run(unixtime: i64) {
+    JANKEN_PROCESS_LAST_RUN_TIMESTAMP_SECONDS.set(unixtime);
+}
+
+pub fn inc_gifts_opened() {
+    GIFTS_OPENED_TOTAL.inc();
+}
+
+pub fn content_type() -> String {
+    TextEncoder::new().format_type().to_string()
+}
+
+pub fn render() -> Vec<u8> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn rendered() -> String {
+        String::from_utf8(render()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn repository_call_should_be_observed() {
+        let result = observe_repository_call("test_repository", "find", async { Ok(1) }).await;
+        assert_eq!(result.unwrap(), 1);
+
+        let result: Result<(), _> = observe_repository_call("test_repository", "find", async {
+            Err(ServiceError::not_found(
+                ErrorCode::NotFound,
+                failure::err_msg("record not found"),
+            ))
+        })
+        .await;
+        assert!(result.is_err());
+
+        let text = rendered();
+        assert!(text.contains(
+            r#"repository_call_duration_seconds_count{method="find",repository="test_repository"} 2"#
+        ));
+        assert!(text.contains(
+            r#"repository_call_errors_total{code="not_found",method="find",repository="test_repository"} 1"#
+        ));
+    }
+
+    #[test]
+    fn point_delta_should_be_split_into_minted_and_burned() {
+        observe_point_delta("test_reason", 10);
+        observe_point_delta("test_reason", -3);
+
+        let text = rendered();
+        assert!(text.contains(r#"points_minted_total{reason="test_reason"} 10"#));
+        assert!(text.contains(r#"points_burned_total{reason="test_reason"} 3"#));
+    }
+}
@@ -1,10 +1,33 @@
 use crate::error::ServiceError;
+use crate::log_context;
+use crate::shutdown::Shutdown;
+use async_trait::async_trait;
 use futures::prelude::*;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Server};
 use path_tree::PathTree;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod cors;
+pub use cors::*;
+
+mod extract;
+pub use extract::*;
+
+mod metrics;
+pub use metrics::*;
+
+mod access_log;
+pub use access_log::*;
+
+mod rate_limit;
+pub use rate_limit::*;
+
+mod openapi;
+pub use openapi::*;
 
 pub type Request = hyper::Request<hyper::Body>;
 pub type Response = hyper::Response<hyper::Body>;
@@ -24,18 +47,21 @@ pub fn response_from<D: serde::Serialize>(result: Result<D, ServiceError>) -> Re
         Err(err) => {
             error!("{:?}", err);
 
-            (err.status_code, hyper::Body::from(err.error.to_string()))
+            (
+                err.status_code,
+                hyper::Body::from(err.to_json().to_string()),
+            )
         }
     };
 
     hyper::Response::builder()
         .status(s)
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
         .header(hyper::header::CONTENT_TYPE, "application/json")
         .body(b)
         .unwrap()
 }
 
+#[derive(Clone)]
 pub struct Params(Vec<(String, String)>);
 
 impl Params {
@@ -53,28 +79,124 @@ impl Params {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+type FutureResult<O> = Pin<Box<dyn Future<Output = O> + Send>>;
+type Handler<D> = Arc<dyn Fn(Request, Params, Arc<D>) -> FutureResult<Response> + Sync + Send>;
 
-    #[test]
-    fn it_should_find_params() {
-        let params = Params(vec![
-            ("a".to_string(), "b".to_string()),
-            ("x".to_string(), "y".to_string()),
-        ]);
+// リクエストのパスに対して登録されているメソッド, ミドルウェアから参照できるようにリクエストに持たせる
+pub struct AllowedMethods(pub Vec<Method>);
+
+// 接続元のアドレス, HttpServerがリクエストに持たせる
+#[derive(Clone, Copy)]
+pub struct RemoteAddr(pub std::net::SocketAddr);
+
+// マッチしたルートの登録時のパス("/gift/:gift_id/open"など)
+#[derive(Clone)]
+pub struct MatchedRoute(pub String);
+
+// ハンドラーが呼ばれた後でも参照できるリクエストの情報
+pub struct RequestHead {
+    pub method: Method,
+    pub uri: http::Uri,
+    pub headers: http::HeaderMap,
+    // どのルートにもマッチしなかったときはNone
+    pub route: Option<String>,
+    pub started_at: Instant,
+}
 
-        assert_eq!(params.find("a"), Some("b".to_string()));
-        assert_eq!(params.find("x"), Some("y".to_string()));
-        assert_eq!(params.find("ttt"), None);
+// ハンドラーの前後に処理を差し込む
+// beforeは登録順に, afterはその逆順に呼ばれる
+#[async_trait]
+pub trait Middleware<D: Sync + Send + 'static>: Sync + Send {
+    // レスポンスを返すとハンドラーや後続のbeforeは呼ばれない
+    async fn before(
+        &self,
+        _req: &mut Request,
+        _params: &Params,
+        _data: Arc<D>,
+    ) -> Option<Response> {
+        None
+    }
+
+    // beforeが呼ばれたものについてだけ呼ばれる
+    async fn after(
+        &self,
+        _head: &RequestHead,
+        _params: &Params,
+        _data: Arc<D>,
+        resp: Response,
+    ) -> Response {
+        resp
     }
 }
 
-type FutureResult<O> = Pin<Box<dyn Future<Output = O> + Send>>;
-type Handler<D> = Arc<dyn Fn(Request, Params, Arc<D>) -> FutureResult<Response> + Sync + Send>;
+async fn run_middlewares<D: Sync + Send + 'static>(
+    middlewares: &[Arc<dyn Middleware<D>>],
+    mut req: Request,
+    params: Params,
+    data: Arc<D>,
+    handler: Handler<D>,
+) -> Response {
+    let started_at = Instant::now();
+
+    let mut called = 0;
+    let mut early_response = None;
+    for middleware in middlewares {
+        called += 1;
+        early_response = middleware.before(&mut req, &params, data.clone()).await;
+        if early_response.is_some() {
+            break;
+        }
+    }
+
+    let head = RequestHead {
+        method: req.method().clone(),
+        uri: req.uri().clone(),
+        headers: req.headers().clone(),
+        route: req
+            .extensions()
+            .get::<MatchedRoute>()
+            .map(|route| route.0.clone()),
+        started_at,
+    };
+    let mut resp = match early_response {
+        Some(resp) => resp,
+        None => handler(req, params.clone(), data.clone()).await,
+    };
+
+    for middleware in middlewares[..called].iter().rev() {
+        resp = middleware.after(&head, &params, data.clone(), resp).await;
+    }
+
+    resp
+}
+
+// App::routeに渡せるハンドラー
+// typedで作ったものは入出力の型をドキュメントに載せ, それ以外の関数は何も載せない
+pub trait IntoRoute<D> {
+    fn into_route(self) -> (Handler<D>, RouteSchema);
+}
+
+impl<D, F, T> IntoRoute<D> for F
+where
+    F: Fn(Request, Params, Arc<D>) -> T + Sync + Send + 'static,
+    T: Future<Output = Response> + Send + 'static,
+{
+    fn into_route(self) -> (Handler<D>, RouteSchema) {
+        (
+            Arc::new(move |r, p, d| Box::pin(self(r, p, d))),
+            RouteSchema::default(),
+        )
+    }
+}
 
 pub struct App<D> {
-    paths: PathTree<Handler<D>>,
+    // ハンドラーと登録時のパス
+    paths: PathTree<(Handler<D>, String)>,
+    // 登録されているメソッドの一覧, パスごとに使えるメソッドを調べるのに使う
+    methods: Vec<Method>,
+    // 登録順のルートとその説明, OpenAPIのドキュメントを作るのに使う
+    docs: Vec<RouteDoc>,
+    middlewares: Vec<Arc<dyn Middleware<D>>>,
     data: Arc<D>,
 }
 
@@ -82,6 +204,9 @@ impl<D> Clone for App<D> {
     fn clone(&self) -> Self {
         App {
             paths: self.paths.clone(),
+            methods: self.methods.clone(),
+            docs: self.docs.clone(),
+            middlewares: self.middlewares.clone(),
             data: self.data.clone(),
         }
     }
@@ -91,19 +216,10 @@ fn internal_path(method: &Method, path: &str) -> String {
     format!("/{}/{}", method, path)
 }
 
-async fn cors_handler<D>(_req: Request, _params: Params, _data: Arc<D>) -> Response {
+async fn not_found_handler<D>(_req: Request, _params: Params, _data: Arc<D>) -> Response {
     hyper::Response::builder()
-        .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        // Safari対策に全部明記する
-        .header(
-            hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
-            "*, Authorization, Content-Type, Origin, Referer, Accept, User-Agent",
-        )
-        .header(
-            hyper::header::ACCESS_CONTROL_ALLOW_METHODS,
-            "POST,PUT,DELETE",
-        )
-        .body(Body::default())
+        .status(404)
+        .body(Body::from(""))
         .unwrap()
 }
 
@@ -111,94 +227,494 @@ impl<D: Sync + Send + 'static> App<D> {
     pub fn new(data: D) -> App<D> {
         App {
             paths: PathTree::new(),
+            methods: Vec::new(),
+            docs: Vec::new(),
+            middlewares: Vec::new(),
             data: Arc::new(data),
         }
     }
 
-    pub fn route<F, T>(mut self, path: &str, method: Method, f: F) -> Self
-    where
-        F: Fn(Request, Params, Arc<D>) -> T + Clone + Sync + Send + 'static,
-        T: Future<Output = Response> + Send + 'static,
-    {
+    // 全てのリクエストに対して適用する
+    pub fn middleware(mut self, middleware: impl Middleware<D> + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+
+        self
+    }
+
+    pub fn route(self, path: &str, method: Method, f: impl IntoRoute<D>) -> Self {
+        self.route_with(path, method, Vec::new(), f)
+    }
+
+    // このルートにだけミドルウェアを適用する, 全体のミドルウェアの内側で呼ばれる
+    pub fn route_with(
+        mut self,
+        path: &str,
+        method: Method,
+        middlewares: Vec<Arc<dyn Middleware<D>>>,
+        f: impl IntoRoute<D>,
+    ) -> Self {
         let ipath = internal_path(&method, path);
         if self.paths.find(&ipath).is_some() {
             error!("The path {:?} does already exist.", ipath);
         }
 
-        self.paths
-            .insert(&ipath, Arc::new(move |r, p, d| Box::pin(f(r, p, d))));
+        let (handler, schema) = f.into_route();
+        let handler: Handler<D> = if middlewares.is_empty() {
+            handler
+        } else {
+            let middlewares = Arc::new(middlewares);
+            Arc::new(move |r, p, d| {
+                let middlewares = middlewares.clone();
+                let handler = handler.clone();
+
+                Box::pin(async move { run_middlewares(&middlewares, r, p, d, handler).await })
+            })
+        };
+        self.paths.insert(&ipath, (handler, path.to_string()));
+        self.docs.push(RouteDoc::new(method.clone(), path, schema));
+        if !self.methods.contains(&method) {
+            self.methods.push(method);
+        }
+
+        self
+    }
 
-        // CORS (こんな適当でいいのか？)
-        self.paths.insert(
-            internal_path(&http::Method::OPTIONS, path).as_str(),
-            Arc::new(move |r, p, d| Box::pin(cors_handler(r, p, d))),
+    // このパスに対して登録されているメソッド
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        self.methods
+            .iter()
+            .filter(|method| self.paths.find(&internal_path(method, path)).is_some())
+            .cloned()
+            .collect()
+    }
+
+    // ソケットを介さずにリクエストを処理する, HttpServerとテストから使う
+    // X-Request-Idを受け取るか発行し, 処理中のログとレスポンスに付ける
+    pub async fn handle(&self, req: Request) -> Response {
+        let request_id = log_context::accept_request_id(
+            req.headers()
+                .get(log_context::REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok()),
         );
 
-        self
+        let mut resp = log_context::scope(request_id.clone(), self.dispatch(req)).await;
+        resp.headers_mut().insert(
+            log_context::REQUEST_ID_HEADER,
+            http::HeaderValue::from_str(&request_id).unwrap(),
+        );
+
+        resp
+    }
+
+    async fn dispatch(&self, mut req: Request) -> Response {
+        let p = internal_path(req.method(), req.uri().path());
+        let allowed_methods = AllowedMethods(self.allowed_methods(req.uri().path()));
+        req.extensions_mut().insert(allowed_methods);
+
+        let (handler, params): (Handler<D>, _) = match self.paths.find(p.as_str()) {
+            None => (
+                Arc::new(|r, p, d| Box::pin(not_found_handler(r, p, d))),
+                Params(Vec::new()),
+            ),
+            Some(((f, route), ps)) => {
+                req.extensions_mut().insert(MatchedRoute(route.clone()));
+
+                (
+                    f.clone(),
+                    Params(
+                        ps.iter()
+                            .map(|(x, y)| (x.to_string(), y.to_string()))
+                            .collect::<Vec<_>>(),
+                    ),
+                )
+            }
+        };
+
+        run_middlewares(&self.middlewares, req, params, self.data.clone(), handler).await
     }
 }
 
 pub struct HttpServer<D> {
-    addr: Option<std::net::SocketAddr>,
+    listener: Option<std::net::TcpListener>,
     app: Option<App<D>>,
+    shutdown: Option<(Shutdown, Duration)>,
 }
 
 impl<D: Sync + Send + 'static> HttpServer<D> {
     pub fn new() -> HttpServer<D> {
         HttpServer {
-            addr: None,
+            listener: None,
             app: None,
+            shutdown: None,
         }
     }
 
+    // その場でポートを確保するので, runより前に来た接続も受け付けられる
     pub fn bind(&mut self, addr: std::net::SocketAddr) -> &mut Self {
-        self.addr = Some(addr);
+        self.listener = Some(
+            std::net::TcpListener::bind(addr)
+                .unwrap_or_else(|err| panic!("Failed to bind {}: {:?}", addr, err)),
+        );
 
         self
     }
 
+    // ポート0でbindしたときに実際に使われているアドレス
+    pub fn local_addr(&self) -> Option<std::net::SocketAddr> {
+        self.listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
     pub fn service(&mut self, app: App<D>) -> &mut Self {
         self.app = Some(app);
 
         self
     }
 
+    // 合図があったら新しい接続を受け付けずに処理中のリクエストを待つ
+    // drain_timeoutを過ぎても終わらないリクエストは打ち切る
+    pub fn graceful_shutdown(&mut self, shutdown: Shutdown, drain_timeout: Duration) -> &mut Self {
+        self.shutdown = Some((shutdown, drain_timeout));
+
+        self
+    }
+
     pub async fn run(&mut self) -> Result<(), hyper::Error> {
-        let addr = self.addr.take().unwrap();
+        let listener = self.listener.take().unwrap();
+        let addr = listener.local_addr().unwrap();
         let app = self.app.take().unwrap();
-        let server = Server::bind(&addr).serve(make_service_fn(|_| {
+        let server = Server::from_tcp(listener)?.serve(make_service_fn(|conn: &AddrStream| {
             let app = app.clone();
+            let remote_addr = RemoteAddr(conn.remote_addr());
 
-            async {
-                Ok::<_, hyper::Error>(service_fn(move |req| {
-                    let paths = app.paths.clone();
-                    let p = internal_path(req.method(), req.uri().path());
-                    let data = app.data.clone();
-
-                    async move {
-                        match paths.find(p.as_str()) {
-                            None => hyper::Response::builder().status(404).body(Body::from("")),
-                            Some((f, ps)) => {
-                                let result = f(
-                                    req,
-                                    Params(
-                                        ps.iter()
-                                            .map(|(x, y)| (x.to_string(), y.to_string()))
-                                            .collect::<Vec<_>>(),
-                                    ),
-                                    data.clone(),
-                                )
-                                .await;
-
-                                Ok(result)
-                            }
-                        }
-                    }
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |mut req: Request| {
+                    let app = app.clone();
+                    req.extensions_mut().insert(remote_addr);
+
+                    async move { Ok::<_, hyper::Error>(app.handle(req).await) }
                 }))
             }
         }));
 
         println!("Listening on http://{}", addr);
-        server.await
+        let (mut shutdown, drain_timeout) = match self.shutdown.take() {
+            Some(shutdown) => shutdown,
+            None => return server.await,
+        };
+
+        let mut signal = shutdown.clone();
+        let server = server.with_graceful_shutdown(async move { signal.wait().await });
+        futures::pin_mut!(server);
+        if let future::Either::Left((result, _)) =
+            future::select(&mut server, shutdown.wait().boxed()).await
+        {
+            return result;
+        }
+
+        match tokio::time::timeout(drain_timeout, server).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    "Gave up waiting for in-flight requests after {:?}",
+                    drain_timeout
+                );
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request_id_handler(_req: Request, _params: Params, _data: Arc<()>) -> Response {
+        hyper::Response::new(Body::from(log_context::request_id().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn request_id_should_be_propagated() {
+        let app = App::new(()).route("/id", Method::GET, request_id_handler);
+
+        let resp = app
+            .handle(
+                hyper::Request::builder()
+                    .uri("/id")
+                    .header("X-Request-Id", "from-client")
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(resp.headers()["x-request-id"], "from-client");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "from-client");
+
+        // 渡されなかったときは発行する, 404でも付ける
+        for path in &["/id", "/unknown"] {
+            let resp = app
+                .handle(
+                    hyper::Request::builder()
+                        .uri(*path)
+                        .body(Body::default())
+                        .unwrap(),
+                )
+                .await;
+            let request_id = resp.headers()["x-request-id"].to_str().unwrap();
+            assert!(uuid::Uuid::parse_str(request_id).is_ok());
+        }
+    }
+
+    // ハンドラーに入ったことを知らせ, releaseの合図があるまでレスポンスを返さない
+    struct Gate {
+        entered: tokio::sync::mpsc::UnboundedSender<()>,
+        release: crate::shutdown::Shutdown,
+    }
+
+    async fn gated_handler(_req: Request, _ps: Params, gate: Arc<Gate>) -> Response {
+        let _ = gate.entered.send(());
+        gate.release.clone().wait().await;
+
+        hyper::Response::new(Body::from("done"))
+    }
+
+    struct TestHttpServer {
+        base: String,
+        shutdown: crate::shutdown::ShutdownTrigger,
+        release: crate::shutdown::ShutdownTrigger,
+        entered: tokio::sync::mpsc::UnboundedReceiver<()>,
+        server: tokio::task::JoinHandle<()>,
+    }
+
+    // ポートはspawnする前に確保するので, 起動を待たずにリクエストを送れる
+    fn start_server(drain_timeout: Duration) -> TestHttpServer {
+        let (shutdown_trigger, shutdown) = crate::shutdown::channel();
+        let (release_trigger, release) = crate::shutdown::channel();
+        let (entered_sender, entered) = tokio::sync::mpsc::unbounded_channel();
+
+        let mut server = HttpServer::new();
+        server
+            .bind(([127, 0, 0, 1], 0).into())
+            .service(
+                App::new(Gate {
+                    entered: entered_sender,
+                    release,
+                })
+                .route("/gated", Method::GET, gated_handler),
+            )
+            .graceful_shutdown(shutdown, drain_timeout);
+        let base = format!("http://{}", server.local_addr().unwrap());
+
+        TestHttpServer {
+            base,
+            shutdown: shutdown_trigger,
+            release: release_trigger,
+            entered,
+            server: tokio::spawn(async move { server.run().await.unwrap() }),
+        }
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_should_drain_in_flight_requests() {
+        let mut server = start_server(Duration::from_secs(5));
+
+        let in_flight = {
+            let url = format!("{}/gated", server.base);
+            tokio::spawn(async move { reqwest::get(&url).await })
+        };
+        // ハンドラーの途中で止める合図を出してから, レスポンスを返させる
+        server.entered.recv().await.unwrap();
+        server.shutdown.trigger();
+        server.release.trigger();
+
+        let resp = in_flight.await.unwrap().unwrap();
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), "done");
+
+        tokio::time::timeout(Duration::from_secs(5), server.server)
+            .await
+            .expect("server should stop after draining")
+            .unwrap();
+        assert!(reqwest::get(&format!("{}/gated", server.base))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_should_give_up_after_deadline() {
+        let mut server = start_server(Duration::from_millis(100));
+
+        let in_flight = {
+            let url = format!("{}/gated", server.base);
+            tokio::spawn(async move { reqwest::get(&url).await })
+        };
+        // レスポンスを返させないまま止める
+        server.entered.recv().await.unwrap();
+        server.shutdown.trigger();
+
+        tokio::time::timeout(Duration::from_secs(5), server.server)
+            .await
+            .expect("server should stop at the deadline")
+            .unwrap();
+        // releaseしていないので, レスポンスを待たずにサーバーが止まったことになる
+        assert!(in_flight.now_or_never().is_none());
+    }
+
+    #[test]
+    fn it_should_find_params() {
+        let params = Params(vec![
+            ("a".to_string(), "b".to_string()),
+            ("x".to_string(), "y".to_string()),
+        ]);
+
+        assert_eq!(params.find("a"), Some("b".to_string()));
+        assert_eq!(params.find("x"), Some("y".to_string()));
+        assert_eq!(params.find("ttt"), None);
+    }
+
+    struct Record {
+        name: &'static str,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+        reject: bool,
+    }
+
+    #[async_trait]
+    impl Middleware<()> for Record {
+        async fn before(
+            &self,
+            req: &mut Request,
+            params: &Params,
+            _data: Arc<()>,
+        ) -> Option<Response> {
+            self.log.lock().unwrap().push(format!(
+                "before {} {} {:?}",
+                self.name,
+                req.uri().path(),
+                params.find("id")
+            ));
+            req.headers_mut()
+                .insert("x-middleware", self.name.parse().unwrap());
+
+            if self.reject {
+                Some(
+                    hyper::Response::builder()
+                        .status(429)
+                        .body(Body::default())
+                        .unwrap(),
+                )
+            } else {
+                None
+            }
+        }
+
+        async fn after(
+            &self,
+            head: &RequestHead,
+            _params: &Params,
+            _data: Arc<()>,
+            resp: Response,
+        ) -> Response {
+            self.log.lock().unwrap().push(format!(
+                "after {} {:?} {}",
+                self.name,
+                head.headers.get("x-middleware"),
+                resp.status()
+            ));
+
+            resp
+        }
+    }
+
+    fn record(
+        name: &'static str,
+        log: &Arc<std::sync::Mutex<Vec<String>>>,
+        reject: bool,
+    ) -> Record {
+        Record {
+            name,
+            log: log.clone(),
+            reject,
+        }
+    }
+
+    async fn ok_handler(req: Request, _params: Params, _data: Arc<()>) -> Response {
+        hyper::Response::new(Body::from(format!(
+            "{:?}",
+            req.headers().get("x-middleware")
+        )))
+    }
+
+    async fn send(app: &App<()>, path: &str) -> Response {
+        app.handle(
+            hyper::Request::builder()
+                .uri(path)
+                .body(Body::default())
+                .unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn middlewares_should_wrap_handler_in_order() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = App::new(())
+            .middleware(record("global", &log, false))
+            .route_with(
+                "/items/:id",
+                Method::GET,
+                vec![Arc::new(record("route", &log, false))],
+                ok_handler,
+            )
+            .route("/other", Method::GET, ok_handler);
+
+        let resp = send(&app, "/items/1").await;
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "Some(\"route\")");
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                "before global /items/1 Some(\"1\")",
+                "before route /items/1 Some(\"1\")",
+                "after route Some(\"route\") 200 OK",
+                "after global Some(\"global\") 200 OK",
+            ]
+        );
+
+        // ルートごとのミドルウェアは他のルートには適用されない, 404にも全体のものは適用される
+        send(&app, "/other").await;
+        send(&app, "/unknown").await;
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                "before global /other None",
+                "after global Some(\"global\") 200 OK",
+                "before global /unknown None",
+                "after global Some(\"global\") 404 Not Found",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn before_should_short_circuit() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = App::new(())
+            .middleware(record("first", &log, false))
+            .middleware(record("limit", &log, true))
+            .middleware(record("last", &log, false))
+            .route("/items", Method::GET, ok_handler);
+
+        let resp = send(&app, "/items").await;
+        assert_eq!(resp.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            log.lock().unwrap().clone(),
+            vec![
+                "before first /items None",
+                "before limit /items None",
+                "after limit Some(\"limit\") 429 Too Many Requests",
+                "after first Some(\"limit\") 429 Too Many Requests",
+            ]
+        );
     }
 }
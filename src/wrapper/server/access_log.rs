@@ -0,0 +1,74 @@
+use super::{Middleware, Params, RequestHead, Response};
+use crate::log_context;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+// 1リクエストにつき1行のJSONのアクセスログを出す
+// 全体の所要時間を測るために最初に登録する
+pub struct AccessLog;
+
+impl AccessLog {
+    fn line(head: &RequestHead, status: http::StatusCode) -> serde_json::Value {
+        serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "level": "INFO",
+            "target": log_context::ACCESS_LOG_TARGET,
+            "request_id": log_context::request_id(),
+            "method": head.method.as_str(),
+            "route": head.route,
+            "status": status.as_u16(),
+            "latency_ms": head.started_at.elapsed().as_secs_f64() * 1000.0,
+            "subject": log_context::subject(),
+        })
+    }
+}
+
+#[async_trait]
+impl<D: Sync + Send + 'static> Middleware<D> for AccessLog {
+    async fn after(
+        &self,
+        head: &RequestHead,
+        _params: &Params,
+        _data: Arc<D>,
+        resp: Response,
+    ) -> Response {
+        info!(
+            target: log_context::ACCESS_LOG_TARGET,
+            "{}",
+            AccessLog::line(head, resp.status())
+        );
+
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn line_should_contain_request_context() {
+        let head = RequestHead {
+            method: http::Method::POST,
+            uri: "/gift/abc/open".parse().unwrap(),
+            headers: http::HeaderMap::new(),
+            route: Some("/gift/:gift_id/open".to_string()),
+            started_at: Instant::now(),
+        };
+
+        let line = log_context::scope("req-1".to_string(), async {
+            log_context::set_subject("auth0|me");
+
+            AccessLog::line(&head, http::StatusCode::CONFLICT)
+        })
+        .await;
+
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["method"], "POST");
+        assert_eq!(line["route"], "/gift/:gift_id/open");
+        assert_eq!(line["status"], 409);
+        assert_eq!(line["subject"], "auth0|me");
+        assert!(line["latency_ms"].is_number());
+    }
+}
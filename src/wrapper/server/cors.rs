@@ -0,0 +1,303 @@
+use super::{response_from, AllowedMethods, Middleware, Params, Request, RequestHead, Response};
+use crate::error::{ErrorCode, ServiceError};
+use crate::log_context;
+use async_trait::async_trait;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method};
+use std::sync::Arc;
+
+// Safari対策に全部明記する
+const DEFAULT_ALLOW_HEADERS: &str =
+    "Authorization, Content-Type, Origin, Referer, Accept, User-Agent";
+
+#[derive(Clone)]
+pub struct CorsConfig {
+    // "*"を含むときは全てのoriginを許可する
+    pub allowed_origins: Vec<String>,
+    pub allow_credentials: bool,
+    // preflightの結果をキャッシュしてよい秒数
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    // 全てのoriginにcredentials付きのリクエストを許すことになるので"*"とcredentialsは併用できない
+    pub fn new(
+        allowed_origins: Vec<String>,
+        allow_credentials: bool,
+        max_age: Option<u64>,
+    ) -> Result<Self, String> {
+        let config = CorsConfig {
+            allowed_origins,
+            allow_credentials,
+            max_age,
+        };
+        if config.allow_credentials && config.allows_any_origin() {
+            return Err("credentials cannot be allowed for any origin".to_string());
+        }
+
+        Ok(config)
+    }
+
+    fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    fn allows_header(&self, origin: &HeaderValue) -> bool {
+        self.allows_any_origin()
+            || origin
+                .to_str()
+                .map(|origin| self.allowed_origins.iter().any(|o| o == origin))
+                .unwrap_or(false)
+    }
+
+    // 許可するoriginを列挙しているときはリクエストのoriginをそのまま返す
+    // "*"のときにoriginを返すとcredentials付きで全てのoriginを許すことになるので返さない
+    fn allow_origin_value(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        match origin {
+            _ if self.allows_any_origin() => Some(HeaderValue::from_static("*")),
+            Some(origin) => Some(origin.clone()),
+            None => None,
+        }
+    }
+
+    fn set_headers(&self, headers: &mut hyper::HeaderMap, origin: Option<&HeaderValue>) {
+        if let Some(value) = self.allow_origin_value(origin) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+        if !self.allows_any_origin() {
+            headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        // 問い合わせのときにブラウザからリクエストIDを読めるようにする
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(log_context::REQUEST_ID_HEADER),
+        );
+    }
+}
+
+// 許可されていないoriginからのリクエストは403で拒否する
+// Originヘッダーのないリクエスト(ブラウザ以外から)はそのまま通す
+pub struct Cors {
+    config: CorsConfig,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Cors { config }
+    }
+
+    fn preflight(&self, req: &Request) -> Response {
+        let allowed_methods = req
+            .extensions()
+            .get::<AllowedMethods>()
+            .map(|methods| methods.0.clone())
+            .unwrap_or_default();
+        if allowed_methods.is_empty() {
+            return hyper::Response::builder()
+                .status(404)
+                .body(Body::from(""))
+                .unwrap();
+        }
+
+        let mut resp = hyper::Response::new(Body::default());
+        let headers = resp.headers_mut();
+        self.config
+            .set_headers(headers, req.headers().get(header::ORIGIN));
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            allowed_methods
+                .iter()
+                .map(|method| method.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+                .parse()
+                .unwrap(),
+        );
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            req.headers()
+                .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned()
+                .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_ALLOW_HEADERS)),
+        );
+        if let Some(max_age) = self.config.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.into());
+        }
+
+        resp
+    }
+}
+
+#[async_trait]
+impl<D: Sync + Send + 'static> Middleware<D> for Cors {
+    async fn before(&self, req: &mut Request, _params: &Params, _data: Arc<D>) -> Option<Response> {
+        if let Some(origin) = req.headers().get(header::ORIGIN) {
+            if !self.config.allows_header(origin) {
+                return Some(response_from::<()>(Err(ServiceError::forbidden(
+                    ErrorCode::OriginNotAllowed,
+                    failure::err_msg(format!("Origin {:?} is not allowed", origin)),
+                ))));
+            }
+        }
+
+        if req.method() == Method::OPTIONS {
+            return Some(self.preflight(req));
+        }
+
+        None
+    }
+
+    async fn after(
+        &self,
+        head: &RequestHead,
+        _params: &Params,
+        _data: Arc<D>,
+        mut resp: Response,
+    ) -> Response {
+        // preflightと拒否したリクエストには付けない
+        let origin = head.headers.get(header::ORIGIN);
+        if head.method != Method::OPTIONS && origin.iter().all(|o| self.config.allows_header(o)) {
+            self.config.set_headers(resp.headers_mut(), origin);
+        }
+
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::App;
+
+    async fn ok_handler(_req: Request, _params: Params, _data: Arc<()>) -> Response {
+        hyper::Response::new(Body::from("ok"))
+    }
+
+    fn app(config: CorsConfig) -> App<()> {
+        App::new(())
+            .middleware(Cors::new(config))
+            .route("/items/:id", Method::GET, ok_handler)
+            .route("/items/:id", Method::PUT, ok_handler)
+    }
+
+    fn request(method: Method, origin: Option<&str>) -> Request {
+        let mut builder = hyper::Request::builder().method(method).uri("/items/1");
+        if let Some(origin) = origin {
+            builder = builder.header(header::ORIGIN, origin);
+        }
+
+        builder.body(Body::default()).unwrap()
+    }
+
+    fn web_client() -> CorsConfig {
+        CorsConfig::new(vec!["https://jitome.ramda.io".to_string()], true, Some(600)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn preflight_should_list_registered_methods() {
+        let resp = app(web_client())
+            .handle(request(Method::OPTIONS, Some("https://jitome.ramda.io")))
+            .await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let headers = resp.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://jitome.ramda.io"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET,PUT");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[header::VARY], "Origin");
+    }
+
+    #[tokio::test]
+    async fn preflight_for_unknown_path_should_be_not_found() {
+        let resp = app(web_client())
+            .handle(
+                hyper::Request::builder()
+                    .method(Method::OPTIONS)
+                    .uri("/unknown")
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn allowed_origin_should_be_echoed() {
+        let resp = app(web_client())
+            .handle(request(Method::GET, Some("https://jitome.ramda.io")))
+            .await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://jitome.ramda.io"
+        );
+
+        // ブラウザ以外からのリクエスト
+        let resp = app(web_client()).handle(request(Method::GET, None)).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert!(!resp
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn other_origin_should_be_rejected() {
+        for method in [Method::GET, Method::OPTIONS] {
+            let resp = app(web_client())
+                .handle(request(method, Some("https://evil.example.com")))
+                .await;
+            assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+            assert!(!resp
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        }
+    }
+
+    #[tokio::test]
+    async fn wildcard_should_allow_any_origin() {
+        let resp = app(CorsConfig::default())
+            .handle(request(Method::GET, Some("https://any.example.com")))
+            .await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[test]
+    fn credentials_should_not_be_allowed_for_any_origin() {
+        assert!(CorsConfig::new(vec!["*".to_string()], true, None).is_err());
+        assert!(CorsConfig::new(vec!["*".to_string()], false, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn wildcard_should_not_reflect_origin() {
+        // フィールドを直接組み立てても, "*"のときはoriginを返さない
+        let config = CorsConfig {
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        let resp = app(config)
+            .handle(request(Method::GET, Some("https://evil.example.com")))
+            .await;
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+}
@@ -0,0 +1,337 @@
+// ハンドラーの入力をリクエストから型付きで取り出す
+use super::{
+    response_from, Handler, IntoRoute, Middleware, Params, Request, Response, RouteSchema,
+};
+use crate::error::{ErrorCode, ServiceError};
+use async_trait::async_trait;
+use futures::prelude::*;
+use hyper::body::HttpBody;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+// BodyLimitが指定されていないときのリクエストボディの上限
+pub const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+#[async_trait]
+pub trait FromRequest: Sized {
+    async fn from_request(req: &mut Request, params: &Params) -> Result<Self, ServiceError>;
+}
+
+// 入力の型がOpenAPIのドキュメントに足すもの
+pub trait DescribeInput {
+    fn describe(schema: RouteSchema) -> RouteSchema {
+        schema
+    }
+}
+
+// typedで作ったハンドラーと, その入出力の型から作ったドキュメント
+pub struct Typed<D> {
+    handler: Handler<D>,
+    schema: RouteSchema,
+}
+
+impl<D> IntoRoute<D> for Typed<D> {
+    fn into_route(self) -> (Handler<D>, RouteSchema) {
+        (self.handler, self.schema)
+    }
+}
+
+// 取り出した入力とArc<D>を受け取ってResultを返す関数をApp::routeに渡せるハンドラーにする
+pub fn typed<D, E, F, T, R>(f: F) -> Typed<D>
+where
+    D: Sync + Send + 'static,
+    E: FromRequest + DescribeInput + Send + 'static,
+    F: Fn(E, Arc<D>) -> T + Clone + Sync + Send + 'static,
+    T: Future<Output = Result<R, ServiceError>> + Send + 'static,
+    R: serde::Serialize + JsonSchema,
+{
+    Typed {
+        handler: Arc::new(move |mut req, params, data| {
+            let f = f.clone();
+
+            Box::pin(async move {
+                match E::from_request(&mut req, &params).await {
+                    Ok(input) => response_from(f(input, data).await),
+                    Err(err) => response_from::<()>(Err(err)),
+                }
+            })
+        }),
+        schema: E::describe(RouteSchema::default()).response::<R>(),
+    }
+}
+
+// ミドルウェアがリクエストに持たせた値
+pub struct Extension<T>(pub T);
+
+#[async_trait]
+impl<T: Send + Sync + 'static> FromRequest for Extension<T> {
+    async fn from_request(req: &mut Request, _params: &Params) -> Result<Self, ServiceError> {
+        req.extensions_mut()
+            .remove::<T>()
+            .map(Extension)
+            .ok_or_else(|| {
+                ServiceError::internal_server_error(
+                    ErrorCode::InternalError,
+                    failure::err_msg(format!(
+                        "Extension {} is not set",
+                        std::any::type_name::<T>()
+                    )),
+                )
+            })
+    }
+}
+
+// 中身の型が説明する, 認証の結果なら認証が必要なルートになる
+impl<T: DescribeInput> DescribeInput for Extension<T> {
+    fn describe(schema: RouteSchema) -> RouteSchema {
+        T::describe(schema)
+    }
+}
+
+// "/gift/:gift_id/open"の:gift_idのようなパスパラメーター, フィールド名で対応させる
+pub struct Path<T>(pub T);
+
+// パスパラメーターはルートのパスから作る
+impl<T> DescribeInput for Path<T> {}
+
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Path<T> {
+    async fn from_request(_req: &mut Request, params: &Params) -> Result<Self, ServiceError> {
+        let encoded = serde_urlencoded::to_string(&params.0).map_err(|err| {
+            ServiceError::bad_request(
+                ErrorCode::InvalidPathParameter,
+                failure::Error::from_boxed_compat(Box::new(err)),
+            )
+        })?;
+
+        serde_urlencoded::from_str(&encoded)
+            .map(Path)
+            .map_err(|err| {
+                let code = if err.to_string().starts_with("missing field") {
+                    ErrorCode::MissingPathParameter
+                } else {
+                    ErrorCode::InvalidPathParameter
+                };
+
+                ServiceError::bad_request(code, failure::Error::from_boxed_compat(Box::new(err)))
+            })
+    }
+}
+
+pub struct Query<T>(pub T);
+
+impl<T: JsonSchema> DescribeInput for Query<T> {
+    fn describe(schema: RouteSchema) -> RouteSchema {
+        schema.query::<T>()
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Query<T> {
+    async fn from_request(req: &mut Request, _params: &Params) -> Result<Self, ServiceError> {
+        serde_urlencoded::from_str(req.uri().query().unwrap_or(""))
+            .map(Query)
+            .map_err(|err| {
+                ServiceError::bad_request(
+                    ErrorCode::InvalidQueryParameter,
+                    failure::Error::from_boxed_compat(Box::new(err)),
+                )
+            })
+    }
+}
+
+// リクエストボディの大きさの上限を変える, ルートごとに指定する
+#[derive(Clone, Copy)]
+pub struct BodyLimit(pub usize);
+
+#[async_trait]
+impl<D: Sync + Send + 'static> Middleware<D> for BodyLimit {
+    async fn before(&self, req: &mut Request, _params: &Params, _data: Arc<D>) -> Option<Response> {
+        req.extensions_mut().insert(*self);
+
+        None
+    }
+}
+
+pub struct Json<T>(pub T);
+
+impl<T: JsonSchema> DescribeInput for Json<T> {
+    fn describe(schema: RouteSchema) -> RouteSchema {
+        schema.request::<T>()
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Json<T> {
+    async fn from_request(req: &mut Request, _params: &Params) -> Result<Self, ServiceError> {
+        let limit = req
+            .extensions()
+            .get::<BodyLimit>()
+            .map(|limit| limit.0)
+            .unwrap_or(DEFAULT_BODY_LIMIT);
+        let too_large = || {
+            ServiceError::payload_too_large(
+                ErrorCode::RequestBodyTooLarge,
+                failure::err_msg(format!("Request body must be at most {} bytes", limit)),
+            )
+            .with_details(serde_json::json!({ "limit": limit }))
+        };
+
+        // Content-Lengthを信用せずに読みながら数える
+        let mut body = std::mem::replace(req.body_mut(), hyper::Body::empty());
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| {
+                ServiceError::bad_request(
+                    ErrorCode::InvalidRequestBody,
+                    failure::Error::from_boxed_compat(Box::new(err)),
+                )
+            })?;
+            if bytes.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(Json(serde_json::from_slice(&bytes)?))
+    }
+}
+
+#[async_trait]
+impl FromRequest for () {
+    async fn from_request(_req: &mut Request, _params: &Params) -> Result<Self, ServiceError> {
+        Ok(())
+    }
+}
+
+impl DescribeInput for () {}
+
+// 複数の入力はタプルで受け取る, 左から順に取り出す
+macro_rules! impl_from_request_for_tuple {
+    ($($t:ident),+) => {
+        #[async_trait]
+        impl<$($t: FromRequest + Send),+> FromRequest for ($($t,)+) {
+            async fn from_request(req: &mut Request, params: &Params) -> Result<Self, ServiceError> {
+                Ok(($($t::from_request(req, params).await?,)+))
+            }
+        }
+
+        impl<$($t: DescribeInput),+> DescribeInput for ($($t,)+) {
+            fn describe(schema: RouteSchema) -> RouteSchema {
+                $(let schema = $t::describe(schema);)+
+
+                schema
+            }
+        }
+    };
+}
+
+impl_from_request_for_tuple!(A);
+impl_from_request_for_tuple!(A, B);
+impl_from_request_for_tuple!(A, B, C);
+impl_from_request_for_tuple!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::App;
+    use serde::*;
+
+    #[derive(Deserialize)]
+    struct ItemPath {
+        id: i32,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    struct LimitQuery {
+        limit: Option<i32>,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    struct Input {
+        name: String,
+    }
+
+    async fn handler(
+        (Path(path), Query(query), Json(input)): (Path<ItemPath>, Query<LimitQuery>, Json<Input>),
+        _data: Arc<()>,
+    ) -> Result<serde_json::Value, ServiceError> {
+        Ok(serde_json::json!({
+            "id": path.id,
+            "limit": query.limit,
+            "name": input.name,
+        }))
+    }
+
+    fn app() -> App<()> {
+        App::new(())
+            .route("/items/:id", http::Method::POST, typed(handler))
+            .route_with(
+                "/small/:id",
+                http::Method::POST,
+                vec![Arc::new(BodyLimit(16))],
+                typed(handler),
+            )
+    }
+
+    async fn send(path: &str, body: &str) -> (http::StatusCode, serde_json::Value) {
+        let resp = app()
+            .handle(
+                hyper::Request::builder()
+                    .method(http::Method::POST)
+                    .uri(path)
+                    .body(hyper::Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn typed_handler_should_receive_inputs() {
+        let (status, body) = send("/items/3?limit=10", r#"{"name":"myon"}"#).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({ "id": 3, "limit": 10, "name": "myon" })
+        );
+
+        let (status, body) = send("/items/3", r#"{"name":"myon"}"#).await;
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body["limit"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn invalid_inputs_should_be_bad_request() {
+        let cases = vec![
+            ("/items/abc", r#"{"name":"myon"}"#, "invalid_path_parameter"),
+            (
+                "/items/3?limit=many",
+                r#"{"name":"myon"}"#,
+                "invalid_query_parameter",
+            ),
+            ("/items/3", r#"{"nam":"myon"}"#, "invalid_request_body"),
+        ];
+
+        for (path, body, code) in cases {
+            let (status, body) = send(path, body).await;
+            assert_eq!(status, http::StatusCode::BAD_REQUEST, "{}", path);
+            assert_eq!(body["error"]["code"], code, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn body_over_limit_should_be_rejected() {
+        let (status, _) = send("/small/3", r#"{"name":"m"}"#).await;
+        assert_eq!(status, http::StatusCode::OK);
+
+        let (status, body) = send("/small/3", r#"{"name":"myonmyonmyon"}"#).await;
+        assert_eq!(status, http::StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"]["code"], "request_body_too_large");
+        assert_eq!(body["error"]["details"]["limit"], 16);
+    }
+}
@@ -0,0 +1,75 @@
+use super::{Middleware, Params, Request, RequestHead, Response};
+use crate::metrics;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+// ルートごとのリクエスト数とレイテンシを記録する
+// 一番外側で計測するために最初に登録する
+pub struct RequestMetrics;
+
+#[async_trait]
+impl<D: Sync + Send + 'static> Middleware<D> for RequestMetrics {
+    async fn after(
+        &self,
+        head: &RequestHead,
+        _params: &Params,
+        _data: Arc<D>,
+        resp: Response,
+    ) -> Response {
+        metrics::observe_request(
+            head.method.as_str(),
+            head.route.as_deref().unwrap_or(metrics::UNMATCHED_ROUTE),
+            resp.status().as_u16(),
+            head.started_at.elapsed(),
+        );
+
+        resp
+    }
+}
+
+// Prometheusのテキスト形式で全てのメトリクスを返す
+pub async fn metrics_handler<D>(_req: Request, _params: Params, _data: Arc<D>) -> Response {
+    hyper::Response::builder()
+        .header(hyper::header::CONTENT_TYPE, metrics::content_type())
+        .body(hyper::Body::from(metrics::render()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::App;
+    use hyper::{Body, Method};
+
+    async fn ok_handler(_req: Request, _params: Params, _data: Arc<()>) -> Response {
+        hyper::Response::new(Body::from("ok"))
+    }
+
+    #[tokio::test]
+    async fn requests_should_be_counted_by_route_template() {
+        let app = App::new(()).middleware(RequestMetrics).route(
+            "/metrics_test/:id",
+            Method::GET,
+            ok_handler,
+        );
+        for path in &[
+            "/metrics_test/1",
+            "/metrics_test/2",
+            "/metrics_test_unknown",
+        ] {
+            app.handle(
+                hyper::Request::builder()
+                    .uri(*path)
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await;
+        }
+
+        let text = String::from_utf8(metrics::render()).unwrap();
+        assert!(text.contains(
+            r#"http_requests_total{method="GET",route="/metrics_test/:id",status="200"} 2"#
+        ));
+        assert!(text.contains(r#"route="unmatched",status="404""#));
+    }
+}
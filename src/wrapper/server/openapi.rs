@@ -0,0 +1,375 @@
+// 登録されたルートと入出力の型からOpenAPI 3のドキュメントを作る
+use super::{response_from, App, Method};
+use crate::error::ErrorResponse;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject, SingleOrVec};
+use schemars::JsonSchema;
+use std::sync::Arc;
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+type RootSchemaFn = fn(&mut SchemaGenerator) -> RootSchema;
+
+fn schema_fn<T: JsonSchema>() -> SchemaFn {
+    |gen| gen.subschema_for::<T>()
+}
+
+// 独自にSerializeを実装した文字列のenumのスキーマ
+pub fn string_enum_schema(values: Vec<String>) -> Schema {
+    Schema::Object(SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(values.into_iter().map(serde_json::Value::from).collect()),
+        ..Default::default()
+    })
+}
+
+// App::docでルートに付ける説明
+#[derive(Clone, Default)]
+pub struct Operation {
+    summary: String,
+    tag: Option<String>,
+}
+
+impl Operation {
+    pub fn new(summary: &str) -> Self {
+        Operation {
+            summary: summary.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+
+        self
+    }
+}
+
+// ルートの入出力, typedに渡したハンドラーの型から作るので手では書かない
+#[derive(Clone, Default)]
+pub struct RouteSchema {
+    authenticated: bool,
+    query: Option<RootSchemaFn>,
+    request: Option<SchemaFn>,
+    response: Option<SchemaFn>,
+}
+
+impl RouteSchema {
+    // Authorizationヘッダーが必要
+    pub fn authenticated(mut self) -> Self {
+        self.authenticated = true;
+
+        self
+    }
+
+    // Tのフィールドをクエリパラメーターにする
+    pub fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(|gen| gen.root_schema_for::<T>());
+
+        self
+    }
+
+    pub fn request<T: JsonSchema>(mut self) -> Self {
+        self.request = Some(schema_fn::<T>());
+
+        self
+    }
+
+    pub fn response<T: JsonSchema>(mut self) -> Self {
+        self.response = Some(schema_fn::<T>());
+
+        self
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct RouteDoc {
+    method: Method,
+    path: String,
+    operation: Operation,
+    schema: RouteSchema,
+}
+
+impl RouteDoc {
+    pub(super) fn new(method: Method, path: &str, schema: RouteSchema) -> Self {
+        RouteDoc {
+            method,
+            path: path.to_string(),
+            operation: Operation::default(),
+            schema,
+        }
+    }
+}
+
+// "/users/:screen_name" -> "/users/{screen_name}"
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn path_parameters(path: &str) -> Vec<serde_json::Value> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix(':'))
+        .map(|name| {
+            serde_json::json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect()
+}
+
+fn query_parameters(root: RootSchema) -> Vec<serde_json::Value> {
+    let object = match root.schema.object {
+        Some(object) => object,
+        None => return Vec::new(),
+    };
+
+    let required = object.required;
+    object
+        .properties
+        .into_iter()
+        .map(|(name, schema)| {
+            serde_json::json!({
+                "required": required.contains(&name),
+                "name": name,
+                "in": "query",
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+// ()を返すハンドラーのボディはnullだけなので載せない
+fn is_null(schema: &Schema) -> bool {
+    match schema {
+        Schema::Object(SchemaObject {
+            instance_type: Some(SingleOrVec::Single(instance_type)),
+            ..
+        }) => **instance_type == InstanceType::Null,
+        _ => false,
+    }
+}
+
+fn json_content(schema: Schema) -> serde_json::Value {
+    serde_json::json!({
+        "application/json": { "schema": schema }
+    })
+}
+
+fn document(title: &str, version: &str, routes: &[RouteDoc]) -> serde_json::Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let error = gen.subschema_for::<ErrorResponse>();
+
+    let mut paths = serde_json::Map::new();
+    for route in routes {
+        let doc = route.operation.clone();
+        let schema = route.schema.clone();
+
+        let mut parameters = path_parameters(&route.path);
+        if let Some(query) = schema.query {
+            parameters.extend(query_parameters(query(&mut gen)));
+        }
+
+        let mut ok = serde_json::json!({ "description": "OK" });
+        if let Some(response) = schema.response.map(|response| response(&mut gen)) {
+            if !is_null(&response) {
+                ok["content"] = json_content(response);
+            }
+        }
+
+        let mut operation = serde_json::json!({
+            "parameters": parameters,
+            "responses": {
+                "200": ok,
+                "default": {
+                    "description": "Error",
+                    "content": json_content(error.clone()),
+                },
+            },
+        });
+        if !doc.summary.is_empty() {
+            operation["summary"] = doc.summary.into();
+        }
+        if let Some(tag) = doc.tag {
+            operation["tags"] = serde_json::json!([tag]);
+        }
+        if let Some(request) = schema.request {
+            operation["requestBody"] = serde_json::json!({
+                "required": true,
+                "content": json_content(request(&mut gen)),
+            });
+        }
+        if schema.authenticated {
+            operation["security"] = serde_json::json!([{ "bearerAuth": [] }]);
+        }
+
+        paths
+            .entry(openapi_path(&route.path))
+            .or_insert_with(|| serde_json::json!({}))[route.method.as_str().to_lowercase()] =
+            operation;
+    }
+
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": title,
+            "version": version,
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.into_definitions(),
+            "securitySchemes": {
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                },
+            },
+        },
+    })
+}
+
+impl<D: Sync + Send + 'static> App<D> {
+    // 直前に登録したルートに説明を付ける
+    pub fn doc(mut self, operation: Operation) -> Self {
+        if let Some(route) = self.docs.last_mut() {
+            route.operation = operation;
+        }
+
+        self
+    }
+
+    pub fn openapi(&self, title: &str, version: &str) -> serde_json::Value {
+        document(title, version, &self.docs)
+    }
+
+    // それまでに登録したルートのドキュメントを返すルートを足す
+    pub fn openapi_route(self, path: &str, title: &str, version: &str) -> Self {
+        let spec = Arc::new(self.openapi(title, version));
+
+        self.route(path, Method::GET, move |_req, _params, _data| {
+            let spec = spec.clone();
+
+            async move { response_from(Ok(spec.as_ref())) }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{typed, DescribeInput, Extension, Json, Query, Request};
+    use serde::*;
+
+    #[derive(Deserialize, JsonSchema)]
+    struct LimitQuery {
+        limit: Option<i32>,
+    }
+
+    #[derive(Deserialize, Serialize, JsonSchema)]
+    struct Item {
+        name: String,
+    }
+
+    struct Credential;
+
+    impl DescribeInput for Credential {
+        fn describe(schema: RouteSchema) -> RouteSchema {
+            schema.authenticated()
+        }
+    }
+
+    async fn list_items(
+        (Extension(_), Query(query)): (Extension<Credential>, Query<LimitQuery>),
+        _data: Arc<()>,
+    ) -> Result<Vec<Item>, crate::error::ServiceError> {
+        Ok((0..query.limit.unwrap_or(1))
+            .map(|i| Item {
+                name: i.to_string(),
+            })
+            .collect())
+    }
+
+    async fn create_item(
+        Json(item): Json<Item>,
+        _data: Arc<()>,
+    ) -> Result<(), crate::error::ServiceError> {
+        assert!(!item.name.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn document_should_contain_registered_routes() {
+        let app = App::new(())
+            .route("/items", Method::GET, typed(list_items))
+            .doc(Operation::new("List items"))
+            .route("/items", Method::POST, typed(create_item))
+            .route("/items/:item_id", Method::DELETE, typed(list_items))
+            .route("/hello", Method::GET, |_req, _params, _data| async {
+                hyper::Response::new(hyper::Body::from("hello"))
+            });
+        let spec = app.openapi("test", "0.1.0");
+
+        let list = &spec["paths"]["/items"]["get"];
+        assert_eq!(list["summary"], "List items");
+        assert_eq!(list["security"][0]["bearerAuth"], serde_json::json!([]));
+        assert_eq!(list["parameters"][0]["name"], "limit");
+        assert_eq!(list["parameters"][0]["in"], "query");
+        assert_eq!(list["parameters"][0]["required"], false);
+        assert_eq!(
+            list["responses"]["200"]["content"]["application/json"]["schema"]["items"]["$ref"],
+            "#/components/schemas/Item"
+        );
+        assert!(spec["components"]["schemas"]["Item"].is_object());
+        assert!(spec["components"]["schemas"]["ErrorResponse"].is_object());
+
+        // 入出力と認証はハンドラーの型から決まる
+        let create = &spec["paths"]["/items"]["post"];
+        assert_eq!(
+            create["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Item"
+        );
+        assert!(create.get("security").is_none());
+        assert!(create["responses"]["200"].get("content").is_none());
+
+        // 説明を付けていないルートも載る
+        let delete = &spec["paths"]["/items/{item_id}"]["delete"];
+        assert_eq!(delete["parameters"][0]["name"], "item_id");
+        assert_eq!(delete["parameters"][0]["in"], "path");
+        assert!(delete.get("summary").is_none());
+
+        // typedを通さないハンドラーは入出力が分からないので何も載せない
+        let hello = &spec["paths"]["/hello"]["get"];
+        assert!(hello["responses"]["200"].get("content").is_none());
+        assert!(hello.get("security").is_none());
+    }
+
+    #[tokio::test]
+    async fn openapi_route_should_serve_document() {
+        let app = App::new(())
+            .route("/items", Method::GET, typed(list_items))
+            .openapi_route("/openapi.json", "test", "0.1.0");
+
+        let req: Request = hyper::Request::builder()
+            .uri("/openapi.json")
+            .body(hyper::Body::default())
+            .unwrap();
+        let resp = app.handle(req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(spec["openapi"], "3.0.3");
+        assert!(spec["paths"]["/items"]["get"].is_object());
+        // 自身は載らない
+        assert!(spec["paths"].get("/openapi.json").is_none());
+    }
+}
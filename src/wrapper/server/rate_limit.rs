@@ -0,0 +1,436 @@
+// トークンバケットによるリクエスト数の制限
+use super::{response_from, MatchedRoute, Middleware, Params, RemoteAddr, Request, Response};
+use crate::error::{ErrorCode, ServiceError};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// periodごとにcapacity回まで, 使わなかった分はcapacityまで貯められる
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitPolicy {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimitPolicy {
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        RateLimitPolicy { capacity, period }
+    }
+
+    pub fn per_minute(capacity: u32) -> Self {
+        RateLimitPolicy::new(capacity, Duration::from_secs(60))
+    }
+
+    fn tokens_per_ms(&self) -> f64 {
+        self.capacity as f64 / self.period.as_millis() as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at_ms: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl TokenBucket {
+    pub fn full(policy: &RateLimitPolicy, now_ms: i64) -> Self {
+        TokenBucket {
+            tokens: policy.capacity as f64,
+            updated_at_ms: now_ms,
+        }
+    }
+
+    // 使われなければcapacityまで戻る時刻, それ以降はバケットを捨てて作り直しても変わらない
+    pub fn full_at_ms(&self, policy: &RateLimitPolicy) -> i64 {
+        let missing = (policy.capacity as f64 - self.tokens).max(0.0);
+
+        self.updated_at_ms + (missing / policy.tokens_per_ms()).ceil() as i64
+    }
+
+    // 経過時間分を補充してから1つ取り出す, ストアの実装から使う
+    pub fn take(&mut self, policy: &RateLimitPolicy, now_ms: i64) -> RateLimitDecision {
+        let elapsed = (now_ms - self.updated_at_ms).max(0) as f64;
+        self.tokens = (self.tokens + elapsed * policy.tokens_per_ms()).min(policy.capacity as f64);
+        self.updated_at_ms = now_ms;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            RateLimitDecision::Allowed
+        } else {
+            let wait_ms = ((1.0 - self.tokens) / policy.tokens_per_ms()).ceil() as u64;
+
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_millis(wait_ms),
+            }
+        }
+    }
+}
+
+// 複数のインスタンスで制限を共有するときはストアを共有のものにする
+#[async_trait]
+pub trait IRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now_ms: i64,
+    ) -> Result<RateLimitDecision, ServiceError>;
+}
+
+// 満タンに戻ったバケットを捨てる間隔, リクエストごとに全てのバケットを調べないようにする
+#[derive(Default)]
+pub struct SweepSchedule {
+    next_ms: AtomicI64,
+}
+
+impl SweepSchedule {
+    const INTERVAL_MS: i64 = 60 * 1000;
+
+    // 前回から間隔が空いていればtrue, 同時に呼ばれても1つだけがtrueになる
+    pub fn is_due(&self, now_ms: i64) -> bool {
+        let next_ms = self.next_ms.load(Ordering::SeqCst);
+
+        now_ms >= next_ms
+            && self
+                .next_ms
+                .compare_exchange(
+                    next_ms,
+                    now_ms + SweepSchedule::INTERVAL_MS,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+    }
+}
+
+// バケットはそれぞれ最後に使われたときのポリシーで満タンかどうかを判断する
+#[derive(Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, (TokenBucket, RateLimitPolicy)>>,
+    sweep: SweepSchedule,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl IRateLimitStore for InMemoryRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now_ms: i64,
+    ) -> Result<RateLimitDecision, ServiceError> {
+        let mut buckets = self.buckets.lock().unwrap();
+        // 満タンになったバケットは持っていても仕方がないので捨てる
+        if self.sweep.is_due(now_ms) {
+            buckets.retain(|_, (bucket, policy)| bucket.full_at_ms(policy) > now_ms);
+        }
+
+        let (bucket, bucket_policy) = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| (TokenBucket::full(policy, now_ms), *policy));
+        *bucket_policy = *policy;
+
+        Ok(bucket.take(policy, now_ms))
+    }
+}
+
+// リクエストを誰からのものとして数えるか, Noneのときは接続元のIPで数える
+pub type RateLimitKey = Arc<dyn Fn(&Request) -> Option<String> + Sync + Send>;
+
+// ルートごとにポリシーを変えられる, 指定のないルートはdefault_policyで全ルート共通に数える
+pub struct RateLimit {
+    store: Arc<dyn IRateLimitStore + Sync + Send>,
+    default_policy: Option<RateLimitPolicy>,
+    routes: HashMap<String, Option<RateLimitPolicy>>,
+    key: RateLimitKey,
+    trust_forwarded_for: bool,
+    // 同じストアを使う他の制限とバケットが混ざらないようにキーの先頭に付ける
+    prefix: String,
+    // X-Forwarded-Forを無視していることを一度だけ警告する
+    warned_forwarded_for: AtomicBool,
+}
+
+impl RateLimit {
+    pub fn new(
+        store: Arc<dyn IRateLimitStore + Sync + Send>,
+        default_policy: Option<RateLimitPolicy>,
+    ) -> Self {
+        RateLimit {
+            store,
+            default_policy,
+            routes: HashMap::new(),
+            key: Arc::new(|_| None),
+            trust_forwarded_for: false,
+            prefix: String::new(),
+            warned_forwarded_for: AtomicBool::new(false),
+        }
+    }
+
+    // Noneを渡すとそのルートは制限しない
+    pub fn route(mut self, route: &str, policy: Option<RateLimitPolicy>) -> Self {
+        self.routes.insert(route.to_string(), policy);
+
+        self
+    }
+
+    pub fn key_by(
+        mut self,
+        key: impl Fn(&Request) -> Option<String> + Sync + Send + 'static,
+    ) -> Self {
+        self.key = Arc::new(key);
+
+        self
+    }
+
+    // ロードバランサーの後ろにいるときはX-Forwarded-Forの先頭を接続元とみなす
+    pub fn trust_forwarded_for(mut self, trust: bool) -> Self {
+        self.trust_forwarded_for = trust;
+
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = format!("{}|", prefix);
+
+        self
+    }
+
+    fn client_ip(&self, req: &Request) -> Option<String> {
+        let forwarded = req
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
+
+        match forwarded {
+            Some(ip) if self.trust_forwarded_for => Some(ip),
+            // ロードバランサーの後ろで信用しないままだと, 全てのクライアントがロードバランサーのIPで数えられる
+            Some(_) if !self.warned_forwarded_for.swap(true, Ordering::SeqCst) => {
+                warn!("X-Forwarded-For is ignored by the rate limit, set RATE_LIMIT_TRUST_FORWARDED_FOR=true behind a load balancer");
+
+                req.extensions()
+                    .get::<RemoteAddr>()
+                    .map(|addr| addr.0.ip().to_string())
+            }
+            _ => req
+                .extensions()
+                .get::<RemoteAddr>()
+                .map(|addr| addr.0.ip().to_string()),
+        }
+    }
+
+    fn policy_for(&self, req: &Request) -> Option<(String, RateLimitPolicy)> {
+        match req.extensions().get::<MatchedRoute>() {
+            Some(MatchedRoute(route)) if self.routes.contains_key(route) => {
+                self.routes[route].map(|policy| (format!("{} {}", req.method(), route), policy))
+            }
+            _ => self.default_policy.map(|policy| ("*".to_string(), policy)),
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[async_trait]
+impl<D: Sync + Send + 'static> Middleware<D> for RateLimit {
+    async fn before(&self, req: &mut Request, _params: &Params, _data: Arc<D>) -> Option<Response> {
+        let (scope, policy) = self.policy_for(req)?;
+        let client = match (self.key)(req) {
+            Some(key) => key,
+            None => format!("ip:{}", self.client_ip(req)?),
+        };
+
+        match self
+            .store
+            .take(
+                &format!("{}{}|{}", self.prefix, scope, client),
+                &policy,
+                now_ms(),
+            )
+            .await
+        {
+            Ok(RateLimitDecision::Allowed) => None,
+            Ok(RateLimitDecision::Limited { retry_after }) => {
+                let seconds = (retry_after.as_millis() as f64 / 1000.0).ceil() as u64;
+                let mut resp = response_from::<()>(Err(ServiceError::too_many_requests(
+                    ErrorCode::RateLimitExceeded,
+                    failure::err_msg("Too many requests"),
+                )
+                .with_details(serde_json::json!({ "retry_after": seconds }))));
+                resp.headers_mut()
+                    .insert(hyper::header::RETRY_AFTER, seconds.into());
+
+                Some(resp)
+            }
+            // ストアが使えないときは制限しない
+            Err(err) => {
+                warn!("Failed to check rate limit: {:?}", err);
+
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::App;
+    use hyper::{Body, Method};
+
+    #[test]
+    fn bucket_should_refill_over_time() {
+        let policy = RateLimitPolicy::new(2, Duration::from_secs(10));
+        let mut bucket = TokenBucket::full(&policy, 0);
+
+        assert_eq!(bucket.take(&policy, 0), RateLimitDecision::Allowed);
+        assert_eq!(bucket.take(&policy, 0), RateLimitDecision::Allowed);
+        assert_eq!(
+            bucket.take(&policy, 1000),
+            RateLimitDecision::Limited {
+                retry_after: Duration::from_millis(4000)
+            }
+        );
+        assert_eq!(bucket.take(&policy, 5000), RateLimitDecision::Allowed);
+
+        // 貯められるのはcapacityまで
+        let mut bucket = TokenBucket::full(&policy, 0);
+        bucket.take(&policy, 1_000_000);
+        assert!((bucket.tokens - 1.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn full_buckets_should_be_swept_by_their_own_policy() -> Result<(), ServiceError> {
+        let store = InMemoryRateLimitStore::new();
+        let short = RateLimitPolicy::per_minute(1);
+        let long = RateLimitPolicy::new(1, Duration::from_secs(60 * 60));
+
+        store.take("short", &short, 0).await?;
+        store.take("long", &long, 0).await?;
+        // shortは満タンに戻っているが, 前回の掃除から間隔が空いていないので残る
+        store.take("other", &short, 59_999).await?;
+        assert_eq!(store.buckets.lock().unwrap().len(), 3);
+
+        // 他のキーのポリシーでは判断しない
+        store.take("other", &short, 60_000).await?;
+        let buckets = store.buckets.lock().unwrap();
+        let mut keys = buckets.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["long".to_string(), "other".to_string()]);
+
+        Ok(())
+    }
+
+    async fn ok_handler(_req: Request, _params: Params, _data: Arc<()>) -> Response {
+        hyper::Response::new(Body::from("ok"))
+    }
+
+    fn app() -> App<()> {
+        App::new(())
+            .middleware(
+                RateLimit::new(
+                    Arc::new(InMemoryRateLimitStore::new()),
+                    Some(RateLimitPolicy::per_minute(2)),
+                )
+                .route("/strict", Some(RateLimitPolicy::per_minute(1)))
+                .route("/free", None)
+                .key_by(|req| {
+                    req.headers()
+                        .get("x-user")
+                        .map(|user| format!("user:{}", user.to_str().unwrap()))
+                }),
+            )
+            .route("/a", Method::GET, ok_handler)
+            .route("/b", Method::GET, ok_handler)
+            .route("/strict", Method::GET, ok_handler)
+            .route("/free", Method::GET, ok_handler)
+    }
+
+    fn request(path: &str, user: Option<&str>, ip: [u8; 4]) -> Request {
+        let mut builder = hyper::Request::builder().uri(path);
+        if let Some(user) = user {
+            builder = builder.header("x-user", user);
+        }
+        let mut req = builder.body(Body::default()).unwrap();
+        req.extensions_mut().insert(RemoteAddr((ip, 10000).into()));
+
+        req
+    }
+
+    #[tokio::test]
+    async fn requests_over_limit_should_be_rejected() {
+        let app = app();
+
+        // 指定のないルートは共通のバケットで数える
+        let resp = app.handle(request("/a", None, [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let resp = app.handle(request("/b", None, [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let resp = app.handle(request("/a", None, [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()[hyper::header::RETRY_AFTER], "30");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "rate_limit_exceeded");
+        assert_eq!(body["error"]["details"]["retry_after"], 30);
+
+        // 他のIPやユーザーは別に数える
+        let resp = app.handle(request("/a", None, [10, 0, 0, 2])).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let resp = app.handle(request("/a", Some("me"), [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn route_policy_should_override_default() {
+        let app = app();
+
+        let resp = app.handle(request("/strict", None, [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let resp = app.handle(request("/strict", None, [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()[hyper::header::RETRY_AFTER], "60");
+
+        for _ in 0..5 {
+            let resp = app.handle(request("/free", None, [10, 0, 0, 1])).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn prefixed_limits_should_not_share_buckets() {
+        let store = Arc::new(InMemoryRateLimitStore::new());
+        let app = App::new(())
+            .middleware(
+                RateLimit::new(store.clone(), Some(RateLimitPolicy::per_minute(2))).prefix("pre"),
+            )
+            .middleware(RateLimit::new(store, Some(RateLimitPolicy::per_minute(2))))
+            .route("/a", Method::GET, ok_handler);
+
+        // 同じキーで数えていれば2回目で止まる
+        for _ in 0..2 {
+            let resp = app.handle(request("/a", None, [10, 0, 0, 1])).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+        }
+        let resp = app.handle(request("/a", None, [10, 0, 0, 1])).await;
+        assert_eq!(resp.status(), http::StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -0,0 +1,83 @@
+// SIGTERMなどで処理を止めるための合図
+use futures::prelude::*;
+use tokio::sync::watch;
+
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+pub struct ShutdownTrigger {
+    sender: watch::Sender<bool>,
+}
+
+pub fn channel() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+
+    (ShutdownTrigger { sender }, Shutdown { receiver })
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        let _ = self.sender.broadcast(true);
+    }
+}
+
+impl Shutdown {
+    // SIGTERMかSIGINT(Ctrl-C)を受け取ったら止める
+    pub fn on_signal() -> Shutdown {
+        let (trigger, shutdown) = channel();
+        tokio::spawn(async move {
+            let mut terminate =
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                    .expect("Failed to install SIGTERM handler");
+            future::select(terminate.recv().boxed(), tokio::signal::ctrl_c().boxed()).await;
+
+            info!("Shutting down...");
+            trigger.trigger();
+        });
+
+        shutdown
+    }
+
+    // 合図を出す側がいないので止まらない, HTTPリクエストの中で実行するときなどに使う
+    pub fn never() -> Shutdown {
+        channel().1
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // 止める合図があるまで待つ, 合図を出す側がいなくなったときは待ち続ける
+    pub async fn wait(&mut self) {
+        while !self.is_triggered() {
+            if self.receiver.recv().await.is_none() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn wait_should_resolve_after_trigger() {
+        let (trigger, shutdown) = channel();
+        assert!(!shutdown.is_triggered());
+
+        let mut waiting = shutdown.clone();
+        let waiter = tokio::spawn(async move { waiting.wait().await });
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        trigger.trigger();
+
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("wait should resolve")
+            .unwrap();
+        assert!(shutdown.is_triggered());
+    }
+}
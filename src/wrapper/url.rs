@@ -1,4 +1,5 @@
+use schemars::JsonSchema;
 use serde::*;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct Url(pub String);